@@ -0,0 +1,77 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use gamerboy::*;
+
+const START: u16 = 0x0150;
+const STEPS: u64 = 1000;
+
+/// A ROM filled with register loads and additions from START on
+fn alu_rom() -> Vec<u8> {
+    // LD A,12h; LD B,34h; ADD A,B; ADC A,01h; ADD HL,BC; LD C,B; LD D,C;
+    // ADC A,D
+    const BODY: [u8; 11] = [
+        0x3E, 0x12, 0x06, 0x34, 0x80, 0xCE, 0x01, 0x09, 0x48, 0x51, 0x8A,
+    ];
+
+    let mut rom = vec![0x00; 0x8000];
+    for (byte, op) in rom[START as usize..0x4000]
+        .iter_mut()
+        .zip(BODY.iter().cycle())
+    {
+        *byte = *op;
+    }
+    rom
+}
+
+fn interpreters(c: &mut Criterion) {
+    let mut group = c.benchmark_group("interpreter");
+    group.throughput(Throughput::Elements(STEPS));
+
+    for (name, cached) in [("plain", false), ("block cache", true)] {
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(alu_rom()));
+        cpu.set_block_cache(cached);
+
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                cpu.set_register(gameboy::Reg::PC, START).expect("PC");
+                for _ in 0..STEPS {
+                    cpu.step().expect("step");
+                }
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn dispatch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(STEPS));
+
+    let cartridge = || gameboy::Cartridge::create(alu_rom());
+    let mut dynamic = gameboy::create_system(Model::DMG, cartridge());
+    let mut fixed =
+        gameboy::create_static_system::<gameboy::RAM<{ 8 * 1024 }>>(Model::DMG, cartridge())
+            .expect("static system");
+
+    group.bench_function("dynamic", |b| {
+        b.iter(|| {
+            dynamic.set_register(gameboy::Reg::PC, START).expect("PC");
+            for _ in 0..STEPS {
+                dynamic.step().expect("step");
+            }
+        })
+    });
+    group.bench_function("static", |b| {
+        b.iter(|| {
+            fixed.set_register(gameboy::Reg::PC, START).expect("PC");
+            for _ in 0..STEPS {
+                fixed.step().expect("step");
+            }
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, interpreters, dispatch);
+criterion_main!(benches);
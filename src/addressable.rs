@@ -1,10 +1,43 @@
+use crate::inspect::Inspect;
+
 use std::error::Error;
 use std::fmt;
 use std::fmt::{Debug, Display, UpperHex};
 
+/// Addresses that can be stepped through, wrapping around the address space
+pub trait Address: Debug + Display + UpperHex + Copy {
+    fn offset(self, offset: usize) -> Self;
+}
+
+impl Address for u16 {
+    fn offset(self, offset: usize) -> Self {
+        self.wrapping_add(offset as u16)
+    }
+}
+
+/// Data two of which make up a little-endian word
+pub trait Data: Debug + Display + UpperHex + Copy {
+    type Word: Debug + Display + UpperHex + Copy;
+
+    fn join(low: Self, high: Self) -> Self::Word;
+    fn split(word: Self::Word) -> [Self; 2];
+}
+
+impl Data for u8 {
+    type Word = u16;
+
+    fn join(low: Self, high: Self) -> Self::Word {
+        u16::from_le_bytes([low, high])
+    }
+
+    fn split(word: Self::Word) -> [Self; 2] {
+        word.to_le_bytes()
+    }
+}
+
 pub trait Addressable {
-    type Addr: Debug + Display + UpperHex + Copy;
-    type Data: Debug + Display + UpperHex + Copy;
+    type Addr: Address;
+    type Data: Data;
 
     fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>>;
     fn write_byte(
@@ -12,6 +45,61 @@ pub trait Addressable {
         addr: Self::Addr,
         data: Self::Data,
     ) -> Result<(), AddressError<Self::Addr>>;
+
+    /// Reads a little-endian word, the high half from the next address
+    fn read_word(
+        &self,
+        addr: Self::Addr,
+    ) -> Result<<Self::Data as Data>::Word, AddressError<Self::Addr>> {
+        let low = self.read_byte(addr)?;
+        let high = self.read_byte(addr.offset(1))?;
+        Ok(Self::Data::join(low, high))
+    }
+
+    /// Writes a little-endian word, the low half first
+    fn write_word(
+        &mut self,
+        addr: Self::Addr,
+        word: <Self::Data as Data>::Word,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        let [low, high] = Self::Data::split(word);
+        self.write_byte(addr, low)?;
+        self.write_byte(addr.offset(1), high)
+    }
+
+    /// Fills `buf` with consecutive reads starting at `addr`
+    fn read_slice(
+        &self,
+        addr: Self::Addr,
+        buf: &mut [Self::Data],
+    ) -> Result<(), AddressError<Self::Addr>> {
+        for (offset, data) in buf.iter_mut().enumerate() {
+            *data = self.read_byte(addr.offset(offset))?;
+        }
+        Ok(())
+    }
+
+    /// Writes `data` to consecutive addresses starting at `addr`
+    fn write_slice(
+        &mut self,
+        addr: Self::Addr,
+        data: &[Self::Data],
+    ) -> Result<(), AddressError<Self::Addr>> {
+        for (offset, &data) in data.iter().enumerate() {
+            self.write_byte(addr.offset(offset), data)?;
+        }
+        Ok(())
+    }
+
+    /// Reads without side effects, for debuggers. Same as
+    /// `Inspect::inspect`, which devices override instead and trait objects
+    /// have to call.
+    fn peek(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>>
+    where
+        Self: Sized + Inspect,
+    {
+        self.inspect(addr)
+    }
 }
 
 #[derive(Debug)]
@@ -1,20 +1,71 @@
-use crate::addressable::Addressable;
+use crate::cartridge::Cartridge;
 use crate::gpu::GPU;
+use crate::inspect::Inspect;
+use crate::model::Model;
 use crate::ram::RAM;
 use crate::timed::Timed;
 
+use std::cell::Ref;
+
+/// Whether a watched address was read or written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A read or write of a watched address
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Access<Addr, Data> {
+    pub kind: AccessKind,
+    pub addr: Addr,
+    pub data: Data,
+}
+
 pub enum CopyOf {
     RAM,
     VRAM,
+    HRAM,
+    /// Cartridge RAM, empty if the cartridge has none
+    SRAM,
+    /// Bytes shifted out over the serial port
+    Serial,
 }
 
-pub trait Bus: Addressable + Timed + std::fmt::Debug {
+/// The boxed devices `Bus::create` wires together
+pub type Devices<Addr, Data> = (
+    Model,
+    Box<dyn Cartridge<Addr = Addr, Data = Data>>,
+    Box<dyn RAM<Addr = Addr, Data = Data>>,
+    Box<dyn GPU<Addr = Addr, Data = Data>>,
+);
+
+pub trait Bus: Inspect + Timed + std::fmt::Debug {
+    /// Only buses built from boxed devices support this
+    #[deprecated(note = "use `gameboy::Bus::create`, or `with_devices` to keep the device types")]
     fn create(
+        model: Model,
+        cartridge: Box<dyn Cartridge<Addr = Self::Addr, Data = Self::Data>>,
         ram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>,
         gpu: Box<dyn GPU<Addr = Self::Addr, Data = Self::Data>>,
     ) -> Self
     where
-        Self: Sized;
+        Self: Sized + From<Devices<Self::Addr, Self::Data>>,
+    {
+        Self::from((model, cartridge, ram, gpu))
+    }
 
     fn copy_of(&self, target: CopyOf) -> Vec<Self::Data>;
+
+    /// Bank of banked memory mapped at `addr`, 0 elsewhere
+    fn bank(&self, addr: Self::Addr) -> usize;
+
+    /// The GPU caught up to now, borrowed out of the bus
+    fn gpu(&self) -> Ref<'_, dyn GPU<Addr = Self::Addr, Data = Self::Data>>;
+
+    /// Performs a pending CGB speed switch, returns whether one happened
+    fn switch_speed(&mut self) -> bool;
+
+    /// Takes the cycles the CPU has to wait for a DMA transfer to finish
+    fn take_stall(&mut self) -> u32;
 }
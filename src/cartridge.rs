@@ -0,0 +1,15 @@
+use crate::inspect::Inspect;
+
+pub trait Cartridge: Inspect + std::fmt::Debug {
+    fn create(rom: Vec<Self::Data>) -> Self
+    where
+        Self: Sized;
+
+    /// Game title as stored in the cartridge header
+    fn title(&self) -> String;
+
+    /// Contents of the cartridge RAM, empty without any
+    fn deep_copy_ram(&self) -> Vec<Self::Data> {
+        Vec::new()
+    }
+}
@@ -1,5 +1,6 @@
 use crate::addressable::*;
 use crate::bus;
+use crate::model::Model;
 
 use std::fmt;
 use std::fmt::{Debug, Display};
@@ -8,10 +9,17 @@ pub trait CPU: Sized + Debug {
     type Addr: Debug + Display + Copy + fmt::UpperHex;
     type Data: Debug + Display + Copy;
 
-    fn create(
-        clock: u32,
-        bus: Box<dyn bus::Bus<Addr = Self::Addr, Data = Self::Data>>,
-    ) -> Self;
+    /// Only CPUs running a boxed bus support this
+    #[deprecated(note = "use `gameboy::CPU::create`, or `with_bus` to keep the bus type")]
+    fn create(model: Model, bus: Box<dyn bus::Bus<Addr = Self::Addr, Data = Self::Data>>) -> Self
+    where
+        Self: From<(
+            Model,
+            Box<dyn bus::Bus<Addr = Self::Addr, Data = Self::Data>>,
+        )>,
+    {
+        Self::from((model, bus))
+    }
 
     /// Executes the instruction at PC and returns cycles spent
     fn step(&mut self) -> Result<u32, CPUError<Self>>;
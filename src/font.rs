@@ -0,0 +1,131 @@
+use crate::framebuffer::Framebuffer;
+
+/// Glyphs are 3x5 pixels, drawn in cells with a pixel of spacing
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 6;
+
+/// Rows of the glyphs from space to Z, the leftmost pixel is bit 2
+const GLYPHS: [[u8; 5]; 59] = [
+    [0b000, 0b000, 0b000, 0b000, 0b000], // space
+    [0b010, 0b010, 0b010, 0b000, 0b010], // !
+    [0b101, 0b101, 0b000, 0b000, 0b000], // "
+    [0b101, 0b111, 0b101, 0b111, 0b101], // #
+    [0b011, 0b110, 0b010, 0b011, 0b110], // $
+    [0b101, 0b001, 0b010, 0b100, 0b101], // %
+    [0b010, 0b101, 0b010, 0b101, 0b011], // &
+    [0b010, 0b010, 0b000, 0b000, 0b000], // '
+    [0b001, 0b010, 0b010, 0b010, 0b001], // (
+    [0b100, 0b010, 0b010, 0b010, 0b100], // )
+    [0b000, 0b101, 0b010, 0b101, 0b000], // *
+    [0b000, 0b010, 0b111, 0b010, 0b000], // +
+    [0b000, 0b000, 0b000, 0b010, 0b100], // ,
+    [0b000, 0b000, 0b111, 0b000, 0b000], // -
+    [0b000, 0b000, 0b000, 0b000, 0b010], // .
+    [0b001, 0b001, 0b010, 0b100, 0b100], // /
+    [0b111, 0b101, 0b101, 0b101, 0b111], // 0
+    [0b010, 0b110, 0b010, 0b010, 0b111], // 1
+    [0b111, 0b001, 0b111, 0b100, 0b111], // 2
+    [0b111, 0b001, 0b111, 0b001, 0b111], // 3
+    [0b101, 0b101, 0b111, 0b001, 0b001], // 4
+    [0b111, 0b100, 0b111, 0b001, 0b111], // 5
+    [0b111, 0b100, 0b111, 0b101, 0b111], // 6
+    [0b111, 0b001, 0b001, 0b010, 0b010], // 7
+    [0b111, 0b101, 0b111, 0b101, 0b111], // 8
+    [0b111, 0b101, 0b111, 0b001, 0b111], // 9
+    [0b000, 0b010, 0b000, 0b010, 0b000], // :
+    [0b000, 0b010, 0b000, 0b010, 0b100], // ;
+    [0b001, 0b010, 0b100, 0b010, 0b001], // <
+    [0b000, 0b111, 0b000, 0b111, 0b000], // =
+    [0b100, 0b010, 0b001, 0b010, 0b100], // >
+    [0b111, 0b001, 0b011, 0b000, 0b010], // ?
+    [0b111, 0b101, 0b111, 0b100, 0b111], // @
+    [0b010, 0b101, 0b111, 0b101, 0b101], // A
+    [0b110, 0b101, 0b110, 0b101, 0b110], // B
+    [0b011, 0b100, 0b100, 0b100, 0b011], // C
+    [0b110, 0b101, 0b101, 0b101, 0b110], // D
+    [0b111, 0b100, 0b110, 0b100, 0b111], // E
+    [0b111, 0b100, 0b110, 0b100, 0b100], // F
+    [0b011, 0b100, 0b101, 0b101, 0b011], // G
+    [0b101, 0b101, 0b111, 0b101, 0b101], // H
+    [0b111, 0b010, 0b010, 0b010, 0b111], // I
+    [0b001, 0b001, 0b001, 0b101, 0b010], // J
+    [0b101, 0b101, 0b110, 0b101, 0b101], // K
+    [0b100, 0b100, 0b100, 0b100, 0b111], // L
+    [0b101, 0b111, 0b111, 0b101, 0b101], // M
+    [0b110, 0b101, 0b101, 0b101, 0b101], // N
+    [0b010, 0b101, 0b101, 0b101, 0b010], // O
+    [0b110, 0b101, 0b110, 0b100, 0b100], // P
+    [0b010, 0b101, 0b101, 0b110, 0b011], // Q
+    [0b110, 0b101, 0b110, 0b101, 0b101], // R
+    [0b011, 0b100, 0b010, 0b001, 0b110], // S
+    [0b111, 0b010, 0b010, 0b010, 0b010], // T
+    [0b101, 0b101, 0b101, 0b101, 0b111], // U
+    [0b101, 0b101, 0b101, 0b101, 0b010], // V
+    [0b101, 0b101, 0b111, 0b111, 0b101], // W
+    [0b101, 0b101, 0b010, 0b101, 0b101], // X
+    [0b101, 0b101, 0b010, 0b010, 0b010], // Y
+    [0b111, 0b001, 0b010, 0b100, 0b111], // Z
+];
+
+fn glyph(c: char) -> &'static [u8; 5] {
+    let index = match c.to_ascii_uppercase() {
+        c @ ' '..='Z' => c as usize - ' ' as usize,
+        _ => '?' as usize - ' ' as usize,
+    };
+    &GLYPHS[index]
+}
+
+/// Draws a line of text with its top left corner at (x, y) on a black
+/// background, so it reads over any picture. Lower case is drawn as upper
+/// case and unknown characters as `?`. Text off the frame is clipped.
+pub fn draw_text(frame: &mut Framebuffer, x: i32, y: i32, text: &str, color: u16) {
+    for (i, c) in text.chars().enumerate() {
+        let rows = glyph(c);
+        let left = x + (i * GLYPH_WIDTH) as i32;
+
+        // The spacing row below the glyph is blank
+        let rows = rows.iter().copied().chain([0]);
+        for (dy, row) in rows.enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                let (px, py) = (left + dx as i32, y + dy as i32);
+                if px < 0 || py < 0 || px as usize >= frame.width() || py as usize >= frame.height()
+                {
+                    continue;
+                }
+
+                let lit = dx < 3 && row & 0b100 >> dx != 0;
+                frame.set(px as usize, py as usize, if lit { color } else { 0 });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn text() {
+        let white = Framebuffer::rgb555(31, 31, 31);
+        let mut frame = Framebuffer::create(8, 6);
+        frame.set(7, 5, white);
+        draw_text(&mut frame, 0, 0, "t!", white);
+
+        let row = |frame: &Framebuffer, y| -> String {
+            (0..8)
+                .map(|x| if frame.get(x, y) == white { '#' } else { '.' })
+                .collect()
+        };
+        assert_eq!(row(&frame, 0), "###..#..");
+        assert_eq!(row(&frame, 1), ".#...#..");
+        assert_eq!(row(&frame, 3), ".#......");
+        assert_eq!(row(&frame, 4), ".#...#..");
+        // The background covers the spacing
+        assert_eq!(row(&frame, 5), "........");
+
+        // Clipped at the edges
+        draw_text(&mut frame, -2, 4, "\u{e9}", white);
+        assert_eq!(row(&frame, 4), "#....#..");
+        assert_eq!(row(&frame, 5), "#.......");
+    }
+}
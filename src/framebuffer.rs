@@ -0,0 +1,66 @@
+/// A rendered frame of 15-bit colors, bits 0-4 hold red, 5-9 green and
+/// 10-14 blue
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<u16>,
+}
+
+impl Framebuffer {
+    pub fn create(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Turns this into a copy of `other`, reusing the pixel allocation
+    pub fn copy_from(&mut self, other: &Framebuffer) {
+        self.width = other.width;
+        self.height = other.height;
+        self.pixels.clone_from(&other.pixels);
+    }
+
+    /// Pixels in row-major order
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> u16 {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: u16) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    /// Packs 5-bit channels into a 15-bit color
+    pub const fn rgb555(r: u8, g: u8, b: u8) -> u16 {
+        (r as u16 & 0x1F) | (g as u16 & 0x1F) << 5 | (b as u16 & 0x1F) << 10
+    }
+
+    /// Quantizes 8-bit channels to a 15-bit color
+    pub const fn from_rgb888(rgb: [u8; 3]) -> u16 {
+        Self::rgb555(rgb[0] >> 3, rgb[1] >> 3, rgb[2] >> 3)
+    }
+
+    /// Expands a 15-bit color to 8-bit channels, mapping 31 to 255
+    pub const fn to_rgb888(color: u16) -> [u8; 3] {
+        [expand5(color), expand5(color >> 5), expand5(color >> 10)]
+    }
+}
+
+const fn expand5(channel: u16) -> u8 {
+    let c = (channel & 0x1F) as u8;
+    c << 3 | c >> 2
+}
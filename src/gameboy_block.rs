@@ -0,0 +1,161 @@
+use crate::bus::Bus;
+use crate::cpu::CPUError;
+use crate::gameboy_cpu::CPU;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::{Range, RangeInclusive};
+use std::rc::Rc;
+
+/// Longest run of instructions decoded into one block
+pub const MAX_BLOCK_LEN: usize = 64;
+
+/// 0xE000-0xFDFF mirrors work RAM at 0xC000-0xDDFF
+const ECHO_START: u16 = 0xE000;
+const ECHO_END: u16 = 0xFDFF;
+const ECHO_OFFSET: u16 = 0x2000;
+/// CGB WRAM bank select
+const SVBK: u16 = 0xFF70;
+
+/// An instruction decoded ahead of time, opcode and immediate fetches
+/// included. Returns the cycles the instruction takes like a step does.
+pub type Op<B> = Box<dyn Fn(&mut CPU<B>) -> Result<u32, CPUError<CPU<B>>>>;
+
+/// Memory code may be cached from, split where banks switch. Only ROM, work
+/// RAM and high RAM qualify, the rest is I/O or written behind the CPU's
+/// back by DMA.
+const REGIONS: [RangeInclusive<u16>; 5] = [
+    0x0000..=0x3FFF,
+    0x4000..=0x7FFF,
+    0xC000..=0xCFFF,
+    0xD000..=0xDFFF,
+    0xFF80..=0xFFFE,
+];
+
+/// Region of cacheable memory `addr` lies in, if any. A block's code all
+/// comes from one, so the bank it starts in is the bank of all of it.
+pub fn region(addr: u16) -> Option<usize> {
+    REGIONS.iter().position(|region| region.contains(&addr))
+}
+
+// Straight-line code predecoded from one address on. Ends at the first
+// instruction without a specialized form, which falls back to the
+// interpreter, or before leaving its region of cacheable memory.
+pub struct Block<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> {
+    pcs: Vec<u16>,
+    ops: Vec<Op<B>>,
+    /// Addresses the instructions were decoded from
+    bytes: Range<u16>,
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> Block<B> {
+    pub fn create(start: u16) -> Self {
+        Block {
+            pcs: Vec::new(),
+            ops: Vec::new(),
+            bytes: start..start,
+        }
+    }
+
+    pub fn push(&mut self, pc: u16, width: u16, op: Op<B>) {
+        self.pcs.push(pc);
+        self.ops.push(op);
+        self.bytes.end = pc + width;
+    }
+
+    /// Address of the instruction at `index`
+    pub fn pc(&self, index: usize) -> Option<u16> {
+        self.pcs.get(index).copied()
+    }
+
+    pub fn op(&self, index: usize) -> &Op<B> {
+        &self.ops[index]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> fmt::Debug for Block<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Block")
+            .field("pcs", &self.pcs)
+            .finish_non_exhaustive()
+    }
+}
+
+// Blocks keyed by the bank and address they start at. Tracks which
+// addresses cached code came from, writing to any of them drops the whole
+// cache as code modifying itself is rare.
+pub struct BlockCache<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> {
+    blocks: HashMap<(usize, u16), Rc<Block<B>>>,
+    /// One bit per address any cached block was decoded from
+    code: Box<[u64; 1024]>,
+    /// Bumped whenever a block being run may have gone stale
+    generation: u64,
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> BlockCache<B> {
+    pub fn create() -> Self {
+        BlockCache {
+            blocks: HashMap::new(),
+            code: Box::new([0; 1024]),
+            generation: 0,
+        }
+    }
+
+    pub fn get(&self, bank: usize, pc: u16) -> Option<Rc<Block<B>>> {
+        self.blocks.get(&(bank, pc)).cloned()
+    }
+
+    pub fn insert(&mut self, bank: usize, block: Block<B>) -> Rc<Block<B>> {
+        for addr in block.bytes.clone() {
+            self.code[addr as usize / 64] |= 1 << (addr % 64);
+        }
+
+        let block = Rc::new(block);
+        self.blocks.insert((bank, block.bytes.start), block.clone());
+        block
+    }
+
+    /// Notes a CPU write, code written over is dropped
+    pub fn write(&mut self, addr: u16) {
+        let addr = match addr {
+            ECHO_START..=ECHO_END => addr - ECHO_OFFSET,
+            // The code following the write may now come from another bank
+            SVBK => {
+                self.generation += 1;
+                return;
+            }
+            _ => addr,
+        };
+
+        if self.code[addr as usize / 64] & 1 << (addr % 64) != 0 {
+            self.flush();
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.blocks.clear();
+        self.code.fill(0);
+        self.generation += 1;
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn len(&self) -> usize {
+        self.blocks.len()
+    }
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> fmt::Debug for BlockCache<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockCache")
+            .field("blocks", &self.blocks.len())
+            .field("generation", &self.generation)
+            .finish()
+    }
+}
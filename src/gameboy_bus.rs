@@ -1,21 +1,600 @@
 use crate::addressable::*;
 use crate::bus;
+use crate::cartridge::Cartridge;
+use crate::gameboy_cheats::{GameGenie, PatchedCartridge};
+use crate::gameboy_hdma::{Hdma, HDMA_BLOCK_CYCLES, HDMA_BLOCK_SIZE};
+use crate::gameboy_joypad::{Buttons, Joypad};
+use crate::gameboy_ram;
+use crate::gameboy_serial::{Serial, SERIAL_REGISTERS};
+use crate::gameboy_sgb::{Request, Sgb, SGB_TRANSFER_SIZE};
+use crate::gameboy_timer::{Timer, TIMER_REGISTERS};
 use crate::gpu::GPU;
+use crate::inspect::Inspect;
+use crate::model::Model;
 use crate::ram::RAM;
+use crate::scheduler::Scheduler;
 use crate::timed::*;
 
+use std::cell::{Cell, Ref, RefCell};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// High RAM, 0xFF80-0xFFFE
+const HRAM_START: u16 = 0xFF80;
+const HRAM_SIZE: usize = 127;
+
+/// 0xE000-0xFDFF mirrors work RAM at 0xC000-0xDDFF
+const ECHO_START: u16 = 0xE000;
+const ECHO_END: u16 = 0xFDFF;
+const ECHO_OFFSET: u16 = 0x2000;
+
+const IO_START: u16 = 0xFF00;
+const IO_END: u16 = 0xFF7F;
+const JOYP: u16 = 0xFF00;
+/// Both select lines released
+const JOYP_SELECT: u8 = 0x30;
+/// Interrupt flags, raised by the timer, the serial port and joypad presses
+const IF: u16 = 0xFF0F;
+const TIMER_INTERRUPT: u8 = 1 << 2;
+const SERIAL_INTERRUPT: u8 = 1 << 3;
+const JOYPAD_INTERRUPT: u8 = 1 << 4;
+const LCDC: u16 = 0xFF40;
+/// LCDC bit selecting tile data at 0x8000 rather than 0x8800
+const LCDC_TILE_DATA: u8 = 1 << 4;
+/// LCD registers, STAT and LY among them change as the GPU runs
+const GPU_REGISTERS: RangeInclusive<u16> = LCDC..=0xFF4B;
+/// CGB mode select, bit 2 drops to DMG compatibility mode
+const KEY0: u16 = 0xFF4C;
+const KEY0_DMG_MODE: u8 = 1 << 2;
+/// CGB speed switch, bit 7 is the current speed and bit 0 arms a switch
+const KEY1: u16 = 0xFF4D;
+/// Writing bit 0 unmaps the boot ROM for good
+const BOOT: u16 = 0xFF50;
+/// CGB VRAM and WRAM bank selects, handled by banked RAM
+const VBK: u16 = 0xFF4F;
+const SVBK: u16 = 0xFF70;
+/// CGB VRAM DMA registers
+const HDMA_START: u16 = 0xFF51;
+const HDMA_END: u16 = 0xFF55;
+/// Writing XX copies 0xXX00-0xXX9F into OAM
+const DMA: u16 = 0xFF46;
+const IE: u16 = 0xFFFF;
+
+const OAM_START: u16 = 0xFE00;
+const OAM_SIZE: u16 = 160;
+
+/// Things components have scheduled to happen
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Event {
+    /// The GPU enters its next mode
+    GpuMode,
+    /// TIMA overflows
+    TimerOverflow,
+    /// The APU's frame sequencer steps, 512 times a second
+    FrameSequencer,
+    /// The serial port shifts a bit
+    SerialBit,
+}
+
+type AccessFn<C, R> = dyn FnMut(&mut Bus<C, R>, bus::Access<u16, u8>);
+
+/// Called with each access of a watched address as it happens, see
+/// `Bus::set_access_hook`
+pub struct AccessHook<
+    C: ?Sized = dyn Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized = dyn RAM<Addr = u16, Data = u8>,
+>(pub Box<AccessFn<C, R>>);
+
+impl<C: ?Sized, R: ?Sized> fmt::Debug for AccessHook<C, R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AccessHook")
+    }
+}
+
+// A device running on the bus's time, only caught up when its registers
+// are accessed or one of its events is due. Reads of the registers happen
+// through a shared reference, hence the cells.
+#[derive(Debug)]
+struct Clocked<D> {
+    device: RefCell<D>,
+    /// Time the device has been caught up to
+    synced: Cell<CycleTime>,
+    /// Registers that change as time passes
+    registers: RangeInclusive<u16>,
+}
+
+impl<D: Timed> Clocked<D> {
+    fn create(device: D, registers: RangeInclusive<u16>, now: CycleTime) -> Self {
+        Clocked {
+            device: RefCell::new(device),
+            synced: Cell::new(now),
+            registers,
+        }
+    }
+
+    /// The device to access `addr` on, caught up first if that is one of
+    /// its registers
+    fn at(&self, addr: u16, now: CycleTime) -> Ref<'_, D> {
+        if self.registers.contains(&addr) {
+            self.catch_up(now);
+        }
+        self.device.borrow()
+    }
+
+    /// Catches the device up unless it is borrowed, e.g. through `Bus::gpu`,
+    /// in which case it has to be left behind
+    fn catch_up(&self, now: CycleTime) {
+        if let Ok(mut device) = self.device.try_borrow_mut() {
+            device.catchup(now - self.synced.get());
+            self.synced.set(now);
+        }
+    }
+
+    /// The device caught up to `now`
+    fn sync(&mut self, now: CycleTime) -> &mut D {
+        let device = self.device.get_mut();
+        device.catchup(now - self.synced.get());
+        self.synced.set(now);
+        device
+    }
+
+    fn get_mut(&mut self) -> &mut D {
+        self.device.get_mut()
+    }
+
+    fn borrow(&self) -> Ref<'_, D> {
+        self.device.borrow()
+    }
+}
+
+// Generic over the cartridge and work RAM, which most accesses go to, so a
+// system whose devices are known at compile time dispatches them
+// statically. The defaults take any device.
 #[derive(Debug)]
-pub struct Bus {
-    ram: Box<dyn RAM<Addr = u16, Data = u8>>,
-    gpu: Box<dyn GPU<Addr = u16, Data = u8>>,
+pub struct Bus<
+    C: ?Sized = dyn Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized = dyn RAM<Addr = u16, Data = u8>,
+> {
+    cartridge: PatchedCartridge<C>,
+    ram: Box<R>,
+    hram: gameboy_ram::RAM<HRAM_SIZE>,
+    serial: Clocked<Serial>,
+    timer: Clocked<Timer>,
+    joypad: Joypad,
+    gpu: Clocked<Box<dyn GPU<Addr = u16, Data = u8>>>,
+    /// Latched values of I/O registers without an emulated device
+    io: [u8; (IO_END - IO_START) as usize + 1],
+    /// Interrupt enable register
+    ie: u8,
+    /// Whether CGB only registers are mapped
+    cgb: bool,
+    /// Whether the boot ROM is still mapped, KEY0 locks once it is gone
+    boot_rom: bool,
+    key1: u8,
+    hdma: Hdma,
+    /// CPU cycles owed to DMA transfers
+    stall: u32,
+    /// Listens for command packets on JOYP on the SGB
+    sgb: Option<Sgb>,
+    watch_reads: BTreeSet<u16>,
+    watch_writes: BTreeSet<u16>,
+    /// Reads happen through a shared reference
+    accesses: RefCell<Vec<bus::Access<u16, u8>>>,
+    access_hook: Option<AccessHook<C, R>>,
+    scheduler: Scheduler<Event>,
+    /// HBlanks HBlank DMA has copied its blocks for. Reads catch the GPU up
+    /// without copying any.
+    hdma_hblanks: u64,
+}
+
+impl Bus {
+    /// CGB models need banked RAM passed here and to the GPU to get the full
+    /// CGB memory map
+    pub fn create(
+        model: Model,
+        cartridge: Box<dyn Cartridge<Addr = u16, Data = u8>>,
+        ram: Box<dyn RAM<Addr = u16, Data = u8>>,
+        gpu: Box<dyn GPU<Addr = u16, Data = u8>>,
+    ) -> Self {
+        Bus::with_devices(model, cartridge, ram, gpu)
+    }
+}
+
+impl From<bus::Devices<u16, u8>> for Bus {
+    fn from((model, cartridge, ram, gpu): bus::Devices<u16, u8>) -> Self {
+        Bus::create(model, cartridge, ram, gpu)
+    }
+}
+
+impl<C, R> Bus<C, R>
+where
+    C: ?Sized + Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized + RAM<Addr = u16, Data = u8>,
+{
+    /// Like `create`, keeping the cartridge and RAM types
+    pub fn with_devices(
+        model: Model,
+        cartridge: Box<C>,
+        ram: Box<R>,
+        gpu: Box<dyn GPU<Addr = u16, Data = u8>>,
+    ) -> Self {
+        let hram = gameboy_ram::RAM::<HRAM_SIZE>::create(HRAM_START);
+        // Ticks of the double speed clock, which every CPU speed divides
+        let frequency = 2 * Model::CYCLE_CLOCK;
+        let start = CycleTime::new(frequency, 0);
+        let hdma_hblanks = gpu.hblanks();
+        let mut bus = Bus {
+            cartridge: PatchedCartridge::create(cartridge),
+            ram,
+            hram,
+            serial: Clocked::create(Serial::create(), SERIAL_REGISTERS, start),
+            timer: Clocked::create(Timer::create(), TIMER_REGISTERS, start),
+            joypad: Joypad::create(),
+            gpu: Clocked::create(gpu, GPU_REGISTERS, start),
+            io: [0; (IO_END - IO_START) as usize + 1],
+            ie: 0,
+            cgb: model.is_cgb(),
+            boot_rom: true,
+            key1: 0,
+            hdma: Hdma::create(),
+            stall: 0,
+            sgb: (model == Model::SGB).then(Sgb::create),
+            watch_reads: BTreeSet::new(),
+            watch_writes: BTreeSet::new(),
+            accesses: RefCell::new(Vec::new()),
+            access_hook: None,
+            scheduler: Scheduler::create(frequency),
+            hdma_hblanks,
+        };
+        bus.schedule_gpu();
+        bus.schedule_timer();
+        bus
+    }
+
+    /// Super Game Boy state, only present on the SGB model
+    pub fn sgb(&self) -> Option<&Sgb> {
+        self.sgb.as_ref()
+    }
+
+    /// Replaces the Game Genie codes patching cartridge reads
+    pub fn set_game_genie(&mut self, codes: Vec<GameGenie>) {
+        self.cartridge.set_codes(codes);
+    }
+
+    /// Addresses whose reads and writes are recorded for `take_accesses`
+    pub fn set_watchpoints(&mut self, reads: BTreeSet<u16>, writes: BTreeSet<u16>) {
+        self.watch_reads = reads;
+        self.watch_writes = writes;
+    }
+
+    /// Accesses of watched addresses since the last call, oldest first
+    pub fn take_accesses(&mut self) -> Vec<bus::Access<u16, u8>> {
+        std::mem::take(self.accesses.get_mut())
+    }
+
+    /// Hands accesses of watched addresses to `hook` as they happen rather
+    /// than keeping them for `take_accesses`. Writes are reported right
+    /// after they land, reads before the next M-cycle or `report_accesses`.
+    /// Accesses the hook makes itself aren't reported.
+    pub fn set_access_hook(&mut self, hook: Option<AccessHook<C, R>>) {
+        self.access_hook = hook;
+    }
+
+    /// Runs the access hook for the accesses recorded so far
+    pub fn report_accesses(&mut self) {
+        if self.accesses.get_mut().is_empty() {
+            return;
+        }
+        let Some(mut hook) = self.access_hook.take() else {
+            return;
+        };
+
+        for access in std::mem::take(self.accesses.get_mut()) {
+            (hook.0)(self, access);
+            self.accesses.get_mut().clear();
+        }
+        self.access_hook = Some(hook);
+    }
+
+    /// Sets the buttons currently held down
+    pub fn set_buttons(&mut self, buttons: Buttons) {
+        if self.joypad.set_buttons(buttons) {
+            self.raise(JOYPAD_INTERRUPT);
+        }
+    }
+
+    fn read_mapped(&self, addr: u16) -> Result<u8, AddressError<u16>> {
+        // Banked RAM doesn't know about compatibility mode
+        if !self.cgb && matches!(addr, VBK | SVBK) {
+            return self.read_unmapped(addr);
+        }
+
+        let now = self.scheduler.now();
+        self.cartridge
+            .read_byte(addr)
+            .or_else(|_| self.ram.read_byte(addr))
+            .or_else(|_| self.hram.read_byte(addr))
+            .or_else(|_| self.serial.at(addr, now).read_byte(addr))
+            .or_else(|_| self.timer.at(addr, now).read_byte(addr))
+            .or_else(|_| self.gpu.at(addr, now).read_byte(addr))
+            .or_else(|_| self.read_unmapped(addr))
+    }
+
+    fn write_mapped(&mut self, addr: u16, data: u8) -> Result<(), AddressError<u16>> {
+        if !self.cgb && matches!(addr, VBK | SVBK) {
+            return self.write_unmapped(addr, data);
+        }
+        if addr == BOOT && data & 0x01 != 0 {
+            self.boot_rom = false;
+        }
+        if addr == KEY0 {
+            if !self.boot_rom {
+                return Ok(());
+            }
+            // The GPU sees the write as well and switches its palettes
+            if self.cgb && data & KEY0_DMG_MODE != 0 {
+                self.cgb = false;
+            }
+        }
+
+        self.cartridge
+            .write_byte(addr, data)
+            .or_else(|_| self.ram.write_byte(addr, data))
+            .or_else(|_| self.hram.write_byte(addr, data))
+            .or_else(|_| self.write_serial(addr, |serial| serial.write_byte(addr, data)))
+            .or_else(|_| self.write_timer(addr, |timer| timer.write_byte(addr, data)))
+            .or_else(|_| self.write_gpu(|gpu| gpu.write_byte(addr, data)))
+            .or_else(|_| self.write_unmapped(addr, data))
+    }
+
+    /// Writes to the serial port caught up, as they start transfers
+    fn write_serial<F>(&mut self, addr: u16, write: F) -> Result<(), AddressError<u16>>
+    where
+        F: FnOnce(&mut Serial) -> Result<(), AddressError<u16>>,
+    {
+        if !SERIAL_REGISTERS.contains(&addr) {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        self.sync_serial();
+        let result = write(self.serial.get_mut());
+        self.schedule_serial();
+        result
+    }
+
+    /// Writes to the timer caught up, as they move its events
+    fn write_timer<F>(&mut self, addr: u16, write: F) -> Result<(), AddressError<u16>>
+    where
+        F: FnOnce(&mut Timer) -> Result<(), AddressError<u16>>,
+    {
+        if !TIMER_REGISTERS.contains(&addr) {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        self.sync_timer();
+        let result = write(self.timer.get_mut());
+        self.schedule_timer();
+        result
+    }
+
+    fn raise(&mut self, interrupt: u8) {
+        self.io[(IF - IO_START) as usize] |= interrupt;
+    }
+
+    fn sync_serial(&mut self) {
+        if self.serial.sync(self.scheduler.now()).take_interrupt() {
+            self.raise(SERIAL_INTERRUPT);
+        }
+    }
+
+    fn schedule_serial(&mut self) {
+        match self.serial.borrow().next_bit() {
+            Some(delay) => self.scheduler.schedule(Event::SerialBit, delay),
+            None => self.scheduler.cancel(Event::SerialBit),
+        }
+    }
+
+    fn sync_timer(&mut self) {
+        if self.timer.sync(self.scheduler.now()).take_interrupt() {
+            self.raise(TIMER_INTERRUPT);
+        }
+    }
+
+    fn schedule_timer(&mut self) {
+        let timer = self.timer.borrow();
+        match timer.next_overflow() {
+            Some(delay) => self.scheduler.schedule(Event::TimerOverflow, delay),
+            None => self.scheduler.cancel(Event::TimerOverflow),
+        }
+        self.scheduler
+            .schedule(Event::FrameSequencer, timer.next_frame_step());
+    }
+
+    /// Writes to the GPU caught up, as the write may change its timing
+    fn write_gpu<F>(&mut self, write: F) -> Result<(), AddressError<u16>>
+    where
+        F: FnOnce(&mut dyn GPU<Addr = u16, Data = u8>) -> Result<(), AddressError<u16>>,
+    {
+        self.sync_gpu();
+        let result = write(&mut **self.gpu.get_mut());
+        self.schedule_gpu();
+        result
+    }
+
+    /// Catches the GPU up to now, HBlank DMA copies a block for every
+    /// HBlank entered
+    fn sync_gpu(&mut self) {
+        let hblanks = self.gpu.sync(self.scheduler.now()).hblanks();
+
+        for _ in self.hdma_hblanks..hblanks {
+            if let Some(block) = self.hdma.hblank_block() {
+                // The destination is always within VRAM
+                let _ = self.vram_dma(block);
+            }
+        }
+        self.hdma_hblanks = hblanks;
+    }
+
+    fn schedule_gpu(&mut self) {
+        match self.gpu.borrow().next_event() {
+            Some(delay) => self.scheduler.schedule(Event::GpuMode, delay),
+            None => self.scheduler.cancel(Event::GpuMode),
+        }
+    }
+
+    /// Regions without an emulated device. I/O registers keep the last
+    /// written value, anything else reads as open bus and ignores writes.
+    fn read_unmapped(&self, addr: u16) -> Result<u8, AddressError<u16>> {
+        match addr {
+            ECHO_START..=ECHO_END => self.ram.read_byte(addr - ECHO_OFFSET),
+            // With both lines released the SGB answers with the selected
+            // controller after an MLT_REQ
+            IO_START..=IO_END if addr == JOYP => match &self.sgb {
+                Some(sgb) if self.joypad.select() == JOYP_SELECT => {
+                    Ok(0xC0 | JOYP_SELECT | sgb.joypad_id())
+                }
+                _ => Ok(self.joypad.read()),
+            },
+            KEY1 if self.cgb => Ok(0x7E | self.key1),
+            KEY1 => Ok(0xFF),
+            HDMA_START..=HDMA_END if self.cgb => self.hdma.read_byte(addr),
+            IO_START..=IO_END => Ok(self.io[(addr - IO_START) as usize]),
+            IE => Ok(self.ie),
+            _ => Ok(0xFF),
+        }
+    }
+
+    fn write_unmapped(&mut self, addr: u16, data: u8) -> Result<(), AddressError<u16>> {
+        match addr {
+            ECHO_START..=ECHO_END => return self.ram.write_byte(addr - ECHO_OFFSET, data),
+            KEY1 if self.cgb => self.key1 = self.key1 & 0x80 | data & 0x01,
+            HDMA_START..=HDMA_END if self.cgb => {
+                self.hdma.write_byte(addr, data)?;
+                while let Some(block) = self.hdma.general_block() {
+                    self.vram_dma(block)?;
+                }
+            }
+            IO_START..=IO_END => {
+                self.io[(addr - IO_START) as usize] = data;
+                if addr == JOYP {
+                    self.joypad.write(data);
+                    self.sgb_write(data);
+                }
+                if addr == DMA {
+                    return self.oam_dma(data);
+                }
+            }
+            IE => self.ie = data,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Stores into regions without an emulated device, leaving out the
+    /// transfers and SGB commands writes start
+    fn patch_unmapped(&mut self, addr: u16, data: u8) -> Result<(), AddressError<u16>> {
+        match addr {
+            ECHO_START..=ECHO_END => return self.ram.patch(addr - ECHO_OFFSET, data),
+            KEY1 if self.cgb => self.key1 = data & 0x81,
+            HDMA_START..=HDMA_END if self.cgb => return self.hdma.patch(addr, data),
+            IO_START..=IO_END => {
+                self.io[(addr - IO_START) as usize] = data;
+                if addr == JOYP {
+                    self.joypad.write(data);
+                }
+            }
+            IE => self.ie = data,
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Copies one HDMA block into VRAM and stalls the CPU for it, twice the
+    /// cycles in double speed mode to take the same time
+    fn vram_dma(&mut self, (src, dest): (u16, u16)) -> Result<(), AddressError<u16>> {
+        for offset in 0..HDMA_BLOCK_SIZE {
+            let byte = self.read_byte(src.wrapping_add(offset)).unwrap_or(0xFF);
+            self.gpu.get_mut().write_byte(dest + offset, byte)?;
+        }
+
+        self.stall += HDMA_BLOCK_CYCLES << (self.key1 >> 7);
+
+        Ok(())
+    }
+
+    /// Passes JOYP writes on to the SGB and carries out whatever the command
+    /// needs from VRAM or the GPU
+    fn sgb_write(&mut self, data: u8) {
+        let Some(sgb) = &mut self.sgb else {
+            return;
+        };
+
+        sgb.write_joypad(data);
+        match sgb.take_request() {
+            Some(Request::Transfer(transfer)) => {
+                let data = self.sgb_transfer_data();
+                if let Some(sgb) = &mut self.sgb {
+                    sgb.transfer(transfer, &data);
+                }
+            }
+            Some(Request::Freeze) => sgb.freeze(self.gpu.borrow().shades().to_vec()),
+            None => {}
+        }
+    }
+
+    /// The SGB reads transfers off the screen, games lay the tiles out in
+    /// order so this is the tile data LCDC points at
+    fn sgb_transfer_data(&self) -> Vec<u8> {
+        let gpu = self.gpu.borrow();
+        let lcdc = gpu.read_byte(LCDC).unwrap_or(0);
+        let base: u16 = if lcdc & LCDC_TILE_DATA != 0 {
+            0x8000
+        } else {
+            0x8800
+        };
+
+        (0..SGB_TRANSFER_SIZE as u16)
+            .map(|offset| gpu.read_byte(base + offset).unwrap_or(0xFF))
+            .collect()
+    }
+
+    /// Copies a page into OAM. The transfer happens instantly rather than
+    /// over 160 M-cycles.
+    fn oam_dma(&mut self, page: u8) -> Result<(), AddressError<u16>> {
+        let src = (page as u16) << 8;
+
+        for offset in 0..OAM_SIZE {
+            let byte = self.read_byte(src + offset)?;
+            self.gpu.get_mut().write_byte(OAM_START + offset, byte)?;
+        }
+
+        Ok(())
+    }
 }
 
-impl Addressable for Bus {
+impl<C, R> Addressable for Bus<C, R>
+where
+    C: ?Sized + Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized + RAM<Addr = u16, Data = u8>,
+{
     type Addr = u16;
     type Data = u8;
 
     fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
-        self.ram.read_byte(addr).or(self.gpu.read_byte(addr))
+        let data = self.read_mapped(addr)?;
+
+        if self.watch_reads.contains(&addr) {
+            self.accesses.borrow_mut().push(bus::Access {
+                kind: bus::AccessKind::Read,
+                addr,
+                data,
+            });
+        }
+
+        Ok(data)
     }
 
     fn write_byte(
@@ -23,31 +602,232 @@ impl Addressable for Bus {
         addr: Self::Addr,
         data: Self::Data,
     ) -> Result<(), AddressError<Self::Addr>> {
-        self.ram
-            .write_byte(addr, data)
-            .or(self.gpu.write_byte(addr, data))
+        self.write_mapped(addr, data)?;
+
+        if self.watch_writes.contains(&addr) {
+            self.accesses.get_mut().push(bus::Access {
+                kind: bus::AccessKind::Write,
+                addr,
+                data,
+            });
+            self.report_accesses();
+        }
+
+        Ok(())
     }
 }
 
-impl Timed for Bus {
-    fn catchup(&mut self, time: CycleTime) {
-        self.gpu.catchup(time);
-        // TODO: self.timer.catchup(time);
+/// Goes through the devices' own inspection, watchpoints don't see it
+impl<C, R> Inspect for Bus<C, R>
+where
+    C: ?Sized + Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized + RAM<Addr = u16, Data = u8>,
+{
+    fn inspect(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        if !self.cgb && matches!(addr, VBK | SVBK) {
+            return self.read_unmapped(addr);
+        }
+
+        let now = self.scheduler.now();
+        self.cartridge
+            .inspect(addr)
+            .or_else(|_| self.ram.inspect(addr))
+            .or_else(|_| self.hram.inspect(addr))
+            .or_else(|_| self.serial.at(addr, now).inspect(addr))
+            .or_else(|_| self.timer.at(addr, now).inspect(addr))
+            .or_else(|_| self.gpu.at(addr, now).inspect(addr))
+            .or_else(|_| self.read_unmapped(addr))
+    }
+
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        if !self.cgb && matches!(addr, VBK | SVBK) {
+            return self.patch_unmapped(addr, data);
+        }
+
+        self.cartridge
+            .patch(addr, data)
+            .or_else(|_| self.ram.patch(addr, data))
+            .or_else(|_| self.hram.patch(addr, data))
+            .or_else(|_| self.write_serial(addr, |serial| serial.patch(addr, data)))
+            .or_else(|_| self.write_timer(addr, |timer| timer.patch(addr, data)))
+            .or_else(|_| self.write_gpu(|gpu| gpu.patch(addr, data)))
+            .or_else(|_| self.patch_unmapped(addr, data))
     }
 }
 
-impl bus::Bus for Bus {
-    fn create(
-        ram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>,
-        gpu: Box<dyn GPU<Addr = Self::Addr, Data = Self::Data>>,
-    ) -> Self {
-        Bus { ram, gpu }
+/// Only moves time forward, components catch up when their events are due
+impl<C, R> Timed for Bus<C, R>
+where
+    C: ?Sized + Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized + RAM<Addr = u16, Data = u8>,
+{
+    fn catchup(&mut self, time: CycleTime) {
+        // Reads of the last M-cycle, made through a shared reference
+        self.report_accesses();
+        self.scheduler.advance(time);
+
+        while let Some(event) = self.scheduler.pop() {
+            match event {
+                Event::GpuMode => {
+                    self.sync_gpu();
+                    self.schedule_gpu();
+                }
+                Event::TimerOverflow | Event::FrameSequencer => {
+                    self.sync_timer();
+                    self.schedule_timer();
+                }
+                Event::SerialBit => {
+                    self.sync_serial();
+                    self.schedule_serial();
+                }
+            }
+        }
     }
+}
 
+impl<C, R> bus::Bus for Bus<C, R>
+where
+    C: ?Sized + Cartridge<Addr = u16, Data = u8>,
+    R: ?Sized + RAM<Addr = u16, Data = u8>,
+{
     fn copy_of(&self, target: bus::CopyOf) -> Vec<Self::Data> {
         match target {
             bus::CopyOf::RAM => self.ram.deep_copy(),
-            bus::CopyOf::VRAM => self.gpu.deep_copy(),
+            bus::CopyOf::VRAM => self.gpu.borrow().deep_copy(),
+            bus::CopyOf::HRAM => self.hram.deep_copy(),
+            bus::CopyOf::SRAM => self.cartridge.cartridge().deep_copy_ram(),
+            bus::CopyOf::Serial => self.serial.borrow().output().to_vec(),
+        }
+    }
+
+    /// Only work RAM is banked, the cartridge has no MBC
+    fn bank(&self, addr: Self::Addr) -> usize {
+        match addr {
+            0xC000..=0xDFFF => self.ram.bank(addr),
+            ECHO_START..=ECHO_END => self.ram.bank(addr - ECHO_OFFSET),
+            _ => 0,
+        }
+    }
+
+    fn gpu(&self) -> Ref<'_, dyn GPU<Addr = Self::Addr, Data = Self::Data>> {
+        self.gpu.catch_up(self.scheduler.now());
+        Ref::map(self.gpu.borrow(), |gpu| &**gpu)
+    }
+
+    fn switch_speed(&mut self) -> bool {
+        if !self.cgb || self.key1 & 0x01 == 0 {
+            return false;
+        }
+
+        // The timer and serial port run off the CPU's clock
+        self.sync_timer();
+        self.sync_serial();
+        self.key1 = !self.key1 & 0x80;
+        let double_speed = self.key1 & 0x80 != 0;
+        self.timer.get_mut().set_double_speed(double_speed);
+        self.serial.get_mut().set_double_speed(double_speed);
+        self.schedule_timer();
+        self.schedule_serial();
+        true
+    }
+
+    fn take_stall(&mut self) -> u32 {
+        std::mem::take(&mut self.stall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn cgb() -> gameboy::CPU {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = 0x80;
+        gameboy::create_system(Model::CGB, gameboy::Cartridge::create(rom))
+    }
+
+    #[test]
+    fn inspect_and_patch() {
+        let mut cpu = cgb();
+        let serial = |cpu: &gameboy::CPU| cpu.bus().copy_of(CopyOf::Serial);
+
+        cpu.bus_apply(|bus| {
+            bus.set_watchpoints([0xC000].into(), [0xC000].into());
+
+            // ROM takes patches, CPU writes go nowhere
+            bus.patch(0x0150, 0x76).expect("ROM patch");
+            bus.write_byte(0x0151, 0x76).expect("ROM write");
+            bus.patch(0xC000, 0x12).expect("RAM patch");
+            assert_eq!(bus.inspect(0xC000).expect("inspect"), 0x12);
+            assert!(bus.take_accesses().is_empty());
+
+            // No transfer starts, and the palette index stays put
+            bus.patch(0xFF01, 0x42).expect("SB patch");
+            bus.patch(0xFF02, 0x81).expect("SC patch");
+            bus.patch(0xFF68, 0x82).expect("BCPS patch");
+            bus.patch(0xFF69, 0x1F).expect("BCPD patch");
+        });
+
+        assert_eq!(cpu.bus().read_byte(0x0150).expect("read"), 0x76);
+        assert_eq!(cpu.bus().read_byte(0x0151).expect("read"), 0x00);
+        assert!(serial(&cpu).is_empty());
+        assert_eq!(cpu.bus().inspect(0xFF02).expect("SC"), 0xFF);
+        assert_eq!(cpu.bus().inspect(0xFF68).expect("BCPS"), 0xC2);
+        assert_eq!(cpu.bus().inspect(0xFF69).expect("BCPD"), 0x1F);
+
+        // The CPU's writes still have their effects
+        cpu.bus_apply(|bus| bus.write_byte(0xFF02, 0x81).expect("SC write"));
+        assert_eq!(serial(&cpu), [0x42]);
+    }
+
+    #[test]
+    fn gpu_registers_read_current() {
+        let mut cpu = cgb();
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xFF40, 0x91).expect("LCDC");
+            // Without its events the GPU is only caught up by the reads
+            bus.scheduler.cancel(super::Event::GpuMode);
+            bus.catchup(CycleTime::new(Model::CYCLE_CLOCK, 456 + 80));
+        });
+
+        assert_eq!(cpu.bus().read_byte(0xFF44).expect("LY"), 1);
+        assert_eq!(cpu.bus().read_byte(0xFF41).expect("STAT") & 0x03, 3);
+    }
+
+    #[test]
+    fn timer_and_serial_raise_interrupts() {
+        let mut cpu = cgb();
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xFF0F, 0x00).expect("IF");
+            bus.write_byte(0xFF05, 0xFF).expect("TIMA");
+            bus.write_byte(0xFF07, 0x05).expect("TAC");
+            bus.write_byte(0xFF02, 0x81).expect("SC");
+        });
+
+        // The timer overflows after 16 cycles, a byte is sent after 4096
+        let mut cycles = 0;
+        while cycles < 4096 {
+            let interrupts = cpu.bus().read_byte(0xFF0F).expect("IF");
+            assert_eq!(interrupts & 0x0C, if cycles < 16 { 0x00 } else { 0x04 });
+            cycles += cpu.step().expect("step");
+        }
+        assert_eq!(cpu.bus().read_byte(0xFF0F).expect("IF") & 0x0C, 0x0C);
+        assert_eq!(cpu.bus().read_byte(0xFF02).expect("SC"), 0x7F);
+
+        // In double speed mode a byte takes as many CPU cycles
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xFF4D, 0x01).expect("KEY1");
+            assert!(bus.switch_speed());
+            bus.write_byte(0xFF02, 0x81).expect("SC");
+        });
+        let mut cycles = 0;
+        while cycles < 4096 {
+            cycles += cpu.step().expect("step");
         }
+        assert_eq!(cpu.bus().read_byte(0xFF02).expect("SC"), 0x7F);
     }
 }
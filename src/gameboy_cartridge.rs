@@ -0,0 +1,115 @@
+use crate::addressable::{AddressError, Addressable};
+use crate::cartridge;
+use crate::inspect::Inspect;
+use crate::model::Model;
+
+/// Header location of the upper-case ASCII game title
+const TITLE_START: usize = 0x0134;
+const TITLE_END: usize = 0x0143;
+/// 0x80 marks CGB enhanced and 0xC0 CGB only software
+const CGB_FLAG: usize = 0x0143;
+/// 0x03 marks SGB support, only honored with the new licensee code
+const SGB_FLAG: usize = 0x0146;
+/// Memory bank controller and extra hardware on the cartridge
+const CARTRIDGE_TYPE: usize = 0x0147;
+const OLD_LICENSEE: usize = 0x014B;
+const NEW_LICENSEE: u8 = 0x33;
+
+// Gameboy cartridge without a memory bank controller, ROM is mapped from 0x0000
+#[derive(Debug)]
+pub struct Cartridge {
+    rom: Vec<u8>,
+}
+
+impl Cartridge {
+    /// Picks the hardware the software was made for from the header, CGB
+    /// support wins over SGB support
+    pub fn model(&self) -> Model {
+        let header = |addr: usize| self.rom.get(addr).copied().unwrap_or(0);
+
+        if header(CGB_FLAG) & 0x80 != 0 {
+            Model::CGB
+        } else if header(SGB_FLAG) == 0x03 && header(OLD_LICENSEE) == NEW_LICENSEE {
+            Model::SGB
+        } else {
+            Model::DMG
+        }
+    }
+
+    /// The header's cartridge type if it asks for a memory bank controller,
+    /// which isn't emulated. ROM only cartridges, with or without RAM, need
+    /// none.
+    pub fn bank_controller(&self) -> Option<u8> {
+        match self.rom.get(CARTRIDGE_TYPE).copied().unwrap_or(0) {
+            0x00 | 0x08 | 0x09 => None,
+            kind => Some(kind),
+        }
+    }
+}
+
+impl Addressable for Cartridge {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        if addr > 0x7FFF {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        self.rom
+            .get(addr as usize)
+            .copied()
+            .ok_or(AddressError::OutOfBounds(addr))
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        _data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        if addr > 0x7FFF {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        // ROM is read-only; writes would target a bank controller we lack
+        Ok(())
+    }
+}
+
+impl Inspect for Cartridge {
+    /// Patches ROM, e.g. for software breakpoints
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        if addr > 0x7FFF {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        let byte = self
+            .rom
+            .get_mut(addr as usize)
+            .ok_or(AddressError::OutOfBounds(addr))?;
+        *byte = data;
+
+        Ok(())
+    }
+}
+
+impl cartridge::Cartridge for Cartridge {
+    fn create(rom: Vec<Self::Data>) -> Self {
+        Cartridge { rom }
+    }
+
+    fn title(&self) -> String {
+        let end = TITLE_END.min(self.rom.len());
+        let start = TITLE_START.min(end);
+
+        self.rom[start..end]
+            .iter()
+            .take_while(|&&b| b.is_ascii_graphic() || b == b' ')
+            .map(|&b| b as char)
+            .collect()
+    }
+}
@@ -0,0 +1,510 @@
+use crate::addressable::{AddressError, Addressable};
+use crate::cartridge::Cartridge;
+use crate::gameboy_cpu::CPU;
+use crate::inspect::Inspect;
+
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Game Genie codes can only patch ROM
+const ROM_END: u16 = 0x7FFF;
+/// CGB WRAM bank select and the window it switches
+const SVBK: u16 = 0xFF70;
+const WRAM_BANK_START: u16 = 0xD000;
+const WRAM_BANK_END: u16 = 0xDFFF;
+
+/// Parses hex digits into nibbles
+fn nibbles(code: &str) -> Result<Vec<u8>, String> {
+    code.chars()
+        .map(|c| {
+            c.to_digit(16)
+                .map(|n| n as u8)
+                .ok_or_else(|| format!("bad digit {c} in {code}"))
+        })
+        .collect()
+}
+
+/// Game Genie code, `ABC-DEF` or `ABC-DEF-GHI`. AB is the new data and
+/// FCDE the address with F inverted. GI is the compare value XORed with
+/// 0xBA and rotated left by two, H isn't used.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameGenie {
+    pub address: u16,
+    pub value: u8,
+    /// The patch only applies while ROM holds this, it tells banks apart
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    /// Data read from `addr` with the patch applied
+    pub fn patch(&self, addr: u16, data: u8) -> u8 {
+        if addr == self.address && self.compare.is_none_or(|compare| compare == data) {
+            self.value
+        } else {
+            data
+        }
+    }
+}
+
+impl FromStr for GameGenie {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = nibbles(&s.replace('-', ""))?;
+        if !matches!(digits.len(), 6 | 9) {
+            return Err(format!("Game Genie codes have 6 or 9 digits, not {s}"));
+        }
+
+        let address = ((digits[5] ^ 0xF) as u16) << 12
+            | (digits[2] as u16) << 8
+            | (digits[3] as u16) << 4
+            | digits[4] as u16;
+        if address > ROM_END {
+            return Err(format!("{s} patches {address:#06X} outside of ROM"));
+        }
+
+        let compare =
+            (digits.len() == 9).then(|| (digits[6] << 4 | digits[8]).rotate_right(2) ^ 0xBA);
+
+        Ok(GameGenie {
+            address,
+            value: digits[0] << 4 | digits[1],
+            compare,
+        })
+    }
+}
+
+impl fmt::Display for GameGenie {
+    /// The unused eighth digit is written as the seventh XOR 8
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let address = self.address;
+        write!(
+            f,
+            "{:02X}{:X}-{:02X}{:X}",
+            self.value,
+            address >> 8 & 0xF,
+            address & 0xFF,
+            address >> 12 ^ 0xF
+        )?;
+
+        if let Some(compare) = self.compare {
+            let encoded = (compare ^ 0xBA).rotate_left(2);
+            let g = encoded >> 4;
+            write!(f, "-{:X}{:X}{:X}", g, g ^ 8, encoded & 0xF)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// GameShark code `TTVVLLHH`, writing VV to address HHLL every frame. Types
+/// 0x80-0x87 and 0x90-0x97 select the CGB WRAM bank in their low bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GameShark {
+    pub kind: u8,
+    pub value: u8,
+    pub address: u16,
+}
+
+impl GameShark {
+    /// WRAM bank to write 0xD000-0xDFFF in
+    pub fn bank(&self) -> Option<u8> {
+        (self.kind & 0xE8 == 0x80).then_some(self.kind & 0x07)
+    }
+}
+
+impl FromStr for GameShark {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits = nibbles(s)?;
+        if digits.len() != 8 {
+            return Err(format!("GameShark codes have 8 digits, not {s}"));
+        }
+
+        let byte = |i: usize| digits[i] << 4 | digits[i + 1];
+        Ok(GameShark {
+            kind: byte(0),
+            value: byte(2),
+            address: u16::from_le_bytes([byte(4), byte(6)]),
+        })
+    }
+}
+
+impl fmt::Display for GameShark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [low, high] = self.address.to_le_bytes();
+        write!(f, "{:02X}{:02X}{low:02X}{high:02X}", self.kind, self.value)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CheatCode {
+    GameGenie(GameGenie),
+    GameShark(GameShark),
+}
+
+impl FromStr for CheatCode {
+    type Err = String;
+
+    /// GameShark codes are the ones with 8 digits and no dashes
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() == 8 && !s.contains('-') {
+            s.parse().map(CheatCode::GameShark)
+        } else {
+            s.parse().map(CheatCode::GameGenie)
+        }
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheatCode::GameGenie(code) => code.fmt(f),
+            CheatCode::GameShark(code) => code.fmt(f),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: CheatCode,
+    pub name: String,
+    pub enabled: bool,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct CheatError {
+    pub line: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.reason)
+    }
+}
+
+impl Error for CheatError {}
+
+// Cheat list of a game. Game Genie codes are handed to the bus, which
+// patches ROM reads with them, and GameShark codes are written to RAM once
+// a frame.
+#[derive(Debug, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+    /// Game Genie codes changed since they were last installed
+    dirty: bool,
+}
+
+impl Cheats {
+    pub fn create() -> Self {
+        Cheats::default()
+    }
+
+    /// Cheat file kept next to a ROM
+    pub fn path_for(rom: &Path) -> PathBuf {
+        rom.with_extension("cht")
+    }
+
+    /// Reads a cheat file, a missing one is an empty list
+    pub fn load(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(Cheats::parse(&text)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Cheats::create()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Parses a code and an optional name per line. Lines starting with `!`
+    /// are disabled cheats and `#` starts a comment.
+    pub fn parse(text: &str) -> Result<Self, CheatError> {
+        let mut cheats = Cheats::create();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (enabled, line) = match line.strip_prefix('!') {
+                Some(line) => (false, line.trim_start()),
+                None => (true, line),
+            };
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let code = code.parse().map_err(|reason| CheatError {
+                line: number + 1,
+                reason,
+            })?;
+
+            cheats.add(Cheat {
+                code,
+                name: name.trim().to_string(),
+                enabled,
+            });
+        }
+
+        Ok(cheats)
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn add(&mut self, cheat: Cheat) {
+        self.dirty |= matches!(cheat.code, CheatCode::GameGenie(_));
+        self.cheats.push(cheat);
+    }
+
+    /// Turns a cheat on or off, returns false if there is no such cheat
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        let Some(cheat) = self.cheats.get_mut(index) else {
+            return false;
+        };
+
+        self.dirty |= matches!(cheat.code, CheatCode::GameGenie(_));
+        cheat.enabled = enabled;
+        true
+    }
+
+    /// Flips a cheat, returns whether it is on now
+    pub fn toggle(&mut self, index: usize) -> Option<bool> {
+        let enabled = !self.cheats.get(index)?.enabled;
+        self.set_enabled(index, enabled);
+        Some(enabled)
+    }
+
+    /// Hands the enabled Game Genie codes to the bus, needed again for a
+    /// new machine
+    pub fn install(&mut self, cpu: &mut CPU) {
+        let codes: Vec<GameGenie> = self
+            .cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .filter_map(|cheat| match cheat.code {
+                CheatCode::GameGenie(code) => Some(code),
+                CheatCode::GameShark(_) => None,
+            })
+            .collect();
+
+        // Cached code may have been decoded from patched reads
+        cpu.bus_apply(|bus| bus.set_game_genie(codes.clone()));
+        self.dirty = false;
+    }
+
+    /// Runs once a frame, writes the GameShark codes and installs Game Genie
+    /// codes that changed
+    pub fn apply(&mut self, cpu: &mut CPU) {
+        if self.dirty {
+            self.install(cpu);
+        }
+
+        let cgb = cpu.model().is_cgb();
+        for cheat in self.cheats.iter().filter(|cheat| cheat.enabled) {
+            let CheatCode::GameShark(code) = cheat.code else {
+                continue;
+            };
+
+            let banked = (WRAM_BANK_START..=WRAM_BANK_END).contains(&code.address);
+            match code.bank() {
+                Some(bank) if cgb && banked => {
+                    let selected = cpu.bus().read_byte(SVBK).unwrap_or(0);
+                    let _ = cpu.write_byte(SVBK, bank);
+                    let _ = cpu.write_byte(code.address, code.value);
+                    let _ = cpu.write_byte(SVBK, selected);
+                }
+                _ => {
+                    let _ = cpu.write_byte(code.address, code.value);
+                }
+            }
+        }
+    }
+}
+
+// Cartridge as the bus sees it through a Game Genie, reads of patched
+// addresses return the new data
+#[derive(Debug)]
+pub struct PatchedCartridge<C: ?Sized = dyn Cartridge<Addr = u16, Data = u8>> {
+    cartridge: Box<C>,
+    codes: Vec<GameGenie>,
+}
+
+impl<C: ?Sized + Cartridge<Addr = u16, Data = u8>> PatchedCartridge<C> {
+    pub fn create(cartridge: Box<C>) -> Self {
+        PatchedCartridge {
+            cartridge,
+            codes: Vec::new(),
+        }
+    }
+
+    pub fn cartridge(&self) -> &C {
+        &self.cartridge
+    }
+
+    pub fn set_codes(&mut self, codes: Vec<GameGenie>) {
+        self.codes = codes;
+    }
+}
+
+impl<C: ?Sized + Cartridge<Addr = u16, Data = u8>> Addressable for PatchedCartridge<C> {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        let data = self.cartridge.read_byte(addr)?;
+        Ok(self
+            .codes
+            .iter()
+            .fold(data, |data, code| code.patch(addr, data)))
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        self.cartridge.write_byte(addr, data)
+    }
+}
+
+/// Inspecting shows the codes applied, patches go to the ROM underneath
+impl<C: ?Sized + Cartridge<Addr = u16, Data = u8>> Inspect for PatchedCartridge<C> {
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        self.cartridge.patch(addr, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn game_genie_codes() {
+        // Super Mario Land, infinite lives
+        let code: gameboy::GameGenie = "00A-17B-C49".parse().expect("code");
+        assert_eq!(
+            code,
+            gameboy::GameGenie {
+                address: 0x4A17,
+                value: 0x00,
+                compare: Some(0xC8),
+            }
+        );
+        assert_eq!(code.to_string(), "00A-17B-C49");
+        // Only while the compare value is there, other banks read through
+        assert_eq!(code.patch(0x4A17, 0xC8), 0x00);
+        assert_eq!(code.patch(0x4A17, 0x3D), 0x3D);
+
+        let code: gameboy::GameGenie = "3E1-50F".parse().expect("code");
+        assert_eq!(
+            (code.address, code.value, code.compare),
+            (0x0150, 0x3E, None)
+        );
+        assert_eq!(code.to_string(), "3E1-50F");
+
+        // Lower case and missing dashes are fine, RAM addresses are not
+        assert_eq!("00a17bc49".parse(), Ok(code_c8()));
+        assert!("001-507".parse::<gameboy::GameGenie>().is_err());
+        assert!("00A-17B-C4".parse::<gameboy::GameGenie>().is_err());
+        assert!("00A-17B-C4X".parse::<gameboy::GameGenie>().is_err());
+    }
+
+    fn code_c8() -> gameboy::GameGenie {
+        "00A-17B-C49".parse().expect("code")
+    }
+
+    #[test]
+    fn game_shark_codes() {
+        // Pokémon Red and Blue, walk through walls
+        let code: gameboy::GameShark = "010138CD".parse().expect("code");
+        assert_eq!(
+            code,
+            gameboy::GameShark {
+                kind: 0x01,
+                value: 0x01,
+                address: 0xCD38,
+            }
+        );
+        assert_eq!(code.to_string(), "010138CD");
+        assert_eq!(code.bank(), None);
+
+        // Pokémon Red and Blue, 999999 money in BCD at 0xD347
+        let money: Vec<gameboy::GameShark> = ["019947D3", "019948D3", "019949D3"]
+            .iter()
+            .map(|code| code.parse().expect("code"))
+            .collect();
+        assert_eq!(
+            money
+                .iter()
+                .map(|code| (code.address, code.value))
+                .collect::<Vec<_>>(),
+            [(0xD347, 0x99), (0xD348, 0x99), (0xD349, 0x99)]
+        );
+
+        let banked: gameboy::GameShark = "91FF10D0".parse().expect("code");
+        assert_eq!((banked.address, banked.bank()), (0xD010, Some(1)));
+        assert!("010238C".parse::<gameboy::GameShark>().is_err());
+    }
+
+    #[test]
+    fn cheat_file() {
+        let cheats = gameboy::Cheats::parse(
+            "# Lives\n00A-17B-C49 Infinite lives\n\n!010138CD  Walk through walls\n",
+        )
+        .expect("parse");
+        let list = cheats.cheats();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].code, gameboy::CheatCode::GameGenie(code_c8()));
+        assert_eq!(
+            (list[0].name.as_str(), list[0].enabled),
+            ("Infinite lives", true)
+        );
+        assert!(matches!(list[1].code, gameboy::CheatCode::GameShark(_)));
+        assert_eq!(
+            (list[1].name.as_str(), list[1].enabled),
+            ("Walk through walls", false)
+        );
+
+        let err = gameboy::Cheats::parse("010138CD\nnonsense").expect_err("bad code");
+        assert_eq!(err.line, 2);
+    }
+
+    #[test]
+    fn patches_rom_and_writes_ram() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0150] = 0x11;
+        rom[0x0151] = 0x22;
+        // CGB software, for banked WRAM
+        rom[0x0143] = 0x80;
+        let mut cpu = gameboy::create_system(Model::CGB, gameboy::Cartridge::create(rom));
+
+        let mut cheats = gameboy::Cheats::parse(
+            "011-50F-A2E compare matches\n021-51F-A2E compare differs\n\
+             019947D3 plain write\n92BB10D0 bank 2",
+        )
+        .expect("parse");
+        cheats.apply(&mut cpu);
+
+        let read = |cpu: &gameboy::CPU, addr| cpu.bus().read_byte(addr).expect("read");
+        assert_eq!(read(&cpu, 0x0150), 0x01);
+        assert_eq!(read(&cpu, 0x0151), 0x22);
+        assert_eq!(read(&cpu, 0xD347), 0x99);
+        // Bank 1 stays selected, the value went to bank 2
+        assert_eq!(read(&cpu, 0xD010), 0x00);
+        cpu.bus_apply(|bus| bus.write_byte(0xFF70, 0x02).expect("SVBK"));
+        assert_eq!(read(&cpu, 0xD010), 0xBB);
+
+        assert_eq!(cheats.toggle(0), Some(false));
+        assert_eq!(cheats.toggle(9), None);
+        cheats.apply(&mut cpu);
+        assert_eq!(read(&cpu, 0x0150), 0x11);
+    }
+}
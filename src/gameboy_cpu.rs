@@ -6,9 +6,15 @@ use crate::bus::Bus;
 use crate::cpu;
 use crate::cpu::CPUError;
 use crate::cpu::Word;
+use crate::gameboy_block::*;
+use crate::gameboy_bus;
 use crate::gameboy_cpu_inst::*;
+use crate::gameboy_gpu::CGB_COMPAT_PALETTE;
+use crate::model::Model;
 use crate::timed::*;
 
+use std::rc::Rc;
+use std::str::FromStr;
 use std::{cmp, fmt, ops};
 
 use either::*;
@@ -68,6 +74,39 @@ impl fmt::Display for Reg {
     }
 }
 
+impl Reg {
+    pub const ALL: [Reg; 14] = [
+        Reg::A,
+        Reg::B,
+        Reg::C,
+        Reg::D,
+        Reg::E,
+        Reg::F,
+        Reg::H,
+        Reg::L,
+        Reg::AF,
+        Reg::BC,
+        Reg::DE,
+        Reg::HL,
+        Reg::PC,
+        Reg::SP,
+    ];
+}
+
+impl FromStr for Reg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Reg::ALL
+            .into_iter()
+            .find(|reg| reg.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown register {s}"))
+    }
+}
+
+/// T-cycles in an M-cycle, the time a memory access takes
+const MCYCLE: u32 = 4;
+
 /// CPU Flag register flags
 enum Flag {
     /// Z (Zero) is set if the result of an operation is 0
@@ -88,22 +127,25 @@ impl Flag {
     const fn bit(self) -> u8 {
         match self {
             Self::Z => 7,
-            Self::C => 6,
-            Self::N => 5,
-            Self::H => 4,
+            Self::N => 6,
+            Self::H => 5,
+            Self::C => 4,
         }
     }
 
+    #[allow(dead_code)]
     const fn mask(self) -> u8 {
         1 << self.bit()
     }
 }
 
-/// GameBoy CPU
+/// GameBoy CPU, generic over its bus so memory accesses dispatch statically
+/// when the bus type is known. The default is the Game Boy bus with its
+/// devices behind trait objects.
 #[derive(Debug)]
-pub struct CPU {
+pub struct CPU<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static = gameboy_bus::Bus> {
     /// CPU bus
-    bus: Box<dyn Bus<Addr = u16, Data = u8>>,
+    bus: Box<B>,
     /// MSB = A, LSB = Flags
     AF: Word,
     /// MSB = B, LSB = C
@@ -116,32 +158,178 @@ pub struct CPU {
     SP: Word,
     /// Program counter
     PC: Word,
-    /// CPU clock speed in Hz
+    /// Emulated hardware, decides the clock and boot state
+    model: Model,
+    /// Clock speed in Hz cycles are counted in, the same on every model
     clock: u32,
+    /// CGB double speed mode doubles the clock
+    double_speed: bool,
+    /// Cycles of the current instruction the bus already caught up on
+    ticked: u32,
+    /// Predecoded code, None runs the plain interpreter
+    blocks: Option<BlockCache<B>>,
+    /// Block being run and the index of its next instruction
+    cursor: Option<(Rc<Block<B>>, usize)>,
 }
 
 fn check_overflow<T>(dst: T, src: T, overflow_mask: T) -> bool
 where
     T: cmp::PartialOrd + ops::Sub<Output = T>,
 {
-    dst <= overflow_mask && src >= overflow_mask
-        || dst <= overflow_mask && dst > overflow_mask - src
+    dst <= overflow_mask && (src >= overflow_mask || dst > overflow_mask - src)
 }
 
 impl CPU {
+    pub fn create(model: Model, bus: Box<gameboy_bus::Bus>) -> Self {
+        CPU::with_bus(model, bus)
+    }
+}
+
+impl From<(Model, Box<dyn Bus<Addr = u16, Data = u8>>)> for CPU<dyn Bus<Addr = u16, Data = u8>> {
+    fn from((model, bus): (Model, Box<dyn Bus<Addr = u16, Data = u8>>)) -> Self {
+        CPU::with_bus(model, bus)
+    }
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> CPU<B> {
     // According to http://marc.rawer.de/Gameboy/Docs/GBCPUman.pdf
     const U4MAX: u8 = 0xF; // 4-bit overflow limit (8-bit Half-Carry)
     const U8MAX: u8 = u8::MAX; // 8-bit overflow limit (8-bit Carry)
     const U12MAX: u16 = 0x0FFF; // 12-bit overflow limit (16-bit Half-Carry)
     const U16MAX: u16 = u16::MAX; // 16-bit overflow limit (16-bit Carry)
 
-    pub fn bus_apply<FUN>(&mut self, f: FUN)
+    /// Like `create`, keeping the bus type
+    pub fn with_bus(model: Model, bus: Box<B>) -> Self {
+        CPU {
+            bus,
+            AF: Word::default(),
+            BC: Word::default(),
+            DE: Word::default(),
+            HL: Word::default(),
+            SP: Word::default(),
+            PC: Word::default(),
+            model,
+            clock: Model::CYCLE_CLOCK,
+            double_speed: false,
+            ticked: 0,
+            blocks: None,
+            cursor: None,
+        }
+    }
+
+    pub fn bus_apply<FUN>(&mut self, mut f: FUN)
     where
-        FUN: Fn(&mut dyn Bus<Addr = <CPU as cpu::CPU>::Addr, Data = <CPU as cpu::CPU>::Data>),
+        FUN: FnMut(&mut B),
     {
+        // Whatever gets written may be cached code
+        if let Some(blocks) = &mut self.blocks {
+            blocks.flush();
+        }
+        self.cursor = None;
+
         f(&mut *self.bus)
     }
 
+    /// Immutable access to the CPU bus, e.g. for tracing and debugging
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// Mutable access to the bus for calls that leave memory alone, e.g.
+    /// setting buttons, so predecoded code is kept. Memory is written with
+    /// `write_byte` or `bus_apply`.
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    /// Writes memory from outside an instruction, only dropping cached code
+    /// decoded from `addr`
+    pub fn write_byte(&mut self, addr: u16, data: u8) -> Result<(), AddressError<u16>> {
+        self.invalidate(addr);
+        self.bus.write_byte(addr, data)
+    }
+
+    /// Drops cached code decoded from `addr`, for writes that went through
+    /// the bus directly
+    pub fn invalidate(&mut self, addr: u16) {
+        if let Some(blocks) = &mut self.blocks {
+            blocks.write(addr);
+        }
+        self.cursor = None;
+    }
+
+    /// Reads any register, 8-bit registers are zero-extended
+    pub fn register(&self, reg: Reg) -> u16 {
+        self.get_reg_value(reg).into_word()
+    }
+
+    /// Writes any register, 8-bit registers only take the low byte of `val`
+    pub fn set_register(&mut self, reg: Reg, val: u16) -> Result<(), CPUError<Self>> {
+        match self.get_reg_value(reg) {
+            Either::Left(_) => self.set_reg_byte(reg, val as u8),
+            Either::Right(_) => self.set_reg_word(reg, val),
+        }
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Puts the registers and LCD in the state the model's boot ROM leaves
+    /// them in when handing over to the cartridge at 0x0100
+    pub fn boot(&mut self) {
+        // Bit 7 of the header's CGB flag marks CGB software
+        let cgb_software = self
+            .bus
+            .read_byte(0x0143)
+            .is_ok_and(|flag| flag & 0x80 != 0);
+
+        let (af, bc, de, hl): (u16, u16, u16, u16) = match (self.model, cgb_software) {
+            (Model::DMG, _) => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            (Model::MGB, _) => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            (Model::SGB, _) => (0x0100, 0x0014, 0x0000, 0xC060),
+            (Model::CGB, true) => (0x1180, 0x0000, 0xFF56, 0x000D),
+            (Model::CGB, false) => (0x1180, 0x0000, 0x0008, 0x007C),
+            // The AGB boot ROM ends in an extra INC B
+            (Model::AGB, true) => (0x1100, 0x0100, 0xFF56, 0x000D),
+            (Model::AGB, false) => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
+
+        self.AF = Word::from(af);
+        self.BC = Word::from(bc);
+        self.DE = Word::from(de);
+        self.HL = Word::from(hl);
+        self.SP = Word::from(0xFFFEu16);
+        self.PC = Word::from(0x0100u16);
+
+        if self.model.is_cgb() && !cgb_software {
+            self.enter_compat_mode();
+        }
+
+        // The boot ROM leaves the LCD and background on, BGP shading color 0 white
+        let _ = self.bus.write_byte(0xFF40, 0x91);
+        let _ = self.bus.write_byte(0xFF47, 0xFC);
+        // Unmapping the boot ROM locks KEY0
+        let _ = self.bus.write_byte(0xFF50, 0x01);
+    }
+
+    /// Loads the compatibility palettes for DMG software and locks the CGB
+    /// features away, as the CGB boot ROM does
+    fn enter_compat_mode(&mut self) {
+        // Background palette 0 and sprite palettes 0 and 1, auto-incrementing
+        for (index, data, palettes) in [(0xFF68, 0xFF69, 1), (0xFF6A, 0xFF6B, 2)] {
+            let _ = self.bus.write_byte(index, 0x80);
+            for _ in 0..palettes {
+                for byte in CGB_COMPAT_PALETTE.iter().flat_map(|c| c.to_le_bytes()) {
+                    let _ = self.bus.write_byte(data, byte);
+                }
+            }
+        }
+
+        // KEY0 selects DMG mode
+        let _ = self.bus.write_byte(0xFF4C, 0x04);
+    }
+
     fn get_reg_byte(&self, reg: Reg) -> Result<u8, CPUError<Self>> {
         match reg {
             Reg::A => Ok(self.AF.get_high()),
@@ -162,7 +350,7 @@ impl CPU {
         match reg {
             Reg::AF => Ok(self.AF.into()),
             Reg::BC => Ok(self.BC.into()),
-            Reg::DE => Ok(self.BC.into()),
+            Reg::DE => Ok(self.DE.into()),
             Reg::HL => Ok(self.HL.into()),
             Reg::SP => Ok(self.SP.into()),
             Reg::PC => Ok(self.PC.into()),
@@ -174,32 +362,70 @@ impl CPU {
 
     fn set_reg_byte(&mut self, reg: Reg, val: u8) -> Result<(), CPUError<Self>> {
         match reg {
-            Reg::A => Ok(self.AF.set_high(val)),
-            Reg::B => Ok(self.BC.set_high(val)),
-            Reg::C => Ok(self.BC.set_low(val)),
-            Reg::D => Ok(self.DE.set_high(val)),
-            Reg::E => Ok(self.DE.set_low(val)),
-            Reg::F => Ok(self.AF.set_low(val)),
-            Reg::H => Ok(self.HL.set_high(val)),
-            Reg::L => Ok(self.HL.set_low(val)),
-            _ => Err(CPUError::BadRegisterAccess(
-                "Mismatching register {reg} and value {val} width",
-            )),
+            Reg::A => self.AF.set_high(val),
+            Reg::B => self.BC.set_high(val),
+            Reg::C => self.BC.set_low(val),
+            Reg::D => self.DE.set_high(val),
+            Reg::E => self.DE.set_low(val),
+            Reg::F => self.AF.set_low(val),
+            Reg::H => self.HL.set_high(val),
+            Reg::L => self.HL.set_low(val),
+            _ => {
+                return Err(CPUError::BadRegisterAccess(
+                    "Mismatching register {reg} and value {val} width",
+                ))
+            }
         }
+
+        Ok(())
     }
 
     fn set_reg_word(&mut self, reg: Reg, val: u16) -> Result<(), CPUError<Self>> {
         match reg {
-            Reg::AF => Ok(self.AF = val.into()),
-            Reg::BC => Ok(self.BC = val.into()),
-            Reg::DE => Ok(self.BC = val.into()),
-            Reg::HL => Ok(self.HL = val.into()),
-            Reg::SP => Ok(self.SP = val.into()),
-            Reg::PC => Ok(self.PC = val.into()),
-            _ => Err(CPUError::BadRegisterAccess(
-                "Mismatching register {reg} and value {val} width",
-            )),
+            Reg::AF => self.AF = val.into(),
+            Reg::BC => self.BC = val.into(),
+            Reg::DE => self.DE = val.into(),
+            Reg::HL => self.HL = val.into(),
+            Reg::SP => self.SP = val.into(),
+            Reg::PC => self.PC = val.into(),
+            _ => {
+                return Err(CPUError::BadRegisterAccess(
+                    "Mismatching register {reg} and value {val} width",
+                ))
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Lets the rest of the system run for one M-cycle
+    fn tick(&mut self) {
+        self.bus
+            .catchup(CycleTime::new(cpu::CPU::frequency(self), MCYCLE as u64));
+        self.ticked += MCYCLE;
+    }
+
+    /// Reads at the end of the M-cycle the access takes, so devices are
+    /// seen as they are at that point within the instruction
+    fn read_cycle(&mut self, addr: u16) -> Result<u8, CPUError<Self>> {
+        self.tick();
+        Ok(self.bus.read_byte(addr)?)
+    }
+
+    /// Writes at the end of the M-cycle the access takes
+    fn write_cycle(&mut self, addr: u16, data: u8) -> Result<(), CPUError<Self>> {
+        self.tick();
+        if let Some(blocks) = &mut self.blocks {
+            blocks.write(addr);
         }
+        Ok(self.bus.write_byte(addr, data)?)
+    }
+
+    /// Reads a little-endian word over two M-cycles
+    fn read_word_cycles(&mut self, addr: u16) -> Result<u16, CPUError<Self>> {
+        let low = self.read_cycle(addr)?;
+        let high = self.read_cycle(addr.wrapping_add(1))?;
+        Ok(u16::from_le_bytes([low, high]))
     }
 
     /// Retrieve either a Byte or a Word from a Reg
@@ -221,42 +447,47 @@ impl CPU {
         }
     }
 
+    /// Resolve a dereferencing operand to the address it points at,
+    /// reading any immediate bytes it needs
+    fn operand_to_addr(&mut self, oper: Operand, instr_pc: Word) -> Result<u16, CPUError<Self>> {
+        // NOTE: Byte registers and Imm8 only hold the lower byte of an
+        // address in 0xFF00-0xFFFF
+        let fixed_offset: u16 = 0xFF00;
+        let imm_addr = u16::from(instr_pc + Word::from(1u8));
+
+        match oper {
+            Operand::DerefReg(r) => Ok(match self.get_reg_value(r) {
+                Either::Left(byte) => fixed_offset | byte as u16,
+                Either::Right(word) => word,
+            }),
+            Operand::DerefImm8 => Ok(fixed_offset | self.read_cycle(imm_addr)? as u16),
+            Operand::DerefImm16 => self.read_word_cycles(imm_addr),
+            _ => Err(CPUError::BadRegisterAccess(
+                "Failed to retrieve address from operand",
+            )),
+        }
+    }
+
     /// Resolve operand to either a Byte or a Word given the current PC
     /// PC is needed if the instruction spans several bytes
     fn operand_to_value(
-        &self,
+        &mut self,
         oper: Operand,
         instr_pc: Word,
     ) -> Result<Either<u8, u16>, CPUError<Self>> {
         match oper {
             Operand::Value(r) => Ok(self.get_reg_value(r)),
-            Operand::DerefReg(r) => {
-                let addr = match self.get_reg_value(r) {
-                    Either::Left(byte) => byte as u16,
-                    Either::Right(word) => word,
-                };
-
-                Ok(Either::Left(self.bus.read_byte(addr)?))
+            Operand::DerefReg(_) | Operand::DerefImm8 | Operand::DerefImm16 => {
+                let addr = self.operand_to_addr(oper, instr_pc)?;
+                Ok(Either::Left(self.read_cycle(addr)?))
             }
             Operand::Imm8 => {
                 let imm_addr = u16::from(instr_pc + Word::from(1u8));
-                Ok(Either::Left(self.bus.read_byte(imm_addr)?))
-            }
-            Operand::DerefImm8 => {
-                // NOTE: DerefImm8 is a special case of an Imm8 that is always
-                // offset by 0xFF00, i.e. the Imm8 will take the place of the
-                // lower byte of 0xFF00.
-                let fixed_offset: u16 = 0xFF00;
-                let derefimm8_addr = u16::from(instr_pc + Word::from(1u8));
-                let derefimm8 = self.bus.read_byte(derefimm8_addr)? as u16;
-                Ok(Either::Left(self.bus.read_byte(fixed_offset | derefimm8)?))
+                Ok(Either::Left(self.read_cycle(imm_addr)?))
             }
             Operand::Imm16 => {
-                let imm_addr_upper = u16::from(instr_pc + Word::from(1u8));
-                let imm_addr_lower = u16::from(instr_pc + Word::from(2u8));
-                let upper_byte = self.bus.read_byte(imm_addr_upper)? as u16;
-                let lower_byte = self.bus.read_byte(imm_addr_lower)? as u16;
-                Ok(Either::Right(upper_byte << 8 | lower_byte))
+                let imm_addr = u16::from(instr_pc + Word::from(1u8));
+                Ok(Either::Right(self.read_word_cycles(imm_addr)?))
             }
             _ => Err(CPUError::BadRegisterAccess(
                 "Failed to retrieve value from operand register: {r}",
@@ -265,7 +496,7 @@ impl CPU {
     }
 
     /// Expect operand to resolve to a Byte
-    fn operand_to_byte(&self, oper: Operand, instr_pc: Word) -> Result<u8, CPUError<Self>> {
+    fn operand_to_byte(&mut self, oper: Operand, instr_pc: Word) -> Result<u8, CPUError<Self>> {
         self.operand_to_value(oper, instr_pc)?
             .left()
             .ok_or(CPUError::AddrErr(AddressError::IllegalInstr(
@@ -274,35 +505,242 @@ impl CPU {
     }
 
     /// Expect operand to resolve to a Word
-    fn operand_to_word(&self, oper: Operand, instr_pc: Word) -> Result<u16, CPUError<Self>> {
+    fn operand_to_word(&mut self, oper: Operand, instr_pc: Word) -> Result<u16, CPUError<Self>> {
         self.operand_to_value(oper, instr_pc)?
             .right()
             .ok_or(CPUError::AddrErr(AddressError::IllegalInstr(
                 self.PC.into(),
             )))
     }
-}
 
-impl cpu::CPU for CPU {
-    type Addr = u16;
-    type Data = u8;
+    /// ADD of `src` to `dst`, the value held by `dst_reg`. ADC adds the
+    /// carry flag in as well.
+    fn add(
+        &mut self,
+        dst_reg: Reg,
+        dst: Either<u8, u16>,
+        src: u16,
+        with_carry: bool,
+    ) -> Result<(), CPUError<Self>> {
+        let carry_val = if with_carry {
+            self.AF.is_bit_set(Flag::C.bit()) as u16
+        } else {
+            // Clear carries
+            self.AF.unset_bit(Flag::H.bit());
+            self.AF.unset_bit(Flag::C.bit());
+            0
+        };
+        // Always clear SUB flag if addition was performed
+        self.AF.unset_bit(Flag::N.bit());
+
+        match dst {
+            Either::Left(dst_byte) => {
+                let src_val = src as u8;
+
+                if check_overflow(dst_byte as u16, src + carry_val, Self::U4MAX.into()) {
+                    self.AF.set_bit(Flag::H.bit());
+                }
+                if check_overflow(dst_byte as u16, src + carry_val, Self::U8MAX.into()) {
+                    self.AF.set_bit(Flag::C.bit());
+                }
 
-    fn create(clock: u32, bus: Box<dyn Bus<Addr = u16, Data = u8>>) -> Self {
-        CPU {
-            bus: bus,
-            AF: Word::default(),
-            BC: Word::default(),
-            DE: Word::default(),
-            HL: Word::default(),
-            SP: Word::default(),
-            PC: Word::default(),
-            clock,
+                self.set_reg_byte(
+                    dst_reg,
+                    dst_byte.wrapping_add(src_val.wrapping_add(carry_val as u8)),
+                )?;
+            }
+            Either::Right(dst_word) => {
+                if check_overflow(
+                    dst_word as u32,
+                    src as u32 + carry_val as u32,
+                    Self::U12MAX.into(),
+                ) {
+                    self.AF.set_bit(Flag::H.bit());
+                }
+                if check_overflow(
+                    dst_word as u32,
+                    src as u32 + carry_val as u32,
+                    Self::U16MAX.into(),
+                ) {
+                    self.AF.set_bit(Flag::C.bit());
+                }
+
+                self.set_reg_word(dst_reg, dst_word.wrapping_add(src.wrapping_add(carry_val)))?;
+            }
+        }
+
+        // Check if result was Zero
+        if self.get_reg_value(dst_reg).into_word() == 0 {
+            self.AF.set_bit(Flag::Z.bit())
         }
+
+        Ok(())
     }
 
-    /// Executes the instruction at PC and returns cycles spent
-    fn step(&mut self) -> Result<u32, CPUError<Self>> {
-        let opcode: u8 = self.bus.read_byte(self.PC.into())?;
+    /// Turns on the cache of predecoded blocks or goes back to decoding
+    /// every instruction as it runs
+    pub fn set_block_cache(&mut self, enabled: bool) {
+        self.blocks = enabled.then(BlockCache::create);
+        self.cursor = None;
+    }
+
+    /// Number of predecoded blocks cached
+    pub fn cached_blocks(&self) -> usize {
+        self.blocks.as_ref().map_or(0, BlockCache::len)
+    }
+
+    /// Runs the instruction at PC from its predecoded block, which is
+    /// decoded first if needed
+    fn run_cached(&mut self) -> Result<u32, CPUError<Self>> {
+        let pc = u16::from(self.PC);
+        let (block, index) = match self.cursor.take() {
+            Some((block, index)) if block.pc(index) == Some(pc) => (block, index),
+            _ => match self.find_block(pc) {
+                Some(block) => (block, 0),
+                None => return self.interpret(),
+            },
+        };
+
+        let generation = self.blocks.as_ref().map(BlockCache::generation);
+        let cycles = block.op(index)(self)?;
+        // Carry on with the next instruction unless the block went stale
+        if self.blocks.as_ref().map(BlockCache::generation) == generation {
+            self.cursor = Some((block, index + 1));
+        }
+
+        Ok(cycles)
+    }
+
+    fn find_block(&mut self, pc: u16) -> Option<Rc<Block<B>>> {
+        region(pc)?;
+
+        let bank = self.bus.bank(pc);
+        if let Some(block) = self.blocks.as_ref()?.get(bank, pc) {
+            return Some(block);
+        }
+
+        let block = self.decode_block(pc);
+        if block.is_empty() {
+            return None;
+        }
+        Some(self.blocks.as_mut()?.insert(bank, block))
+    }
+
+    /// Decodes the straight-line code at `pc` without touching the bus
+    fn decode_block(&self, pc: u16) -> Block<B> {
+        let mut block = Block::create(pc);
+        let mut addr = pc;
+        let start = region(pc);
+
+        for _ in 0..MAX_BLOCK_LEN {
+            let Ok(opcode) = self.bus.inspect(addr) else {
+                break;
+            };
+            let instruction = INSTRUCTION_LOOKUP[opcode as usize];
+            let width = instruction.width as u16;
+            let last = addr.wrapping_add(width.max(1) - 1);
+            if last < addr || region(last) != start {
+                break;
+            }
+
+            let mut bytes = [opcode, 0, 0];
+            for (offset, byte) in bytes.iter_mut().enumerate().take(width as usize).skip(1) {
+                match self.bus.inspect(addr + offset as u16) {
+                    Ok(data) => *byte = data,
+                    Err(_) => return block,
+                }
+            }
+
+            match Self::compile(instruction, bytes) {
+                Some(op) => block.push(addr, width, op),
+                None => {
+                    block.push(addr, width, Box::new(Self::interpret));
+                    break;
+                }
+            }
+            addr += width;
+        }
+
+        block
+    }
+
+    /// Specializes the common instructions whose operands are all known
+    /// ahead of time, the rest are left to the interpreter
+    fn compile(instruction: Instr, bytes: [u8; 3]) -> Option<Op<B>> {
+        let cycles = instruction.cycles;
+        let width = Word::from(instruction.width);
+        let is_byte = |reg: Reg| (Reg::A as u8..=Reg::L as u8).contains(&(reg as u8));
+
+        let op: Op<B> = match (instruction.opcode, instruction.dst, instruction.src) {
+            (Opcode::NOP, _, _) => Box::new(move |cpu| {
+                cpu.tick();
+                cpu.PC += width;
+                Ok(cycles)
+            }),
+            (Opcode::LD, Operand::Value(dst), Operand::Value(src))
+                if is_byte(dst) && is_byte(src) =>
+            {
+                Box::new(move |cpu| {
+                    cpu.tick();
+                    let val = cpu.get_reg_byte(src)?;
+                    cpu.set_reg_byte(dst, val)?;
+                    cpu.PC += width;
+                    Ok(cycles)
+                })
+            }
+            (Opcode::LD, Operand::Value(dst), Operand::Imm8) if is_byte(dst) => {
+                Box::new(move |cpu| {
+                    cpu.tick();
+                    cpu.tick();
+                    cpu.set_reg_byte(dst, bytes[1])?;
+                    cpu.PC += width;
+                    Ok(cycles)
+                })
+            }
+            (opcode @ (Opcode::ADD | Opcode::ADC), Operand::Value(Reg::A), Operand::Value(src))
+                if is_byte(src) =>
+            {
+                let with_carry = matches!(opcode, Opcode::ADC);
+                Box::new(move |cpu| {
+                    cpu.tick();
+                    let dst = Either::Left(cpu.AF.get_high());
+                    let src = cpu.get_reg_byte(src)? as u16;
+                    cpu.add(Reg::A, dst, src, with_carry)?;
+                    cpu.PC += width;
+                    Ok(cycles)
+                })
+            }
+            (opcode @ (Opcode::ADD | Opcode::ADC), Operand::Value(Reg::A), Operand::Imm8) => {
+                let with_carry = matches!(opcode, Opcode::ADC);
+                Box::new(move |cpu| {
+                    cpu.tick();
+                    cpu.tick();
+                    let dst = Either::Left(cpu.AF.get_high());
+                    cpu.add(Reg::A, dst, bytes[1] as u16, with_carry)?;
+                    cpu.PC += width;
+                    Ok(cycles)
+                })
+            }
+            (Opcode::ADD, Operand::Value(Reg::HL), Operand::Value(src)) if !is_byte(src) => {
+                Box::new(move |cpu| {
+                    cpu.tick();
+                    let dst = Either::Right(u16::from(cpu.HL));
+                    let src = cpu.get_reg_word(src)?;
+                    cpu.add(Reg::HL, dst, src, false)?;
+                    cpu.PC += width;
+                    Ok(cycles)
+                })
+            }
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// Fetches, decodes and executes the instruction at PC, returns the
+    /// cycles it takes
+    fn interpret(&mut self) -> Result<u32, CPUError<Self>> {
+        let opcode: u8 = self.read_cycle(self.PC.into())?;
         let instruction: Instr = INSTRUCTION_LOOKUP[opcode as usize];
 
         match instruction.opcode {
@@ -310,7 +748,7 @@ impl cpu::CPU for CPU {
             Opcode::NOP => self.PC += Word::from(1u8),
             Opcode::LD => {
                 // First we retrieve the value we need to store
-                let src_val: Self::Data = self
+                let src_val: u8 = self
                     .operand_to_value(instruction.src, self.PC)?
                     .expect_left("LD to only fetch a single byte");
 
@@ -320,134 +758,26 @@ impl cpu::CPU for CPU {
                         self.set_reg_byte(r, src_val)?;
                     }
                     _ => {
-                        let dst_addr: Self::Addr =
-                            self.operand_to_value(instruction.dst, self.PC)?.into_word();
-                        self.bus.write_byte(dst_addr, src_val)?;
-                    }
-                }
-
-                self.PC += Word::from(instruction.width);
-            }
-            Opcode::ADD => {
-                // We expect DST to be a Reg since there is no ADD instruction
-                // with anything other than a Reg as the dst
-                let dst_reg = self
-                    .operand_to_reg(instruction.dst)
-                    .expect("ADD dst operand to be a register");
-
-                // Clear carries
-                self.AF.unset_bit(Flag::H.bit());
-                self.AF.unset_bit(Flag::C.bit());
-                // Always clear SUB flag if addition was performed
-                self.AF.unset_bit(Flag::N.bit());
-
-                match self.operand_to_value(instruction.dst, self.PC)? {
-                    Either::Left(dst_byte) => {
-                        let src_val = self.operand_to_byte(instruction.src, self.PC)?;
-
-                        if check_overflow(dst_byte, src_val, CPU::U4MAX) {
-                            self.AF.set_bit(Flag::H.bit());
-                        }
-                        if check_overflow(dst_byte, src_val, CPU::U8MAX) {
-                            self.AF.set_bit(Flag::C.bit());
-                        }
-
-                        self.set_reg_byte(dst_reg, dst_byte.wrapping_add(src_val))?;
-                    }
-                    Either::Right(dst_word) => {
-                        let src_val = self.operand_to_word(instruction.src, self.PC)?;
-
-                        if check_overflow(dst_word, src_val, CPU::U12MAX) {
-                            self.AF.set_bit(Flag::H.bit());
-                        }
-                        if check_overflow(dst_word, src_val, CPU::U16MAX) {
-                            self.AF.set_bit(Flag::C.bit());
-                        }
-
-                        self.set_reg_word(dst_reg, dst_word.wrapping_add(src_val))?;
+                        let dst_addr = self.operand_to_addr(instruction.dst, self.PC)?;
+                        self.write_cycle(dst_addr, src_val)?;
                     }
                 }
 
-                // Check if result was Zero
-                if self.get_reg_value(dst_reg).into_word() == 0 {
-                    self.AF.set_bit(Flag::Z.bit())
-                }
-
                 self.PC += Word::from(instruction.width);
             }
-            Opcode::ADC => {
+            Opcode::ADD | Opcode::ADC => {
                 // We expect DST to be a Reg since there is no ADD instruction
                 // with anything other than a Reg as the dst
                 let dst_reg = self
                     .operand_to_reg(instruction.dst)
                     .expect("ADD dst operand to be a register");
 
-                // Always clear SUB flag if addition was performed
-                self.AF.unset_bit(Flag::N.bit());
-
-                match self.operand_to_value(instruction.dst, self.PC)? {
-                    Either::Left(dst_byte) => {
-                        let src_val = self.operand_to_byte(instruction.src, self.PC)?;
-                        let carry_val = if self.AF.is_bit_set(Flag::C.bit()) {
-                            1
-                        } else {
-                            0
-                        };
-
-                        if check_overflow(
-                            dst_byte as u16,
-                            src_val as u16 + carry_val as u16,
-                            CPU::U4MAX.into(),
-                        ) {
-                            self.AF.set_bit(Flag::H.bit());
-                        }
-                        if check_overflow(
-                            dst_byte as u16,
-                            src_val as u16 + carry_val as u16,
-                            CPU::U8MAX.into(),
-                        ) {
-                            self.AF.set_bit(Flag::C.bit());
-                        }
-
-                        self.set_reg_byte(
-                            dst_reg,
-                            dst_byte.wrapping_add(src_val.wrapping_add(carry_val)),
-                        )?;
-                    }
-                    Either::Right(dst_word) => {
-                        let src_val = self.operand_to_word(instruction.src, self.PC)?;
-                        let carry_val = if self.AF.is_bit_set(Flag::C.bit()) {
-                            1
-                        } else {
-                            0
-                        };
-
-                        if check_overflow(
-                            dst_word as u32,
-                            src_val as u32 + carry_val as u32,
-                            CPU::U12MAX.into(),
-                        ) {
-                            self.AF.set_bit(Flag::H.bit());
-                        }
-                        if check_overflow(
-                            dst_word as u32,
-                            src_val as u32 + carry_val as u32,
-                            CPU::U16MAX.into(),
-                        ) {
-                            self.AF.set_bit(Flag::C.bit());
-                        }
-
-                        self.set_reg_word(
-                            dst_reg,
-                            dst_word.wrapping_add(src_val.wrapping_add(carry_val)),
-                        )?;
-                    }
-                }
-
-                // Check if result was Zero
-                if self.get_reg_value(dst_reg).into_word() == 0 {
-                    self.AF.set_bit(Flag::Z.bit())
-                }
+                let dst = self.operand_to_value(instruction.dst, self.PC)?;
+                let src = match dst {
+                    Either::Left(_) => self.operand_to_byte(instruction.src, self.PC)? as u16,
+                    Either::Right(_) => self.operand_to_word(instruction.src, self.PC)?,
+                };
+                self.add(dst_reg, dst, src, matches!(instruction.opcode, Opcode::ADC))?;
 
                 self.PC += Word::from(instruction.width);
             }
@@ -457,7 +787,15 @@ impl cpu::CPU for CPU {
             Opcode::RRA => unimplemented!(),
             Opcode::JR => unimplemented!(),
             Opcode::RRCA => unimplemented!(),
-            Opcode::STOP => unimplemented!(),
+            Opcode::STOP => {
+                // Without a pending CGB speed switch STOP waits for a button
+                // press, there is no joypad so it resumes immediately
+                if self.bus.switch_speed() {
+                    self.double_speed = !self.double_speed;
+                }
+
+                self.PC += Word::from(instruction.width);
+            }
             Opcode::RLA => unimplemented!(),
             Opcode::LDI => unimplemented!(),
             Opcode::DAA => unimplemented!(),
@@ -484,13 +822,53 @@ impl cpu::CPU for CPU {
             Opcode::DI => unimplemented!(),
             Opcode::LDHL => unimplemented!(),
             Opcode::EI => unimplemented!(),
+            // CB-prefixed operations are only reachable through PREFIX
+            Opcode::RLC
+            | Opcode::RRC
+            | Opcode::RL
+            | Opcode::RR
+            | Opcode::SLA
+            | Opcode::SRA
+            | Opcode::SWAP
+            | Opcode::SRL
+            | Opcode::BIT
+            | Opcode::RES
+            | Opcode::SET => unreachable!(),
         }
 
-        self.bus
-            .catchup(CycleTime::new(self.frequency(), instruction.cycles));
-
         Ok(instruction.cycles)
     }
+}
+
+impl<B: ?Sized + Bus<Addr = u16, Data = u8> + 'static> cpu::CPU for CPU<B> {
+    type Addr = u16;
+    type Data = u8;
+
+    /// Executes the instruction at PC and returns cycles spent. The bus
+    /// ticks along with every memory access, and catches up on the
+    /// instruction's internal cycles at the end.
+    fn step(&mut self) -> Result<u32, CPUError<Self>> {
+        self.ticked = 0;
+        let cycles = match self.blocks {
+            Some(_) => self.run_cached()?,
+            None => self.interpret()?,
+        };
+
+        let internal = cycles.saturating_sub(self.ticked);
+        if internal > 0 {
+            self.bus
+                .catchup(CycleTime::new(self.frequency(), internal as u64));
+        }
+
+        // VRAM DMA halts the CPU while the rest of the system keeps going
+        let stall = self.bus.take_stall();
+        if stall > 0 {
+            self.bus
+                .catchup(CycleTime::new(self.frequency(), stall as u64));
+        }
+
+        Ok(cycles + stall)
+    }
 
     /// Pushes any interrupt onto the stack if any were available
     fn interrupt(&mut self) -> Option<()> {
@@ -498,7 +876,11 @@ impl cpu::CPU for CPU {
     }
 
     fn frequency(&self) -> u32 {
-        self.clock
+        if self.double_speed {
+            self.clock * 2
+        } else {
+            self.clock
+        }
     }
 }
 
@@ -515,13 +897,30 @@ mod tests {
     fn setup_gameboy(pc: u16) -> gameboy_cpu::CPU {
         let ram = Box::new(gameboy::RAM::<RAM_SIZE>::create(RAM_START));
         let vram = Box::new(gameboy::RAM::<VRAM_SIZE>::create(VRAM_START));
-        let gpu = Box::new(gameboy::GPU::create(vram));
-        let bus = Box::new(gameboy::Bus::create(ram, gpu));
-        let mut cpu = gameboy::CPU::create(4194304, bus);
+        let gpu = Box::new(gameboy::GPU::create(Model::DMG, vram));
+        let cartridge = Box::new(gameboy::Cartridge::create(Vec::new()));
+        let bus = Box::new(gameboy::Bus::create(Model::DMG, cartridge, ram, gpu));
+        let mut cpu = gameboy::CPU::create(Model::DMG, bus);
         cpu.PC = pc.into();
         cpu
     }
 
+    #[test]
+    #[allow(deprecated)]
+    fn trait_constructors() {
+        let ram = Box::new(gameboy::RAM::<RAM_SIZE>::create(RAM_START));
+        let vram = Box::new(gameboy::RAM::<VRAM_SIZE>::create(VRAM_START));
+        let gpu = Box::new(gameboy::GPU::create(Model::DMG, vram));
+        let cartridge = Box::new(gameboy::Cartridge::create(Vec::new()));
+        let bus: gameboy::Bus = Bus::create(Model::DMG, cartridge, ram, gpu);
+        let mut cpu: gameboy::CPU<dyn Bus<Addr = u16, Data = u8>> =
+            CPU::create(Model::DMG, Box::new(bus));
+
+        cpu.PC = RAM_START.into();
+        assert_eq!(cpu.step().expect("NOP step"), 4);
+        assert_eq!(cpu.register(gameboy::Reg::PC), RAM_START + 1);
+    }
+
     #[test]
     fn test_cpu_ADD() {
         let mut cpu = setup_gameboy(RAM_START);
@@ -715,4 +1114,181 @@ mod tests {
         assert_eq!(cpu.AF.get_bit(Flag::C.bit()), Flag::C.mask() as u16);
         assert_eq!(cpu.AF.get_bit(Flag::H.bit()), Flag::H.mask() as u16);
     }
+
+    #[test]
+    fn test_cpu_STOP_speed_switch() {
+        let ram = Box::new(gameboy::BankedWRAM::create(RAM_START));
+        let vram = Box::new(gameboy::BankedVRAM::create(VRAM_START));
+        let mut gpu = Box::new(gameboy::GPU::create(Model::CGB, vram));
+        gpu.write_byte(0xFF40, 0x80).expect("LCD on");
+        let cartridge = Box::new(gameboy::Cartridge::create(Vec::new()));
+        let bus = Box::new(gameboy::Bus::create(Model::CGB, cartridge, ram, gpu));
+        let mut cpu = gameboy::CPU::create(Model::CGB, bus);
+        cpu.PC = RAM_START.into();
+
+        cpu.bus_apply(|bus| {
+            const STOP: u8 = 0x10;
+
+            bus.write_byte(RAM_START, STOP)
+                .expect("STOP to be written to RAM");
+            bus.write_byte(RAM_START + 2, STOP)
+                .expect("STOP to be written to RAM");
+            // Arm the speed switch
+            bus.write_byte(0xFF4D, 0x01).expect("KEY1 write");
+        });
+
+        cpu.step().expect("CPU to switch speed");
+        assert_eq!(u16::from(cpu.PC), RAM_START + 2);
+        assert_eq!(cpu.frequency(), 2 * 4194304);
+        assert_eq!(cpu.bus().read_byte(0xFF4D).expect("KEY1 read"), 0xFE);
+
+        // Without arming KEY1 again STOP leaves the speed alone
+        cpu.step().expect("CPU to step");
+        assert_eq!(cpu.frequency(), 2 * 4194304);
+
+        // The PPU keeps its pace, each instruction now only lasts 2 dots. The
+        // first STOP was fetched at normal speed, so the two STOPs already
+        // used up 6 of the 456 dots in the scanline
+        let mut nops = 0;
+        while cpu.bus().read_byte(0xFF44).expect("LY read") == 0 {
+            cpu.step().expect("NOP step");
+            nops += 1;
+        }
+        assert_eq!(nops, (456 - 4 - 2) / 2);
+    }
+
+    #[test]
+    fn test_cpu_access_timing() {
+        let mut cpu = setup_gameboy(RAM_START);
+        cpu.HL = 0xFF44u16.into();
+
+        cpu.bus_apply(|bus| {
+            const LD_A_DEREF_HL: u8 = 0x7E;
+
+            bus.write_byte(0xFF40, 0x80).expect("LCD on");
+            // 112 NOPs take up 448 of the 456 dots in the first scanline
+            bus.write_byte(RAM_START + 112, LD_A_DEREF_HL)
+                .expect("LD A (HL) to be written to RAM");
+        });
+
+        for _ in 0..112 {
+            assert_eq!(cpu.step().expect("NOP step"), 4);
+        }
+
+        // The read of LY happens in the second M-cycle, once the line is over
+        assert_eq!(cpu.step().expect("LD step"), 8);
+        assert_eq!(cpu.AF.get_high(), 1);
+        assert_eq!(u16::from(cpu.PC), RAM_START + 113);
+    }
+
+    #[test]
+    fn test_block_cache_matches_interpreter() {
+        // LD A,12h; LD B,F0h; ADD A,B; ADC A,01h; ADD HL,BC; LD C,B;
+        // LD A,(HL); NOP
+        const PROGRAM: [u8; 12] = [
+            0x3E, 0x12, 0x06, 0xF0, 0x80, 0xCE, 0x01, 0x09, 0x48, 0x7E, 0x00, 0x00,
+        ];
+
+        let run = |cached: bool| {
+            let mut cpu = setup_gameboy(RAM_START);
+            cpu.set_block_cache(cached);
+            cpu.HL = RAM_START.into();
+            cpu.bus_apply(|bus| {
+                bus.write_slice(RAM_START, &PROGRAM)
+                    .expect("program to be written to RAM")
+            });
+
+            let cycles: Vec<u32> = (0..8).map(|_| cpu.step().expect("step")).collect();
+            let regs = gameboy::Reg::ALL.map(|reg| cpu.register(reg));
+            (cycles, regs, cpu.cached_blocks())
+        };
+
+        let (plain_cycles, plain_regs, _) = run(false);
+        let (cached_cycles, cached_regs, blocks) = run(true);
+        assert_eq!(cached_cycles, plain_cycles);
+        assert_eq!(cached_regs, plain_regs);
+        // LD A,(HL) ends the first block and the NOPs start another
+        assert_eq!(blocks, 2);
+    }
+
+    #[test]
+    fn test_block_cache_invalidation() {
+        const LD_B_IMM8: u8 = 0x06;
+        const LD_DEREF_HL_A: u8 = 0x77;
+
+        let mut cpu = setup_gameboy(RAM_START);
+        cpu.set_block_cache(true);
+        cpu.AF.set_high(0x09);
+        // Points at the immediate of LD B
+        cpu.HL = (RAM_START + 1).into();
+        cpu.bus_apply(|bus| {
+            bus.write_slice(RAM_START, &[LD_B_IMM8, 0x05, LD_DEREF_HL_A])
+                .expect("program to be written to RAM")
+        });
+
+        cpu.step().expect("LD B step");
+        assert_eq!(cpu.BC.get_high(), 0x05);
+        assert_eq!(cpu.cached_blocks(), 1);
+
+        // Code writing over itself
+        cpu.step().expect("LD (HL) step");
+        assert_eq!(cpu.cached_blocks(), 0);
+        cpu.PC = RAM_START.into();
+        cpu.step().expect("LD B step");
+        assert_eq!(cpu.BC.get_high(), 0x09);
+
+        // Writes from outside the CPU
+        cpu.bus_apply(|bus| bus.write_byte(RAM_START + 1, 0x11).expect("RAM write"));
+        cpu.PC = RAM_START.into();
+        cpu.step().expect("LD B step");
+        assert_eq!(cpu.BC.get_high(), 0x11);
+
+        // Only writes to cached code drop it
+        cpu.bus_mut().set_buttons(gameboy::Buttons::A);
+        cpu.write_byte(RAM_START + 0x10, 0x22).expect("RAM write");
+        assert_eq!(cpu.cached_blocks(), 1);
+        cpu.write_byte(RAM_START + 1, 0x33).expect("RAM write");
+        assert_eq!(cpu.cached_blocks(), 0);
+        cpu.PC = RAM_START.into();
+        cpu.step().expect("LD B step");
+        assert_eq!(cpu.BC.get_high(), 0x33);
+    }
+
+    #[test]
+    fn test_block_cache_wram_banks() {
+        const LD_A_IMM8: u8 = 0x3E;
+        const LD_DEREF_HL_A: u8 = 0x77;
+
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = 0x80;
+        let mut cpu = gameboy::create_system(Model::CGB, gameboy::Cartridge::create(rom));
+        cpu.set_block_cache(true);
+        cpu.bus_apply(|bus| {
+            // NOPs run from bank 0 into LD A of each switchable bank
+            bus.write_slice(0xCFFE, &[0x00, 0x00]).expect("bank 0");
+            for bank in [1, 2] {
+                bus.write_byte(0xFF70, bank).expect("SVBK");
+                bus.write_slice(0xD000, &[LD_A_IMM8, bank * 0x11])
+                    .expect("bank");
+            }
+            bus.write_byte(0xFF70, 0x01).expect("SVBK");
+            bus.write_byte(0xC000, LD_DEREF_HL_A).expect("bank 0");
+        });
+
+        let run = |cpu: &mut gameboy::CPU| {
+            cpu.PC = 0xCFFEu16.into();
+            for _ in 0..3 {
+                cpu.step().expect("step");
+            }
+            cpu.AF.get_high()
+        };
+        assert_eq!(run(&mut cpu), 0x11);
+
+        // The program switches banks itself, leaving the cache be
+        cpu.HL = 0xFF70u16.into();
+        cpu.AF.set_high(0x02);
+        cpu.PC = 0xC000u16.into();
+        cpu.step().expect("LD (HL) step");
+        assert_eq!(run(&mut cpu), 0x22);
+    }
 }
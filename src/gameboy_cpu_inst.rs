@@ -1,5 +1,6 @@
 use crate::gameboy_cpu::*;
-use crate::cpu;
+
+use std::fmt;
 
 #[derive(Clone, Copy, Debug)]
 pub enum Opcode {
@@ -41,6 +42,28 @@ pub enum Opcode {
     DI,
     LDHL,
     EI,
+    /// CB-prefixed operations
+    RLC,
+    RRC,
+    RL,
+    RR,
+    SLA,
+    SRA,
+    SWAP,
+    SRL,
+    BIT,
+    RES,
+    SET,
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Invalid opcodes are emitted as raw data bytes
+            Opcode::Invalid => f.write_str("DB"),
+            op => fmt::Debug::fmt(op, f),
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -64,6 +87,8 @@ pub enum Operand {
     FlagNZ,
     FlagC,
     FlagZ,
+    /// Bit index of the CB-prefixed BIT, RES and SET operations
+    Bit(u8),
     None,
 }
 
@@ -91,8 +116,56 @@ impl Instr {
             width,
         }
     }
+
+    /// Decodes the second byte of a CB-prefixed instruction. The lower three
+    /// bits select the operand, the upper five bits select the operation.
+    const fn prefixed(op: u8) -> Self {
+        let target = match op & 0x07 {
+            0 => Operand::Value(Reg::B),
+            1 => Operand::Value(Reg::C),
+            2 => Operand::Value(Reg::D),
+            3 => Operand::Value(Reg::E),
+            4 => Operand::Value(Reg::H),
+            5 => Operand::Value(Reg::L),
+            6 => Operand::DerefReg(Reg::HL),
+            _ => Operand::Value(Reg::A),
+        };
+        let deref = op & 0x07 == 6;
+        let bit = Operand::Bit((op >> 3) & 0x07);
+
+        match op >> 6 {
+            0 => {
+                let opcode = match (op >> 3) & 0x07 {
+                    0 => Opcode::RLC,
+                    1 => Opcode::RRC,
+                    2 => Opcode::RL,
+                    3 => Opcode::RR,
+                    4 => Opcode::SLA,
+                    5 => Opcode::SRA,
+                    6 => Opcode::SWAP,
+                    _ => Opcode::SRL,
+                };
+                Instr::create(2, opcode, target, Operand::None, if deref { 16 } else { 8 })
+            }
+            1 => Instr::create(2, Opcode::BIT, bit, target, if deref { 12 } else { 8 }),
+            2 => Instr::create(2, Opcode::RES, bit, target, if deref { 16 } else { 8 }),
+            _ => Instr::create(2, Opcode::SET, bit, target, if deref { 16 } else { 8 }),
+        }
+    }
 }
 
+/// Instructions following the 0xCB prefix byte. Widths and cycles include the
+/// prefix itself.
+pub const PREFIX_INSTRUCTION_LOOKUP: [Instr; 256] = {
+    let mut table = [INSTRUCTION_LOOKUP[0xCB]; 256];
+    let mut op = 0;
+    while op < table.len() {
+        table[op] = Instr::prefixed(op as u8);
+        op += 1;
+    }
+    table
+};
+
 #[rustfmt::skip]
 pub const INSTRUCTION_LOOKUP: [Instr; 256] = [
     /* 0x00 */ Instr::create(1, Opcode::NOP    , Operand::None             , Operand::None             , 4),
@@ -111,7 +184,7 @@ pub const INSTRUCTION_LOOKUP: [Instr; 256] = [
     /* 0x0D */ Instr::create(1, Opcode::DEC    , Operand::Value(Reg::C)    , Operand::None             , 4),
     /* 0x0E */ Instr::create(2, Opcode::LD     , Operand::Value(Reg::C)    , Operand::Imm8             , 8),
     /* 0x0F */ Instr::create(1, Opcode::RRCA   , Operand::None             , Operand::None             , 4),
-    /* 0x10 */ Instr::create(1, Opcode::STOP   , Operand::None             , Operand::None             , 4),
+    /* 0x10 */ Instr::create(2, Opcode::STOP   , Operand::None             , Operand::None             , 4),
     /* 0x11 */ Instr::create(3, Opcode::LD     , Operand::Value(Reg::DE)   , Operand::Imm16            , 12),
     /* 0x12 */ Instr::create(1, Opcode::LD     , Operand::DerefReg(Reg::DE), Operand::Value(Reg::A)    , 8),
     /* 0x13 */ Instr::create(1, Opcode::INC    , Operand::Value(Reg::DE)   , Operand::None             , 8),
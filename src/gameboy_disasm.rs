@@ -0,0 +1,372 @@
+use crate::addressable::AddressError;
+use crate::gameboy_cpu::Reg;
+use crate::gameboy_cpu_inst::*;
+use crate::inspect::Inspect;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
+
+/// Restart vectors, interrupt vectors and the cartridge entry point
+pub const ENTRY_POINTS: [(u16, &str); 14] = [
+    (0x0000, "rst_00"),
+    (0x0008, "rst_08"),
+    (0x0010, "rst_10"),
+    (0x0018, "rst_18"),
+    (0x0020, "rst_20"),
+    (0x0028, "rst_28"),
+    (0x0030, "rst_30"),
+    (0x0038, "rst_38"),
+    (0x0040, "int_vblank"),
+    (0x0048, "int_stat"),
+    (0x0050, "int_timer"),
+    (0x0058, "int_serial"),
+    (0x0060, "int_joypad"),
+    (0x0100, "entry"),
+];
+
+/// An instruction decoded at a fixed address together with its operand bytes
+#[derive(Clone, Copy, Debug)]
+pub struct Decoded {
+    /// Address of the first instruction byte
+    pub addr: u16,
+    pub instr: Instr,
+    /// Raw instruction bytes, only the first `instr.width` are valid
+    pub bytes: [u8; 3],
+}
+
+impl Decoded {
+    fn imm8(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    fn imm16(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[1], self.bytes[2]])
+    }
+
+    /// Address of the instruction following this one
+    pub fn next(&self) -> u16 {
+        self.addr.wrapping_add(self.instr.width as u16)
+    }
+
+    fn is_conditional(&self) -> bool {
+        matches!(
+            self.instr.dst,
+            Operand::FlagNC | Operand::FlagNZ | Operand::FlagC | Operand::FlagZ
+        )
+    }
+
+    /// Statically known destination of a jump, call or restart
+    pub fn target(&self) -> Option<u16> {
+        let has_imm16 =
+            matches!(self.instr.dst, Operand::Imm16) || matches!(self.instr.src, Operand::Imm16);
+
+        match self.instr.opcode {
+            Opcode::JR => Some(self.next().wrapping_add(self.imm8() as i8 as u16)),
+            Opcode::JP | Opcode::CALL if has_imm16 => Some(self.imm16()),
+            Opcode::RST => rst_vector(self.instr.dst),
+            _ => None,
+        }
+    }
+
+    /// Whether execution may continue with the instruction at `next()`
+    pub fn falls_through(&self) -> bool {
+        match self.instr.opcode {
+            Opcode::Invalid | Opcode::RETI => false,
+            Opcode::JP | Opcode::JR | Opcode::RET => self.is_conditional(),
+            _ => true,
+        }
+    }
+
+    fn write_operand(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        oper: Operand,
+        labels: &BTreeMap<u16, String>,
+    ) -> fmt::Result {
+        let write_target = |f: &mut fmt::Formatter<'_>, addr: u16| match labels.get(&addr) {
+            Some(label) => f.write_str(label),
+            None => write!(f, "${addr:04X}"),
+        };
+
+        match oper {
+            Operand::Value(r) => write!(f, "{r}"),
+            Operand::DerefReg(Reg::C) => f.write_str("($FF00+C)"),
+            Operand::DerefReg(r) => write!(f, "({r})"),
+            Operand::Imm8 => write!(f, "${:02X}", self.imm8()),
+            Operand::Imm8Signed => match self.target() {
+                Some(addr) => write_target(f, addr),
+                None => write!(f, "{:+}", self.imm8() as i8),
+            },
+            Operand::DerefImm8 => write!(f, "($FF{:02X})", self.imm8()),
+            Operand::Imm16 => match self.target() {
+                Some(addr) => write_target(f, addr),
+                None => write!(f, "${:04X}", self.imm16()),
+            },
+            Operand::DerefImm16 => write!(f, "(${:04X})", self.imm16()),
+            Operand::Rst00H
+            | Operand::Rst08H
+            | Operand::Rst10H
+            | Operand::Rst18H
+            | Operand::Rst20H
+            | Operand::Rst28H
+            | Operand::Rst30H
+            | Operand::Rst38H => write!(f, "${:02X}", rst_vector(oper).unwrap_or_default()),
+            Operand::FlagNC => f.write_str("NC"),
+            Operand::FlagNZ => f.write_str("NZ"),
+            Operand::FlagC => f.write_str("C"),
+            Operand::FlagZ => f.write_str("Z"),
+            Operand::Bit(bit) => write!(f, "{bit}"),
+            Operand::None => Ok(()),
+        }
+    }
+
+    /// Writes the instruction, substituting known targets with their label
+    fn write_labelled(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        labels: &BTreeMap<u16, String>,
+    ) -> fmt::Result {
+        if let Opcode::Invalid = self.instr.opcode {
+            return write!(f, "{} ${:02X}", self.instr.opcode, self.bytes[0]);
+        }
+
+        write!(f, "{}", self.instr.opcode)?;
+
+        let operands = [self.instr.dst, self.instr.src];
+        let mut operands = operands.iter().filter(|o| !matches!(o, Operand::None));
+
+        if let Some(&dst) = operands.next() {
+            f.write_str(" ")?;
+            self.write_operand(f, dst, labels)?;
+        }
+        if let Some(&src) = operands.next() {
+            f.write_str(",")?;
+            self.write_operand(f, src, labels)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for Decoded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_labelled(f, &BTreeMap::new())
+    }
+}
+
+fn rst_vector(oper: Operand) -> Option<u16> {
+    match oper {
+        Operand::Rst00H => Some(0x00),
+        Operand::Rst08H => Some(0x08),
+        Operand::Rst10H => Some(0x10),
+        Operand::Rst18H => Some(0x18),
+        Operand::Rst20H => Some(0x20),
+        Operand::Rst28H => Some(0x28),
+        Operand::Rst30H => Some(0x30),
+        Operand::Rst38H => Some(0x38),
+        _ => None,
+    }
+}
+
+/// Decodes the instruction at `addr`, following the 0xCB prefix if present
+pub fn decode<A>(bus: &A, addr: u16) -> Result<Decoded, AddressError<u16>>
+where
+    A: Inspect<Addr = u16, Data = u8> + ?Sized,
+{
+    let opcode = bus.inspect(addr)?;
+    let mut bytes = [opcode, 0, 0];
+
+    let instr = if opcode == 0xCB {
+        bytes[1] = bus.inspect(addr.wrapping_add(1))?;
+        PREFIX_INSTRUCTION_LOOKUP[bytes[1] as usize]
+    } else {
+        INSTRUCTION_LOOKUP[opcode as usize]
+    };
+
+    for (offset, byte) in bytes.iter_mut().enumerate().take(instr.width as usize) {
+        *byte = bus.inspect(addr.wrapping_add(offset as u16))?;
+    }
+
+    Ok(Decoded { addr, instr, bytes })
+}
+
+/// Linearly decodes every instruction starting within `range`
+pub fn disassemble<A>(bus: &A, range: Range<u16>) -> Result<Vec<Decoded>, AddressError<u16>>
+where
+    A: Inspect<Addr = u16, Data = u8> + ?Sized,
+{
+    let mut instructions = Vec::new();
+    let mut addr = range.start;
+
+    while range.contains(&addr) {
+        let decoded = decode(bus, addr)?;
+        instructions.push(decoded);
+
+        // Stop rather than wrap around the address space
+        if decoded.next() <= addr {
+            break;
+        }
+        addr = decoded.next();
+    }
+
+    Ok(instructions)
+}
+
+/// Code reachable from a set of entry points, keyed by address
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub code: BTreeMap<u16, Decoded>,
+    pub labels: BTreeMap<u16, String>,
+}
+
+impl Listing {
+    /// Recursive-descent disassembly following every statically known jump,
+    /// call and restart from the given named entry points
+    pub fn walk<A>(bus: &A, entries: &[(u16, &str)]) -> Self
+    where
+        A: Inspect<Addr = u16, Data = u8> + ?Sized,
+    {
+        let mut listing = Listing::default();
+        let mut pending = Vec::new();
+
+        for &(addr, name) in entries {
+            listing.labels.insert(addr, name.to_string());
+            pending.push(addr);
+        }
+
+        while let Some(mut addr) = pending.pop() {
+            while !listing.code.contains_key(&addr) {
+                // Branches into unmapped memory end the walk of that path
+                let decoded = match decode(bus, addr) {
+                    Ok(decoded) => decoded,
+                    Err(_) => break,
+                };
+                listing.code.insert(addr, decoded);
+
+                if let Some(target) = decoded.target() {
+                    let prefix = match decoded.instr.opcode {
+                        Opcode::CALL | Opcode::RST => "sub",
+                        _ => "L",
+                    };
+                    listing
+                        .labels
+                        .entry(target)
+                        .or_insert_with(|| format!("{prefix}_{target:04X}"));
+                    pending.push(target);
+                }
+
+                if !decoded.falls_through() {
+                    break;
+                }
+                addr = decoded.next();
+            }
+        }
+
+        let code = &listing.code;
+        listing.labels.retain(|addr, _| code.contains_key(addr));
+
+        listing
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (addr, decoded) in &self.code {
+            if let Some(label) = self.labels.get(addr) {
+                writeln!(f)?;
+                writeln!(f, "{label}:")?;
+            }
+
+            let bytes: Vec<String> = decoded.bytes[..decoded.instr.width as usize]
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect();
+
+            write!(f, "    {addr:04X}  {:<8}  ", bytes.join(" "))?;
+            decoded.write_labelled(f, &self.labels)?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn rom_with(code: &[(u16, &[u8])]) -> gameboy::Cartridge {
+        let mut rom = vec![0xFFu8; 0x8000];
+        for (addr, bytes) in code {
+            let start = *addr as usize;
+            rom[start..start + bytes.len()].copy_from_slice(bytes);
+        }
+        gameboy::Cartridge::create(rom)
+    }
+
+    fn text_at(rom: &gameboy::Cartridge, addr: u16) -> String {
+        gameboy::decode(rom, addr).expect("decode").to_string()
+    }
+
+    #[test]
+    fn display_resolves_operands() {
+        let rom = rom_with(&[
+            (0x00, &[0x01, 0x34, 0x12]), // LD BC,$1234
+            (0x03, &[0xE0, 0x44]),       // LDH ($FF44),A
+            (0x05, &[0xE8, 0xFE]),       // ADD SP,-2
+            (0x07, &[0xCB, 0x7C]),       // BIT 7,H
+            (0x09, &[0x20, 0xFB]),       // JR NZ,$0006
+            (0x0B, &[0xE2]),             // LD ($FF00+C),A
+            (0x0C, &[0xD3]),             // Invalid
+            (0x0D, &[0xFA, 0x00, 0xC0]), // LD A,($C000)
+        ]);
+
+        assert_eq!(text_at(&rom, 0x00), "LD BC,$1234");
+        assert_eq!(text_at(&rom, 0x03), "LDH ($FF44),A");
+        assert_eq!(text_at(&rom, 0x05), "ADD SP,-2");
+        assert_eq!(text_at(&rom, 0x07), "BIT 7,H");
+        assert_eq!(text_at(&rom, 0x09), "JR NZ,$0006");
+        assert_eq!(text_at(&rom, 0x0B), "LD ($FF00+C),A");
+        assert_eq!(text_at(&rom, 0x0C), "DB $D3");
+        assert_eq!(text_at(&rom, 0x0D), "LD A,($C000)");
+    }
+
+    #[test]
+    fn disassemble_range() {
+        let rom = rom_with(&[(0x00, &[0x00, 0x3E, 0x10, 0xCB, 0x37, 0xC9])]);
+
+        let text: Vec<String> = gameboy::disassemble(&rom, 0x00..0x06)
+            .expect("disassemble")
+            .iter()
+            .map(|d| d.to_string())
+            .collect();
+
+        assert_eq!(text, ["NOP", "LD A,$10", "SWAP A", "RET"]);
+    }
+
+    #[test]
+    fn walk_follows_control_flow() {
+        let rom = rom_with(&[
+            (0x0100, &[0x00, 0xC3, 0x50, 0x01]), // NOP; JP $0150
+            (0x0150, &[0xCD, 0x60, 0x01]),       // CALL $0160
+            (0x0153, &[0x18, 0xFE]),             // JR $0153
+            (0x0160, &[0xC9]),                   // RET
+        ]);
+
+        let listing = gameboy::Listing::walk(&rom, &[(0x0100, "entry")]);
+
+        let addrs: Vec<u16> = listing.code.keys().copied().collect();
+        assert_eq!(addrs, [0x0100, 0x0101, 0x0150, 0x0153, 0x0160]);
+
+        assert_eq!(listing.labels[&0x0100], "entry");
+        assert_eq!(listing.labels[&0x0150], "L_0150");
+        assert_eq!(listing.labels[&0x0153], "L_0153");
+        assert_eq!(listing.labels[&0x0160], "sub_0160");
+
+        let text = listing.to_string();
+        assert!(text.contains("0101  C3 50 01  JP L_0150"));
+        assert!(text.contains("0150  CD 60 01  CALL sub_0160"));
+        assert!(text.contains("0153  18 FE     JR L_0153"));
+    }
+}
@@ -0,0 +1,79 @@
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_joypad::Buttons;
+use crate::gameboy_tty::run_tty;
+use crate::triple_buffer::TripleReader;
+
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+/// Requests a frontend sends back to the emulation thread, which applies
+/// them between frames
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Control {
+    Pause,
+    Resume,
+    /// Power cycles the machine with the same cartridge
+    Reset,
+    SaveState,
+    /// Buttons held from now on
+    Input(Buttons),
+    /// Turns the cheat at an index in the cheat list on or off
+    ToggleCheat(usize),
+    Quit,
+}
+
+/// Where frames are shown
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Frontend {
+    /// Frames are taken and dropped
+    #[default]
+    None,
+    /// Half-block graphics in a 24-bit color terminal
+    Tty,
+}
+
+impl Frontend {
+    pub const ALL: [Frontend; 2] = [Frontend::None, Frontend::Tty];
+
+    /// Shows frames until the emulator stops, sending user requests back
+    pub fn run(
+        self,
+        mut frames: TripleReader<Framebuffer>,
+        control: Sender<Control>,
+    ) -> io::Result<()> {
+        match self {
+            Frontend::None => {
+                while !frames.is_closed() {
+                    frames.read(|_frame| {});
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Ok(())
+            }
+            Frontend::Tty => run_tty(frames, control),
+        }
+    }
+}
+
+impl fmt::Display for Frontend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Frontend::None => f.write_str("none"),
+            Frontend::Tty => f.write_str("tty"),
+        }
+    }
+}
+
+impl FromStr for Frontend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Frontend::ALL
+            .into_iter()
+            .find(|frontend| frontend.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown frontend {s}"))
+    }
+}
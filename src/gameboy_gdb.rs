@@ -0,0 +1,657 @@
+use crate::bus::Bus as _;
+use crate::cpu::{CPUError, CPU as _};
+use crate::gameboy_cpu::{Reg, CPU};
+use crate::gameboy_recorder::{Recorder, SILENT_AUDIO};
+use crate::gameboy_search::{MemorySearch, Predicate, Width};
+use crate::gameboy_system::screen;
+use crate::inspect::Inspect;
+
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+
+/// Register order of the `g`/`G` packets and numbering of `p`/`P`, each
+/// register is sent as a little-endian 16-bit value
+const REGISTERS: [Reg; 6] = [Reg::AF, Reg::BC, Reg::DE, Reg::HL, Reg::SP, Reg::PC];
+
+/// Number of instructions run between polls for a debugger interrupt
+const INTERRUPT_POLL_INTERVAL: u32 = 1024;
+
+/// Candidates listed by `monitor search list`
+const SEARCH_LIST_LIMIT: usize = 32;
+
+/// Stop reasons reported to the debugger as POSIX signal numbers
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// GDB remote serial protocol stub driving a CPU on behalf of a debugger
+#[derive(Debug, Default)]
+pub struct GdbStub {
+    /// Software breakpoints, checked against PC before every instruction
+    breakpoints: BTreeSet<u16>,
+    /// Started with `monitor record`, takes every frame the CPU completes
+    recorder: Option<Recorder>,
+    /// GPU frame count at the last recorded frame
+    recorded_frame: u64,
+    /// Started with `monitor search start`
+    search: Option<MemorySearch>,
+}
+
+/// Packet framing on top of the debugger connection
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> io::Result<u8> {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+
+    /// Receives the next packet payload, acknowledging it. Stray interrupt
+    /// requests and acknowledgements are discarded.
+    fn read_packet(&mut self) -> io::Result<String> {
+        loop {
+            if self.read_byte()? != b'$' {
+                continue;
+            }
+
+            let mut payload = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    b'#' => break,
+                    byte => payload.push(byte),
+                }
+            }
+
+            let checksum = [self.read_byte()?, self.read_byte()?];
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+            if expected == Some(checksum_of(&payload)) {
+                self.stream.write_all(b"+")?;
+                return Ok(String::from_utf8_lossy(&payload).into_owned());
+            }
+
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    /// Sends a packet, retransmitting until the debugger acknowledges it
+    fn send_packet(&mut self, payload: &str) -> io::Result<()> {
+        let packet = format!("${payload}#{:02x}", checksum_of(payload.as_bytes()));
+
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+
+            loop {
+                match self.read_byte()? {
+                    b'+' => return Ok(()),
+                    b'-' => break,
+                    _ => continue,
+                }
+            }
+        }
+    }
+
+    /// Checks without blocking whether the debugger requested a break (^C)
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+
+        let mut byte = [0u8];
+        let received = match self.stream.read(&mut byte) {
+            Ok(0) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        };
+
+        self.stream.set_nonblocking(false)?;
+        received
+    }
+}
+
+fn checksum_of(payload: &[u8]) -> u8 {
+    payload.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+/// Parses the `addr,length` argument of memory packets
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)?, parse_hex(len)?))
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(text: &str) -> String {
+    text.bytes().map(|b| format!("{b:02x}")).collect()
+}
+
+fn stop_signal(err: &CPUError<CPU>) -> u8 {
+    match err {
+        CPUError::AddrErr(crate::AddressError::OutOfBounds(_)) => SIGSEGV,
+        _ => SIGILL,
+    }
+}
+
+impl GdbStub {
+    pub fn create() -> Self {
+        GdbStub::default()
+    }
+
+    /// Serves one debugger session, returning once the debugger detaches,
+    /// kills the target or disconnects. The CPU only runs while the
+    /// debugger has resumed it.
+    pub fn serve(&mut self, cpu: &mut CPU, stream: TcpStream) -> io::Result<()> {
+        // Packets are small and latency bound, don't let Nagle batch them
+        stream.set_nodelay(true)?;
+        let mut conn = Connection { stream };
+
+        loop {
+            let packet = match conn.read_packet() {
+                Ok(packet) => packet,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(err) => return Err(err),
+            };
+
+            // A packet opening with a non-ASCII byte has no command to split
+            // off and is answered as unknown
+            let (command, args) = packet.split_at_checked(1).unwrap_or_default();
+            let reply = match command {
+                "?" => format!("S{SIGTRAP:02x}"),
+                "g" => REGISTERS
+                    .iter()
+                    .map(|&r| {
+                        let [low, high] = cpu.register(r).to_le_bytes();
+                        format!("{low:02x}{high:02x}")
+                    })
+                    .collect(),
+                "G" => self.write_registers(cpu, args),
+                "p" => match parse_hex(args).and_then(|n| REGISTERS.get(n as usize)) {
+                    Some(&r) => {
+                        let [low, high] = cpu.register(r).to_le_bytes();
+                        format!("{low:02x}{high:02x}")
+                    }
+                    None => "E01".to_string(),
+                },
+                "P" => self.write_register(cpu, args),
+                "m" => self.read_memory(cpu, args),
+                "M" => self.write_memory(cpu, args),
+                "Z" | "z" => self.breakpoint(command == "Z", args),
+                "s" | "c" => {
+                    if let Some(pc) = parse_hex(args) {
+                        let _ = cpu.set_register(Reg::PC, pc);
+                    }
+                    let signal = if command == "s" {
+                        let signal = match cpu.step() {
+                            Ok(_) => SIGTRAP,
+                            Err(err) => stop_signal(&err),
+                        };
+                        self.capture(cpu);
+                        signal
+                    } else {
+                        self.resume(cpu, &mut conn)?
+                    };
+                    format!("S{signal:02x}")
+                }
+                "H" => "OK".to_string(),
+                "q" if args == "Attached" => "1".to_string(),
+                "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+                "q" if args.starts_with("Rcmd,") => self.monitor(cpu, &args["Rcmd,".len()..]),
+                "D" => {
+                    conn.send_packet("OK")?;
+                    return Ok(());
+                }
+                "k" => return Ok(()),
+                // Empty replies mark the packet as unsupported
+                _ => String::new(),
+            };
+
+            conn.send_packet(&reply)?;
+        }
+    }
+
+    /// Runs the CPU until a breakpoint, a CPU error or a debugger interrupt
+    /// and returns the signal describing why it stopped
+    fn resume(&mut self, cpu: &mut CPU, conn: &mut Connection) -> io::Result<u8> {
+        let mut steps = 0u32;
+
+        loop {
+            if let Err(err) = cpu.step() {
+                return Ok(stop_signal(&err));
+            }
+            self.capture(cpu);
+
+            if self.breakpoints.contains(&cpu.register(Reg::PC)) {
+                return Ok(SIGTRAP);
+            }
+
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_POLL_INTERVAL) && conn.interrupted()? {
+                return Ok(SIGINT);
+            }
+        }
+    }
+
+    /// Records the frame the CPU just completed, if recording. A failing
+    /// recording is dropped.
+    fn capture(&mut self, cpu: &CPU) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+
+        let frames = cpu.bus().gpu().frames();
+        if frames == self.recorded_frame {
+            return;
+        }
+        self.recorded_frame = frames;
+
+        if recorder
+            .record(&screen(cpu), cpu.bus().gpu().shades())
+            .is_err()
+        {
+            self.recorder = None;
+        }
+    }
+
+    /// Handles `monitor` commands, the reply is console output. Supports
+    /// `record <file.gif|file.y4m> [audio]`, `record stop` and the `search`
+    /// commands.
+    fn monitor(&mut self, cpu: &CPU, hex: &str) -> String {
+        let Some(command) = decode_hex(hex).and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            return "E01".to_string();
+        };
+        let words: Vec<&str> = command.split_whitespace().collect();
+
+        let output = match words[..] {
+            ["record", "stop"] => match self.recorder.take() {
+                Some(recorder) => {
+                    let frames = recorder.frames();
+                    match recorder.finish() {
+                        Ok(()) => format!("recorded {frames} frames\n"),
+                        Err(err) => format!("recording failed: {err}\n"),
+                    }
+                }
+                None => "not recording\n".to_string(),
+            },
+            ["record", path, ref audio @ ..] if audio.is_empty() || audio == ["audio"] => {
+                match Recorder::create(Path::new(path), cpu.model(), !audio.is_empty()) {
+                    Ok(recorder) => {
+                        if let Some(previous) = self.recorder.replace(recorder) {
+                            let _ = previous.finish();
+                        }
+                        self.recorded_frame = cpu.bus().gpu().frames();
+                        match audio.is_empty() {
+                            true => format!("recording to {path}\n"),
+                            false => format!("recording to {path}, {SILENT_AUDIO}\n"),
+                        }
+                    }
+                    Err(err) => format!("{path}: {err}\n"),
+                }
+            }
+            ["search", ref args @ ..] => self.search(cpu, args),
+            _ => "usage: record <file.gif|file.y4m> [audio], record stop\n".to_string(),
+        };
+
+        encode_hex(&output)
+    }
+
+    /// Memory search over RAM, started with `search start [8|16]` and
+    /// narrowed with `search equal|changed|increased|decreased` or `search
+    /// value <n>`. `search list` shows the candidates left.
+    fn search(&mut self, cpu: &CPU, args: &[&str]) -> String {
+        let predicate = match args {
+            ["start", ref width @ ..] => {
+                let width = match width {
+                    [] => Width::Byte,
+                    [width] => match width.parse() {
+                        Ok(width) => width,
+                        Err(err) => return format!("{err}\n"),
+                    },
+                    _ => return "usage: search start [8|16]\n".to_string(),
+                };
+                let search = self.search.insert(MemorySearch::create(cpu.bus(), width));
+                return format!("{} candidates\n", search.candidates().len());
+            }
+            ["equal"] => Predicate::Equal,
+            ["changed"] => Predicate::Changed,
+            ["increased"] => Predicate::Increased,
+            ["decreased"] => Predicate::Decreased,
+            ["value", value] => {
+                let parsed = match value.strip_prefix("0x") {
+                    Some(hex) => u16::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                match parsed {
+                    Ok(value) => Predicate::EqualTo(value),
+                    Err(_) => return format!("bad value {value}\n"),
+                }
+            }
+            ["list"] => {
+                let Some(search) = &self.search else {
+                    return "no search running, start one with search start\n".to_string();
+                };
+
+                let digits = search.width().bytes() * 2;
+                let candidates = search.candidates();
+                let mut output: String = candidates
+                    .iter()
+                    .take(SEARCH_LIST_LIMIT)
+                    .map(|&location| format!("{location} = {:0digits$X}\n", search.value(location)))
+                    .collect();
+                if candidates.len() > SEARCH_LIST_LIMIT {
+                    output += &format!("{} more\n", candidates.len() - SEARCH_LIST_LIMIT);
+                }
+                return output;
+            }
+            _ => {
+                return "usage: search start [8|16], search equal|changed|increased|decreased, \
+                        search value <n>, search list\n"
+                    .to_string()
+            }
+        };
+
+        match &mut self.search {
+            Some(search) => format!("{} candidates\n", search.narrow(cpu.bus(), predicate)),
+            None => "no search running, start one with search start\n".to_string(),
+        }
+    }
+
+    fn write_registers(&mut self, cpu: &mut CPU, args: &str) -> String {
+        let Some(bytes) = decode_hex(args).filter(|b| b.len() == REGISTERS.len() * 2) else {
+            return "E01".to_string();
+        };
+
+        for (&r, word) in REGISTERS.iter().zip(bytes.chunks(2)) {
+            let _ = cpu.set_register(r, u16::from_le_bytes([word[0], word[1]]));
+        }
+
+        "OK".to_string()
+    }
+
+    fn write_register(&mut self, cpu: &mut CPU, args: &str) -> String {
+        let register = args
+            .split_once('=')
+            .and_then(|(n, val)| Some((REGISTERS.get(parse_hex(n)? as usize)?, decode_hex(val)?)));
+
+        match register {
+            Some((&r, val)) if val.len() == 2 => {
+                let _ = cpu.set_register(r, u16::from_le_bytes([val[0], val[1]]));
+                "OK".to_string()
+            }
+            _ => "E01".to_string(),
+        }
+    }
+
+    fn read_memory(&self, cpu: &CPU, args: &str) -> String {
+        let Some((addr, len)) = parse_range(args) else {
+            return "E01".to_string();
+        };
+
+        let bytes: Result<Vec<u8>, _> = (0..len)
+            .map(|offset| cpu.bus().inspect(addr.wrapping_add(offset)))
+            .collect();
+
+        match bytes {
+            Ok(bytes) => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+            Err(_) => "E14".to_string(),
+        }
+    }
+
+    fn write_memory(&self, cpu: &mut CPU, args: &str) -> String {
+        let write = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = parse_range(range)?;
+            let data = decode_hex(data)?;
+            (data.len() == len as usize).then_some((addr, data))
+        });
+
+        let Some((addr, data)) = write else {
+            return "E01".to_string();
+        };
+
+        let mut result = Ok(());
+        cpu.bus_apply(|bus| {
+            result = data
+                .iter()
+                .zip(0u16..)
+                .try_for_each(|(&b, offset)| bus.patch(addr.wrapping_add(offset), b));
+        });
+
+        match result {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E14".to_string(),
+        }
+    }
+
+    /// Handles `Z`/`z` packets, software and hardware breakpoints are treated
+    /// alike since neither patches memory
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let mut fields = args.split(',');
+        let (Some(kind), Some(addr)) = (fields.next(), fields.next().and_then(parse_hex)) else {
+            return "E01".to_string();
+        };
+
+        if kind != "0" && kind != "1" {
+            return String::new();
+        }
+
+        if insert {
+            self.breakpoints.insert(addr);
+        } else {
+            self.breakpoints.remove(&addr);
+        }
+
+        "OK".to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use gameboy_gdb::encode_hex as hex;
+
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    /// Sends a packet and returns the payload of the acknowledged reply
+    fn request(stream: &mut TcpStream, payload: &str) -> String {
+        let sum = payload.bytes().fold(0u8, |s, b| s.wrapping_add(b));
+        write!(stream, "${payload}#{sum:02x}").expect("send packet");
+
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte).expect("ack");
+        assert_eq!(byte[0], b'+');
+
+        let mut reply = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).expect("reply");
+            match byte[0] {
+                b'$' => reply.clear(),
+                b'#' => break,
+                b => reply.push(b),
+            }
+        }
+        let mut checksum = [0u8; 2];
+        stream.read_exact(&mut checksum).expect("checksum");
+        stream.write_all(b"+").expect("ack reply");
+
+        String::from_utf8(reply).expect("utf8 reply")
+    }
+
+    #[test]
+    fn scripted_session() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0x3E, 0x42, 0x00]);
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(rom));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            stream.set_nodelay(true).expect("nodelay");
+            let script = [
+                ("qSupported:swbreak+", "PacketSize=1000"),
+                ("?", "S05"),
+                ("g", "b0011300d8004d01feff0001"),
+                ("p2", "d800"),
+                ("P2=3412", "OK"),
+                ("p2", "3412"),
+                ("m0100,4", "003e4200"),
+                ("Z0,0103,1", "OK"),
+                ("c", "S05"),
+                ("p5", "0301"),
+                ("p0", "b042"),
+                ("z0,0103,1", "OK"),
+                ("s", "S05"),
+                ("p5", "0401"),
+                ("Mc000,2:abcd", "OK"),
+                ("mc000,2", "abcd"),
+                // Debugger writes reach ROM
+                ("M0200,1:76", "OK"),
+                ("m0200,1", "76"),
+                ("vMustReplyEmpty", ""),
+                ("D", "OK"),
+            ];
+
+            for (packet, expected) in script {
+                assert_eq!(request(&mut stream, packet), expected, "reply to {packet}");
+            }
+        });
+
+        let (stream, _) = listener.accept().expect("accept");
+        gameboy::GdbStub::create()
+            .serve(&mut cpu, stream)
+            .expect("session");
+
+        client.join().expect("client script");
+    }
+
+    #[test]
+    fn non_ascii_command() {
+        let mut cpu =
+            gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0x00; 0x8000]));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            for (packet, expected) in [("\u{e9}g", ""), ("?", "S05"), ("D", "OK")] {
+                assert_eq!(request(&mut stream, packet), expected, "reply to {packet}");
+            }
+        });
+
+        let (stream, _) = listener.accept().expect("accept");
+        gameboy::GdbStub::create()
+            .serve(&mut cpu, stream)
+            .expect("session");
+        client.join().expect("client script");
+    }
+
+    #[test]
+    fn monitor_record() {
+        let mut cpu =
+            gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0x00; 0x8000]));
+        let path = std::env::temp_dir().join(format!("gamerboy-{}-gdb.gif", std::process::id()));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let script = [
+            (
+                format!("qRcmd,{}", hex("record stop")),
+                hex("not recording\n"),
+            ),
+            (
+                format!("qRcmd,{}", hex(&format!("record {}", path.display()))),
+                hex(&format!("recording to {}\n", path.display())),
+            ),
+            ("s".to_string(), "S05".to_string()),
+            (
+                format!("qRcmd,{}", hex("record stop")),
+                hex("recorded 0 frames\n"),
+            ),
+            (
+                format!("qRcmd,{}", hex("play")),
+                hex("usage: record <file.gif|file.y4m> [audio], record stop\n"),
+            ),
+            ("D".to_string(), "OK".to_string()),
+        ];
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            for (packet, expected) in script {
+                assert_eq!(request(&mut stream, &packet), expected, "reply to {packet}");
+            }
+        });
+
+        let (stream, _) = listener.accept().expect("accept");
+        gameboy::GdbStub::create()
+            .serve(&mut cpu, stream)
+            .expect("session");
+        client.join().expect("client script");
+
+        let gif = std::fs::read(&path).expect("read GIF");
+        std::fs::remove_file(&path).expect("remove GIF");
+        assert_eq!(&gif[..6], b"GIF89a");
+        assert_eq!(gif.last(), Some(&0x3B));
+    }
+
+    #[test]
+    fn monitor_search() {
+        let mut cpu =
+            gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0x00; 0x8000]));
+        let monitor = |command: &str| format!("qRcmd,{}", hex(command));
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+
+        let script = [
+            (
+                monitor("search changed"),
+                hex("no search running, start one with search start\n"),
+            ),
+            // 8K of WRAM and 127 bytes of HRAM
+            (monitor("search start"), hex("8319 candidates\n")),
+            ("MC010,1:05".to_string(), "OK".to_string()),
+            ("MFF90,1:07".to_string(), "OK".to_string()),
+            (monitor("search increased"), hex("2 candidates\n")),
+            (monitor("search value 0x7"), hex("1 candidates\n")),
+            (monitor("search list"), hex("FF90 = 07\n")),
+            (monitor("search start 16"), hex("8317 candidates\n")),
+            (
+                monitor("search start 32"),
+                hex("widths are 8 or 16 bits, not 32\n"),
+            ),
+            ("D".to_string(), "OK".to_string()),
+        ];
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).expect("connect");
+            for (packet, expected) in script {
+                assert_eq!(request(&mut stream, &packet), expected, "reply to {packet}");
+            }
+        });
+
+        let (stream, _) = listener.accept().expect("accept");
+        gameboy::GdbStub::create()
+            .serve(&mut cpu, stream)
+            .expect("session");
+        client.join().expect("client script");
+    }
+}
@@ -1,21 +1,528 @@
 use crate::addressable::{AddressError, Addressable};
+use crate::framebuffer::Framebuffer;
 use crate::gpu;
+use crate::inspect::Inspect;
+use crate::model::Model;
 use crate::ram::RAM;
 use crate::timed::{CycleTime, Timed};
 
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+/// The PPU advances one dot per cycle of the 4 MiHz system clock
+const DOT_FREQUENCY: u64 = Model::CYCLE_CLOCK as u64;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+const LINE_DOTS: u32 = 456;
+const TOTAL_LINES: u8 = 154;
+
+const OAM_START: u16 = 0xFE00;
+const OAM_END: u16 = 0xFE9F;
+const OAM_SIZE: usize = 160;
+/// Sprites the PPU can show on a single scanline
+const SPRITES_PER_LINE: usize = 10;
+
+const LCDC: u16 = 0xFF40;
+const STAT: u16 = 0xFF41;
+const SCY: u16 = 0xFF42;
+const SCX: u16 = 0xFF43;
+const LY: u16 = 0xFF44;
+const LYC: u16 = 0xFF45;
+const BGP: u16 = 0xFF47;
+const OBP0: u16 = 0xFF48;
+const OBP1: u16 = 0xFF49;
+const WY: u16 = 0xFF4A;
+const WX: u16 = 0xFF4B;
+/// CGB mode select, written by the boot ROM
+const KEY0: u16 = 0xFF4C;
+const KEY0_DMG_MODE: u8 = 1 << 2;
+/// CGB palette RAM index and data ports for background and sprites
+const BCPS: u16 = 0xFF68;
+const BCPD: u16 = 0xFF69;
+const OCPS: u16 = 0xFF6A;
+const OCPD: u16 = 0xFF6B;
+
+/// LCDC bits
+const LCDC_BG_ENABLE: u8 = 1 << 0;
+const LCDC_OBJ_ENABLE: u8 = 1 << 1;
+const LCDC_OBJ_TALL: u8 = 1 << 2;
+const LCDC_BG_MAP: u8 = 1 << 3;
+const LCDC_TILE_DATA: u8 = 1 << 4;
+const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
+const LCDC_WINDOW_MAP: u8 = 1 << 6;
+const LCDC_ENABLE: u8 = 1 << 7;
+
+/// Sprite and CGB background map attribute bits
+const ATTR_CGB_PALETTE: u8 = 0x07;
+const ATTR_BANK: u8 = 1 << 3;
+const ATTR_DMG_PALETTE: u8 = 1 << 4;
+const ATTR_X_FLIP: u8 = 1 << 5;
+const ATTR_Y_FLIP: u8 = 1 << 6;
+const ATTR_PRIORITY: u8 = 1 << 7;
+
+/// Palette index registers auto-increment after data writes with this bit set
+const PALETTE_AUTO_INCREMENT: u8 = 1 << 7;
+const PALETTE_RAM_SIZE: usize = 64;
+
+/// DMG shades from lightest to darkest
+pub const DMG_SHADES: [u16; 4] = [
+    Framebuffer::rgb555(31, 31, 31),
+    Framebuffer::rgb555(21, 21, 21),
+    Framebuffer::rgb555(10, 10, 10),
+    Framebuffer::rgb555(0, 0, 0),
+];
+
+/// Colors the CGB boot ROM gives DMG software it doesn't recognize, in the
+/// 15-bit format of palette RAM
+pub const CGB_COMPAT_PALETTE: [u16; 4] = [
+    Framebuffer::from_rgb888([0xFF, 0xFF, 0xFF]),
+    Framebuffer::from_rgb888([0x7B, 0xFF, 0x31]),
+    Framebuffer::from_rgb888([0x00, 0x63, 0xC5]),
+    Framebuffer::from_rgb888([0x00, 0x00, 0x00]),
+];
+
+/// PPU modes as reported in the lower STAT bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+/// CGB palette memory, eight palettes of four little-endian 15-bit colors
+/// accessed through an index and a data register
+#[derive(Debug)]
+struct PaletteRAM {
+    index: u8,
+    data: [u8; PALETTE_RAM_SIZE],
+}
+
+impl PaletteRAM {
+    fn create() -> Self {
+        // Powers up white
+        PaletteRAM {
+            index: 0,
+            data: [0xFF; PALETTE_RAM_SIZE],
+        }
+    }
+
+    fn read_index(&self) -> u8 {
+        0x40 | self.index
+    }
+
+    fn write_index(&mut self, data: u8) {
+        self.index = data & (PALETTE_AUTO_INCREMENT | 0x3F);
+    }
+
+    fn read_data(&self) -> u8 {
+        self.data[(self.index & 0x3F) as usize]
+    }
+
+    fn write_data(&mut self, data: u8) {
+        self.data[(self.index & 0x3F) as usize] = data;
+
+        if self.index & PALETTE_AUTO_INCREMENT != 0 {
+            self.index = PALETTE_AUTO_INCREMENT | (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Stores at the current index without incrementing it
+    fn patch_data(&mut self, data: u8) {
+        self.data[(self.index & 0x3F) as usize] = data;
+    }
+
+    fn color(&self, palette: u8, index: u8) -> u16 {
+        let offset = (palette as usize * 4 + index as usize) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]]) & 0x7FFF
+    }
+}
+
+/// Approximates how colors look on the CGB LCD, which is darker and less
+/// saturated than a modern display
+pub fn correct_color(color: u16) -> u16 {
+    let [r, g, b] = [color & 0x1F, color >> 5 & 0x1F, color >> 10 & 0x1F].map(u32::from);
+
+    Framebuffer::from_rgb888([
+        ((r * 13 + g * 2 + b) >> 1) as u8,
+        ((g * 3 + b) << 1) as u8,
+        ((r * 3 + g * 2 + b * 11) >> 1) as u8,
+    ])
+}
+
 #[derive(Debug)]
 pub struct GPU {
     vram: Box<dyn RAM<Addr = u16, Data = u8>>,
+    /// Sprite attribute table
+    oam: [u8; OAM_SIZE],
+    lcdc: u8,
+    /// Only the interrupt select bits are stored, the rest is derived
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+    mode: Mode,
+    /// Dots into the current scanline
+    dot: u32,
+    /// Time short of a whole dot left over from the last catchup, times
+    /// the dot frequency
+    leftover: u64,
+    /// Window lines rendered so far this frame
+    window_line: u8,
+    framebuffer: Framebuffer,
+    /// DMG shade of each framebuffer pixel, row by row
+    shades: Vec<u8>,
+    frames: u64,
+    hblanks: u64,
+    /// CGB mode, colors come from palette RAM and tiles from both banks
+    cgb: bool,
+    /// CGB hardware running DMG software, DMG palettes index palette RAM
+    compat: bool,
+    bg_palettes: PaletteRAM,
+    obj_palettes: PaletteRAM,
+    color_correction: bool,
+}
+
+/// Color index of pixel `bit` (7 is leftmost) in a 2bpp tile row
+fn color_index(low: u8, high: u8, bit: u8) -> u8 {
+    ((high >> bit) & 1) << 1 | (low >> bit) & 1
+}
+
+/// Shade a DMG palette register maps color `index` to
+fn shade_index(palette: u8, index: u8) -> u8 {
+    (palette >> (index * 2)) & 0x03
+}
+
+fn shade(palette: u8, index: u8) -> u16 {
+    DMG_SHADES[shade_index(palette, index) as usize]
+}
+
+impl GPU {
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Passes rendered colors through `correct_color`
+    pub fn set_color_correction(&mut self, enabled: bool) {
+        self.color_correction = enabled;
+    }
+
+    fn vram_byte(&self, bank: u8, addr: u16) -> u8 {
+        self.vram.read_bank(bank as usize, addr).unwrap_or(0xFF)
+    }
+
+    /// Low and high bit planes of one row of the tile at `tile_addr`
+    fn tile_row(&self, bank: u8, tile_addr: u16, row: u16) -> (u8, u8) {
+        let addr = tile_addr + row * 2;
+        (self.vram_byte(bank, addr), self.vram_byte(bank, addr + 1))
+    }
+
+    fn bg_color(&self, index: u8, attrs: u8) -> u16 {
+        if self.cgb {
+            self.bg_palettes.color(attrs & ATTR_CGB_PALETTE, index)
+        } else if self.compat {
+            self.bg_palettes.color(0, shade_index(self.bgp, index))
+        } else {
+            shade(self.bgp, index)
+        }
+    }
+
+    /// OBP0 or OBP1, whichever the sprite selects
+    fn obj_palette(&self, attrs: u8) -> u8 {
+        if attrs & ATTR_DMG_PALETTE != 0 {
+            self.obp1
+        } else {
+            self.obp0
+        }
+    }
+
+    fn obj_color(&self, index: u8, attrs: u8) -> u16 {
+        let dmg_palette = self.obj_palette(attrs);
+
+        if self.cgb {
+            self.obj_palettes.color(attrs & ATTR_CGB_PALETTE, index)
+        } else if self.compat {
+            let palette = (attrs & ATTR_DMG_PALETTE != 0) as u8;
+            self.obj_palettes
+                .color(palette, shade_index(dmg_palette, index))
+        } else {
+            shade(dmg_palette, index)
+        }
+    }
+
+    /// Address of a background or window tile, which depending on LCDC is
+    /// indexed unsigned from 0x8000 or signed from 0x9000
+    fn bg_tile_addr(&self, tile: u8) -> u16 {
+        if self.lcdc & LCDC_TILE_DATA != 0 {
+            0x8000 + tile as u16 * 16
+        } else {
+            0x9000u16.wrapping_add((tile as i8 as i16 * 16) as u16)
+        }
+    }
+
+    fn render_scanline(&mut self) {
+        let ly = self.ly as usize;
+        let mut bg_index = [0u8; SCREEN_WIDTH];
+        // Map attributes from VRAM bank 1, always 0 on DMG
+        let mut bg_attrs = [0u8; SCREEN_WIDTH];
+        // On CGB the BG enable bit instead takes the background's priority
+        let bg_priority = !self.cgb || self.lcdc & LCDC_BG_ENABLE != 0;
+
+        if self.cgb || self.lcdc & LCDC_BG_ENABLE != 0 {
+            let bg_map = if self.lcdc & LCDC_BG_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            let window_map = if self.lcdc & LCDC_WINDOW_MAP != 0 {
+                0x9C00
+            } else {
+                0x9800
+            };
+            let window_x = self.wx as usize;
+            let window_visible = self.lcdc & LCDC_WINDOW_ENABLE != 0
+                && ly >= self.wy as usize
+                && window_x < SCREEN_WIDTH + 7;
+
+            for x in 0..SCREEN_WIDTH {
+                let (map, px, py): (u16, usize, usize) = if window_visible && x + 7 >= window_x {
+                    (window_map, x + 7 - window_x, self.window_line as usize)
+                } else {
+                    (
+                        bg_map,
+                        (x + self.scx as usize) & 0xFF,
+                        (ly + self.scy as usize) & 0xFF,
+                    )
+                };
+
+                let map_addr = map + (py / 8 * 32 + px / 8) as u16;
+                let tile = self.vram_byte(0, map_addr);
+                let attrs = if self.cgb {
+                    self.vram_byte(1, map_addr)
+                } else {
+                    0
+                };
+
+                let row = if attrs & ATTR_Y_FLIP != 0 {
+                    7 - py % 8
+                } else {
+                    py % 8
+                };
+                let bank = (attrs & ATTR_BANK != 0) as u8;
+                let (low, high) = self.tile_row(bank, self.bg_tile_addr(tile), row as u16);
+
+                let bit = if attrs & ATTR_X_FLIP != 0 {
+                    px % 8
+                } else {
+                    7 - px % 8
+                };
+                bg_index[x] = color_index(low, high, bit as u8);
+                bg_attrs[x] = attrs;
+            }
+
+            if window_visible {
+                self.window_line += 1;
+            }
+        }
+
+        let mut line: [u16; SCREEN_WIDTH] =
+            std::array::from_fn(|x| self.bg_color(bg_index[x], bg_attrs[x]));
+        let mut shades: [u8; SCREEN_WIDTH] =
+            std::array::from_fn(|x| shade_index(self.bgp, bg_index[x]));
+
+        if self.lcdc & LCDC_OBJ_ENABLE != 0 {
+            let height = if self.lcdc & LCDC_OBJ_TALL != 0 {
+                16
+            } else {
+                8
+            };
+
+            let mut sprites: Vec<&[u8]> = self
+                .oam
+                .chunks(4)
+                .filter(|obj| {
+                    let top = obj[0] as usize;
+                    ly + 16 >= top && ly + 16 < top + height
+                })
+                .take(SPRITES_PER_LINE)
+                .collect();
+            // On DMG lower X wins and ties go to the earlier OAM entry (stable
+            // sort), CGB only goes by OAM order
+            if !self.cgb {
+                sprites.sort_by_key(|obj| obj[1]);
+            }
+
+            for (x, (pixel, shade)) in line.iter_mut().zip(&mut shades).enumerate() {
+                for obj in &sprites {
+                    let (top, left, tile, attrs) = (obj[0], obj[1], obj[2], obj[3]);
+                    if x + 8 < left as usize || x + 8 >= left as usize + 8 {
+                        continue;
+                    }
+
+                    let mut row = ly + 16 - top as usize;
+                    if attrs & ATTR_Y_FLIP != 0 {
+                        row = height - 1 - row;
+                    }
+                    let tile = if height == 16 { tile & 0xFE } else { tile };
+                    let bank = (self.cgb && attrs & ATTR_BANK != 0) as u8;
+                    let (low, high) = self.tile_row(bank, 0x8000 + tile as u16 * 16, row as u16);
+
+                    let column = (x + 8 - left as usize) as u8;
+                    let bit = if attrs & ATTR_X_FLIP != 0 {
+                        column
+                    } else {
+                        7 - column
+                    };
+                    let index = color_index(low, high, bit);
+                    if index == 0 {
+                        continue;
+                    }
+
+                    let behind_bg = (attrs | bg_attrs[x]) & ATTR_PRIORITY != 0;
+                    if !(bg_priority && behind_bg && bg_index[x] != 0) {
+                        *pixel = self.obj_color(index, attrs);
+                        *shade = shade_index(self.obj_palette(attrs), index);
+                    }
+                    break;
+                }
+            }
+        }
+
+        for (x, &color) in line.iter().enumerate() {
+            let color = if self.color_correction {
+                correct_color(color)
+            } else {
+                color
+            };
+            self.framebuffer.set(x, ly, color);
+        }
+        self.shades[ly * SCREEN_WIDTH..][..SCREEN_WIDTH].copy_from_slice(&shades);
+    }
+
+    /// Dot the current mode ends at
+    fn mode_end(&self) -> u32 {
+        match self.mode {
+            Mode::OamScan => OAM_SCAN_DOTS,
+            Mode::Drawing => OAM_SCAN_DOTS + DRAWING_DOTS,
+            Mode::HBlank | Mode::VBlank => LINE_DOTS,
+        }
+    }
+
+    fn advance_mode(&mut self) {
+        match self.mode {
+            Mode::OamScan => self.mode = Mode::Drawing,
+            Mode::Drawing => {
+                self.render_scanline();
+                self.mode = Mode::HBlank;
+                self.hblanks += 1;
+            }
+            Mode::HBlank | Mode::VBlank => {
+                self.dot = 0;
+                self.ly += 1;
+
+                if self.ly as usize == SCREEN_HEIGHT {
+                    self.mode = Mode::VBlank;
+                    self.frames += 1;
+                } else if self.ly == TOTAL_LINES {
+                    self.ly = 0;
+                    self.window_line = 0;
+                    self.mode = Mode::OamScan;
+                } else if self.mode == Mode::HBlank {
+                    self.mode = Mode::OamScan;
+                }
+            }
+        }
+    }
+
+    fn read_stat(&self) -> u8 {
+        let coincidence = if self.ly == self.lyc { 1 << 2 } else { 0 };
+        0x80 | self.stat | coincidence | self.mode as u8
+    }
+
+    fn write_lcdc(&mut self, data: u8) {
+        let was_enabled = self.lcdc & LCDC_ENABLE != 0;
+        self.lcdc = data;
+
+        // Switching the LCD off resets the PPU to the top of the screen
+        if was_enabled && data & LCDC_ENABLE == 0 {
+            self.ly = 0;
+            self.dot = 0;
+            self.leftover = 0;
+            self.window_line = 0;
+            self.mode = Mode::HBlank;
+        } else if !was_enabled && data & LCDC_ENABLE != 0 {
+            self.mode = Mode::OamScan;
+        }
+    }
 }
 
 impl gpu::GPU for GPU {
-    fn create(vram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>) -> Self {
-        GPU { vram }
+    /// CGB models need banked VRAM to get tile attributes
+    fn create(model: Model, vram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>) -> Self {
+        GPU {
+            vram,
+            oam: [0; OAM_SIZE],
+            lcdc: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
+            mode: Mode::HBlank,
+            dot: 0,
+            leftover: 0,
+            window_line: 0,
+            framebuffer: Framebuffer::create(SCREEN_WIDTH, SCREEN_HEIGHT),
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frames: 0,
+            hblanks: 0,
+            cgb: model.is_cgb(),
+            compat: false,
+            bg_palettes: PaletteRAM::create(),
+            obj_palettes: PaletteRAM::create(),
+            color_correction: false,
+        }
     }
 
     fn deep_copy(&self) -> Vec<Self::Data> {
         self.vram.deep_copy()
     }
+
+    fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    fn hblanks(&self) -> u64 {
+        self.hblanks
+    }
+
+    fn next_event(&self) -> Option<CycleTime> {
+        if self.lcdc & LCDC_ENABLE == 0 {
+            return None;
+        }
+
+        let dots = (self.mode_end() - self.dot) as u64;
+        Some(CycleTime::new(DOT_FREQUENCY as u32, dots))
+    }
 }
 
 impl Addressable for GPU {
@@ -23,7 +530,27 @@ impl Addressable for GPU {
     type Data = u8;
 
     fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
-        Ok(0)
+        match addr {
+            0x8000..=0x9FFF => self.vram.read_byte(addr),
+            OAM_START..=OAM_END => Ok(self.oam[(addr - OAM_START) as usize]),
+            LCDC => Ok(self.lcdc),
+            STAT => Ok(self.read_stat()),
+            SCY => Ok(self.scy),
+            SCX => Ok(self.scx),
+            LY => Ok(self.ly),
+            LYC => Ok(self.lyc),
+            BGP => Ok(self.bgp),
+            OBP0 => Ok(self.obp0),
+            OBP1 => Ok(self.obp1),
+            WY => Ok(self.wy),
+            WX => Ok(self.wx),
+            BCPS if self.cgb => Ok(self.bg_palettes.read_index()),
+            BCPD if self.cgb => Ok(self.bg_palettes.read_data()),
+            OCPS if self.cgb => Ok(self.obj_palettes.read_index()),
+            OCPD if self.cgb => Ok(self.obj_palettes.read_data()),
+            // Banked VRAM answers its own bank select register
+            _ => self.vram.read_byte(addr),
+        }
     }
 
     fn write_byte(
@@ -31,10 +558,216 @@ impl Addressable for GPU {
         addr: Self::Addr,
         data: Self::Data,
     ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            0x8000..=0x9FFF => return self.vram.write_byte(addr, data),
+            OAM_START..=OAM_END => self.oam[(addr - OAM_START) as usize] = data,
+            LCDC => self.write_lcdc(data),
+            STAT => self.stat = data & 0x78,
+            SCY => self.scy = data,
+            SCX => self.scx = data,
+            // LY is read-only
+            LY => {}
+            LYC => self.lyc = data,
+            BGP => self.bgp = data,
+            OBP0 => self.obp0 = data,
+            OBP1 => self.obp1 = data,
+            WY => self.wy = data,
+            WX => self.wx = data,
+            BCPS if self.cgb => self.bg_palettes.write_index(data),
+            BCPD if self.cgb => self.bg_palettes.write_data(data),
+            OCPS if self.cgb => self.obj_palettes.write_index(data),
+            OCPD if self.cgb => self.obj_palettes.write_data(data),
+            // There is no way back to CGB mode
+            KEY0 if self.cgb => {
+                if data & KEY0_DMG_MODE != 0 {
+                    self.cgb = false;
+                    self.compat = true;
+                }
+            }
+            _ => return self.vram.write_byte(addr, data),
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for GPU {
+    /// Palette data is stored without advancing the index, and the mode
+    /// can't be switched
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            BCPD if self.cgb => self.bg_palettes.patch_data(data),
+            OCPD if self.cgb => self.obj_palettes.patch_data(data),
+            KEY0 if self.cgb => {}
+            _ => return self.write_byte(addr, data),
+        }
+
         Ok(())
     }
 }
 
 impl Timed for GPU {
-    fn catchup(&mut self, time: CycleTime) {}
+    fn catchup(&mut self, time: CycleTime) {
+        if self.lcdc & LCDC_ENABLE == 0 {
+            return;
+        }
+
+        // Partial dots carry over so catching up in small steps adds up
+        let scaled = time.cycles() * DOT_FREQUENCY + self.leftover;
+        let mut dots = scaled / time.frequency() as u64;
+        self.leftover = scaled % time.frequency() as u64;
+
+        while dots > 0 {
+            let boundary = self.mode_end();
+
+            let step = dots.min((boundary - self.dot) as u64) as u32;
+            self.dot += step;
+            dots -= step as u64;
+
+            if self.dot == boundary {
+                self.advance_mode();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn setup_gpu() -> gameboy::GPU {
+        let vram = Box::new(gameboy::RAM::<{ 8 * 1024 }>::create(0x8000));
+        let mut gpu = gameboy::GPU::create(Model::DMG, vram);
+        gpu.write_byte(0xFF40, 0x91).expect("LCDC");
+        gpu.write_byte(0xFF47, 0xE4).expect("BGP");
+        gpu
+    }
+
+    fn run_frame(gpu: &mut gameboy::GPU) {
+        // One frame is 154 lines of 456 dots
+        gpu.catchup(CycleTime::new(4194304, 70224));
+    }
+
+    #[test]
+    fn frame_timing() {
+        let mut gpu = setup_gpu();
+
+        assert_eq!(gpu.mode(), gameboy::Mode::OamScan);
+        gpu.catchup(CycleTime::new(4194304, 80));
+        assert_eq!(gpu.mode(), gameboy::Mode::Drawing);
+        gpu.catchup(CycleTime::new(4194304, 172));
+        assert_eq!(gpu.mode(), gameboy::Mode::HBlank);
+        gpu.catchup(CycleTime::new(4194304, 204));
+        assert_eq!(gpu.read_byte(0xFF44).expect("LY"), 1);
+
+        gpu.catchup(CycleTime::new(4194304, 143 * 456));
+        assert_eq!(gpu.mode(), gameboy::Mode::VBlank);
+        assert_eq!(gpu.frames(), 1);
+        assert_eq!(gpu.read_byte(0xFF41).expect("STAT") & 0x03, 1);
+
+        gpu.catchup(CycleTime::new(4194304, 10 * 456));
+        assert_eq!(gpu.read_byte(0xFF44).expect("LY"), 0);
+        assert_eq!(gpu.mode(), gameboy::Mode::OamScan);
+    }
+
+    #[test]
+    fn renders_background_and_sprites() {
+        let mut gpu = setup_gpu();
+
+        // Tile 1 is solid color 3, tile 2 has only its leftmost column in color 1
+        for row in 0..8 {
+            gpu.write_byte(0x8010 + row * 2, 0xFF).expect("tile 1 low");
+            gpu.write_byte(0x8011 + row * 2, 0xFF).expect("tile 1 high");
+            gpu.write_byte(0x8020 + row * 2, 0x80).expect("tile 2 low");
+        }
+        // Top-left background tile uses tile 1
+        gpu.write_byte(0x9800, 0x01).expect("map");
+        // Sprite 0 at screen position (16, 0) using tile 2
+        gpu.write_byte(0xFF40, 0x93).expect("LCDC with sprites");
+        gpu.write_byte(0xFF48, 0xE4).expect("OBP0");
+        for (i, b) in [16u8, 24, 2, 0].iter().enumerate() {
+            gpu.write_byte(0xFE00 + i as u16, *b).expect("OAM");
+        }
+
+        run_frame(&mut gpu);
+
+        let frame = gpu.framebuffer();
+        let black = Framebuffer::rgb555(0, 0, 0);
+        let white = Framebuffer::rgb555(31, 31, 31);
+        let light = Framebuffer::rgb555(21, 21, 21);
+
+        assert_eq!(frame.get(0, 0), black);
+        assert_eq!(frame.get(7, 7), black);
+        assert_eq!(frame.get(8, 0), white);
+        assert_eq!(frame.get(16, 0), light);
+        assert_eq!(frame.get(17, 0), white);
+        assert_eq!(frame.get(0, 8), white);
+
+        // Shades survive color correction changing the colors
+        gpu.set_color_correction(true);
+        run_frame(&mut gpu);
+        let shades = gpu.shades();
+        assert_eq!([shades[0], shades[8], shades[16], shades[17]], [3, 0, 1, 0]);
+    }
+
+    #[test]
+    fn palette_ram_auto_increment() {
+        let vram = Box::new(gameboy::BankedVRAM::create(0x8000));
+        let mut gpu = gameboy::GPU::create(Model::CGB, vram);
+
+        gpu.write_byte(0xFF68, 0x80 | 0x3E).expect("BCPS");
+        for b in [0x1F, 0x00, 0xE0, 0x03] {
+            gpu.write_byte(0xFF69, b).expect("BCPD");
+        }
+        // Wraps around into the first color of palette 0
+        assert_eq!(gpu.read_byte(0xFF68).expect("BCPS"), 0xC2);
+
+        gpu.write_byte(0xFF68, 0x3F).expect("BCPS");
+        assert_eq!(gpu.read_byte(0xFF69).expect("BCPD"), 0x00);
+        gpu.write_byte(0xFF69, 0x7C).expect("BCPD");
+        assert_eq!(gpu.read_byte(0xFF68).expect("BCPS"), 0x7F);
+    }
+
+    #[test]
+    fn renders_cgb_attributes() {
+        let mut vram = Box::new(gameboy::BankedVRAM::create(0x8000));
+
+        // Tile 1 in bank 1 has only its leftmost column in color 1
+        vram.write_byte(0xFF4F, 1).expect("VBK");
+        for row in 0..8 {
+            vram.write_byte(0x8010 + row * 2, 0x80).expect("tile 1 low");
+        }
+        // Second background tile uses tile 1 from bank 1, X flipped with
+        // palette 2
+        vram.write_byte(0x9801, 0x08 | 0x20 | 0x02)
+            .expect("attributes");
+        vram.write_byte(0xFF4F, 0).expect("VBK");
+        vram.write_byte(0x9801, 0x01).expect("map");
+
+        let mut gpu = gameboy::GPU::create(Model::CGB, vram);
+        gpu.write_byte(0xFF40, 0x91).expect("LCDC");
+        // Palette 2 color 1 is red
+        gpu.write_byte(0xFF68, 0x80 | (2 * 8 + 2)).expect("BCPS");
+        gpu.write_byte(0xFF69, 0x1F).expect("BCPD");
+        gpu.write_byte(0xFF69, 0x00).expect("BCPD");
+
+        run_frame(&mut gpu);
+
+        let frame = gpu.framebuffer();
+        let red = Framebuffer::rgb555(31, 0, 0);
+        let white = Framebuffer::rgb555(31, 31, 31);
+
+        assert_eq!(frame.get(8, 0), white);
+        assert_eq!(frame.get(15, 0), red);
+        assert_eq!(frame.get(15, 7), red);
+
+        gpu.set_color_correction(true);
+        run_frame(&mut gpu);
+        assert_eq!(gpu.framebuffer().get(15, 0), gameboy::correct_color(red));
+        assert_ne!(gameboy::correct_color(red), red);
+    }
 }
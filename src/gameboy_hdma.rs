@@ -0,0 +1,206 @@
+use crate::addressable::{AddressError, Addressable};
+use crate::inspect::Inspect;
+
+/// Source address, high and low byte
+const HDMA1: u16 = 0xFF51;
+const HDMA2: u16 = 0xFF52;
+/// Destination address in VRAM, high and low byte
+const HDMA3: u16 = 0xFF53;
+const HDMA4: u16 = 0xFF54;
+/// Length, mode and start
+const HDMA5: u16 = 0xFF55;
+
+/// Bytes copied per block, one block per HBlank in HBlank mode
+pub const HDMA_BLOCK_SIZE: u16 = 16;
+/// CPU cycles a block takes at normal speed
+pub const HDMA_BLOCK_CYCLES: u32 = 32;
+
+const HBLANK_MODE: u8 = 1 << 7;
+
+// CGB VRAM DMA. The bus performs the copies, this only tracks the transfer
+// registers and hands out the next block to copy.
+#[derive(Debug, Default)]
+pub struct Hdma {
+    source: u16,
+    dest: u16,
+    /// Blocks left to copy, kept after cancelling for the status read back
+    remaining: u8,
+    active: bool,
+    hblank: bool,
+}
+
+impl Hdma {
+    pub fn create() -> Self {
+        Hdma::default()
+    }
+
+    /// Next block of a general purpose transfer, these run to completion as
+    /// soon as they are started
+    pub fn general_block(&mut self) -> Option<(u16, u16)> {
+        if self.hblank {
+            return None;
+        }
+        self.next_block()
+    }
+
+    /// Next block of an HBlank transfer, to be copied once per HBlank
+    pub fn hblank_block(&mut self) -> Option<(u16, u16)> {
+        if !self.hblank {
+            return None;
+        }
+        self.next_block()
+    }
+
+    /// Source and destination of the next block
+    fn next_block(&mut self) -> Option<(u16, u16)> {
+        if !self.active {
+            return None;
+        }
+
+        let block = (self.source, self.dest);
+        self.source = self.source.wrapping_add(HDMA_BLOCK_SIZE);
+        // The destination wraps around within VRAM
+        self.dest = 0x8000 | (self.dest + HDMA_BLOCK_SIZE) & 0x1FF0;
+        self.remaining -= 1;
+        self.active = self.remaining > 0;
+
+        Some(block)
+    }
+}
+
+impl Addressable for Hdma {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        match addr {
+            HDMA1..=HDMA4 => Ok(0xFF),
+            // Bit 7 is clear while a transfer is active, the rest counts the
+            // blocks left minus one, 0xFF once finished
+            HDMA5 if self.remaining == 0 => Ok(0xFF),
+            HDMA5 => Ok((!self.active as u8) << 7 | (self.remaining - 1)),
+            _ => Err(AddressError::OutOfBounds(addr)),
+        }
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            HDMA1 => self.source = (data as u16) << 8 | self.source & 0x00FF,
+            HDMA2 => self.source = self.source & 0xFF00 | (data & 0xF0) as u16,
+            HDMA3 => self.dest = 0x8000 | ((data & 0x1F) as u16) << 8 | self.dest & 0x00FF,
+            HDMA4 => self.dest = 0x8000 | self.dest & 0x1F00 | (data & 0xF0) as u16,
+            // Writing a general purpose request during HBlank DMA cancels it
+            HDMA5 if self.active && self.hblank && data & HBLANK_MODE == 0 => {
+                self.active = false;
+            }
+            HDMA5 => {
+                self.remaining = (data & 0x7F) + 1;
+                self.hblank = data & HBLANK_MODE != 0;
+                self.active = true;
+            }
+            _ => return Err(AddressError::OutOfBounds(addr)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for Hdma {
+    /// HDMA5 takes the status it reads back as, without requesting a
+    /// transfer
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            HDMA5 if data == 0xFF => {
+                self.remaining = 0;
+                self.active = false;
+            }
+            HDMA5 => {
+                self.remaining = (data & 0x7F) + 1;
+                self.active = data & 0x80 == 0;
+            }
+            _ => return self.write_byte(addr, data),
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn setup_bus() -> gameboy::Bus {
+        let ram = Box::new(gameboy::BankedWRAM::create(0xC000));
+        let vram = Box::new(gameboy::BankedVRAM::create(0x8000));
+        let mut gpu = Box::new(gameboy::GPU::create(Model::CGB, vram));
+        gpu.write_byte(0xFF40, 0x80).expect("LCD on");
+        let cartridge = Box::new(gameboy::Cartridge::create(Vec::new()));
+        let mut bus = gameboy::Bus::create(Model::CGB, cartridge, ram, gpu);
+
+        for offset in 0..0x40 {
+            bus.write_byte(0xC000 + offset, offset as u8 + 1)
+                .expect("source write");
+        }
+        // Copy from 0xC000 to 0x8800
+        for (addr, data) in [
+            (0xFF51, 0xC0),
+            (0xFF52, 0x00),
+            (0xFF53, 0x08),
+            (0xFF54, 0x00),
+        ] {
+            bus.write_byte(addr, data).expect("HDMA address write");
+        }
+
+        bus
+    }
+
+    fn copied(bus: &gameboy::Bus) -> usize {
+        (0..0x40)
+            .take_while(|offset| bus.read_byte(0x8800 + offset).ok() == Some(*offset as u8 + 1))
+            .count()
+    }
+
+    #[test]
+    fn general_purpose() {
+        let mut bus = setup_bus();
+
+        // Four blocks
+        bus.write_byte(0xFF55, 0x03).expect("HDMA5 write");
+        assert_eq!(copied(&bus), 0x40);
+        assert_eq!(bus.read_byte(0xFF55).expect("HDMA5 read"), 0xFF);
+        assert_eq!(bus.take_stall(), 4 * gameboy::HDMA_BLOCK_CYCLES);
+        assert_eq!(bus.take_stall(), 0);
+    }
+
+    #[test]
+    fn hblank_transfer_and_cancel() {
+        let mut bus = setup_bus();
+
+        bus.write_byte(0xFF55, 0x80 | 0x03).expect("HDMA5 write");
+        assert_eq!(copied(&bus), 0);
+        assert_eq!(bus.read_byte(0xFF55).expect("HDMA5 read"), 0x03);
+
+        // OAM scan and drawing take 252 dots before the first HBlank
+        bus.catchup(CycleTime::new(4194304, 252));
+        assert_eq!(copied(&bus), 0x10);
+        assert_eq!(bus.read_byte(0xFF55).expect("HDMA5 read"), 0x02);
+        assert_eq!(bus.take_stall(), gameboy::HDMA_BLOCK_CYCLES);
+
+        bus.catchup(CycleTime::new(4194304, 456));
+        assert_eq!(copied(&bus), 0x20);
+
+        bus.write_byte(0xFF55, 0x00).expect("HDMA5 cancel");
+        assert_eq!(bus.read_byte(0xFF55).expect("HDMA5 read"), 0x81);
+
+        bus.catchup(CycleTime::new(4194304, 456));
+        assert_eq!(copied(&bus), 0x20);
+    }
+}
@@ -0,0 +1,146 @@
+use std::ops;
+
+/// JOYP bits selecting the directions or the action buttons, active low
+const SELECT_DIRECTIONS: u8 = 1 << 4;
+const SELECT_ACTIONS: u8 = 1 << 5;
+const SELECT_MASK: u8 = SELECT_DIRECTIONS | SELECT_ACTIONS;
+
+/// Set of held buttons. The low nibble holds the directions and the high
+/// nibble the action buttons, each in JOYP bit order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub const NONE: Buttons = Buttons(0);
+    pub const RIGHT: Buttons = Buttons(1 << 0);
+    pub const LEFT: Buttons = Buttons(1 << 1);
+    pub const UP: Buttons = Buttons(1 << 2);
+    pub const DOWN: Buttons = Buttons(1 << 3);
+    pub const A: Buttons = Buttons(1 << 4);
+    pub const B: Buttons = Buttons(1 << 5);
+    pub const SELECT: Buttons = Buttons(1 << 6);
+    pub const START: Buttons = Buttons(1 << 7);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Buttons(bits)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub const fn contains(self, buttons: Buttons) -> bool {
+        self.0 & buttons.0 == buttons.0
+    }
+
+    /// Presses or releases `buttons`, leaving the others alone
+    pub const fn with(self, buttons: Buttons, pressed: bool) -> Self {
+        if pressed {
+            Buttons(self.0 | buttons.0)
+        } else {
+            Buttons(self.0 & !buttons.0)
+        }
+    }
+}
+
+impl ops::BitOr for Buttons {
+    type Output = Buttons;
+
+    fn bitor(self, rhs: Buttons) -> Buttons {
+        Buttons(self.0 | rhs.0)
+    }
+}
+
+// The button matrix behind JOYP. The bus keeps it as the SGB listens in on
+// the same register.
+#[derive(Debug, Default)]
+pub struct Joypad {
+    buttons: Buttons,
+    /// Select bits as last written
+    select: u8,
+}
+
+impl Joypad {
+    pub fn create() -> Self {
+        Joypad {
+            buttons: Buttons::NONE,
+            select: SELECT_MASK,
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    /// Updates the held buttons, returns whether a selected button went down
+    /// and the joypad interrupt should be raised
+    pub fn set_buttons(&mut self, buttons: Buttons) -> bool {
+        let pressed = buttons.0 & !self.buttons.0;
+        self.buttons = buttons;
+        self.lines(pressed) != 0
+    }
+
+    /// Select bits of the last write, both set means nothing is selected
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    pub fn write(&mut self, data: u8) {
+        self.select = data & SELECT_MASK;
+    }
+
+    /// Pressed buttons of the selected groups pull their lines low
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | !self.lines(self.buttons.0) & 0x0F
+    }
+
+    /// Input lines `buttons` pull low with the current selection
+    fn lines(&self, buttons: u8) -> u8 {
+        let mut lines = 0;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines |= buttons & 0x0F;
+        }
+        if self.select & SELECT_ACTIONS == 0 {
+            lines |= buttons >> 4;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn button_matrix() {
+        let mut bus = gameboy::Bus::create(
+            Model::DMG,
+            Box::new(gameboy::Cartridge::create(Vec::new())),
+            Box::new(gameboy::RAM::<0x2000>::create(0xC000)),
+            Box::new(gameboy::GPU::create(
+                Model::DMG,
+                Box::new(gameboy::RAM::<0x2000>::create(0x8000)),
+            )),
+        );
+        let joyp = |bus: &mut gameboy::Bus, select: u8| {
+            bus.write_byte(0xFF00, select).expect("JOYP write");
+            bus.read_byte(0xFF00).expect("JOYP read")
+        };
+
+        bus.set_buttons(gameboy::Buttons::DOWN | gameboy::Buttons::A);
+        assert_eq!(joyp(&mut bus, 0x30), 0xFF);
+        assert_eq!(joyp(&mut bus, 0x20), 0xE7);
+        assert_eq!(joyp(&mut bus, 0x10), 0xDE);
+        assert_eq!(joyp(&mut bus, 0x00), 0xC6);
+
+        // Nothing selected, so no interrupt
+        bus.write_byte(0xFF0F, 0x00).expect("IF write");
+        joyp(&mut bus, 0x30);
+        bus.set_buttons(gameboy::Buttons::START);
+        assert_eq!(bus.read_byte(0xFF0F).expect("IF read"), 0x00);
+
+        joyp(&mut bus, 0x10);
+        bus.set_buttons(gameboy::Buttons::START | gameboy::Buttons::B);
+        assert_eq!(bus.read_byte(0xFF0F).expect("IF read"), 0x10);
+    }
+}
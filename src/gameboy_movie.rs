@@ -0,0 +1,448 @@
+use crate::bus::Bus as _;
+use crate::cartridge::Cartridge as _;
+use crate::cpu::CPU as _;
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_cartridge::Cartridge;
+use crate::gameboy_cpu::CPU;
+use crate::gameboy_joypad::Buttons;
+use crate::gameboy_pacer::frame_over;
+use crate::gameboy_system::{create_system, screen};
+use crate::model::Model;
+use crate::png::crc32;
+
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write as _;
+
+const MAGIC: &str = "gamerboy-movie 1";
+
+/// Button letters of a frame line in `Buttons` bit order: right, left, up,
+/// down, A, B, select and start
+const BUTTON_LETTERS: [char; 8] = ['R', 'L', 'U', 'D', 'A', 'B', 's', 'S'];
+
+/// CRC-32 of a frame's pixels, what movies compare to detect divergence
+pub fn frame_hash(frame: &Framebuffer) -> u32 {
+    let bytes: Vec<u8> = frame
+        .pixels()
+        .iter()
+        .flat_map(|p| p.to_le_bytes())
+        .collect();
+    crc32(&bytes)
+}
+
+/// Input and checks of one frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MovieFrame {
+    /// Buttons held during the frame
+    pub buttons: Buttons,
+    /// The machine was power cycled before the frame
+    pub reset: bool,
+    /// Hash of the screen at the end of the frame, kept every few frames
+    pub hash: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MovieError {
+    Parse {
+        line: usize,
+        reason: String,
+    },
+    /// The movie was recorded with a different ROM
+    RomMismatch {
+        expected: u32,
+        actual: u32,
+    },
+    /// The CPU stopped while playing
+    Crashed {
+        frame: u64,
+        reason: String,
+    },
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MovieError::Parse { line, reason } => write!(f, "line {line}: {reason}"),
+            MovieError::RomMismatch { expected, actual } => write!(
+                f,
+                "movie was recorded with ROM {expected:08x}, this one is {actual:08x}"
+            ),
+            MovieError::Crashed { frame, reason } => {
+                write!(f, "CPU stopped in frame {frame}: {reason}")
+            }
+        }
+    }
+}
+
+impl Error for MovieError {}
+
+/// First frame whose screen differs from the recording
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Divergence {
+    pub frame: u64,
+    pub expected: u32,
+    pub actual: u32,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "diverged in frame {}: screen hash {:08x}, recorded {:08x}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+// Joypad input per frame for bit-exact replays. Emulation is deterministic,
+// so the ROM and model plus the input reproduce a run from power-on. Movies
+// are text with a header and a line per frame, e.g. `..U.A... reset
+// #1a2b3c4d` for up and A held right after a reset, with a screen hash.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Movie {
+    pub model: Model,
+    /// CRC-32 of the ROM
+    pub rom_hash: u32,
+    /// Screens are hashed every this many frames, never if 0
+    pub hash_interval: u64,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    /// Starts an empty power-on movie
+    pub fn create(model: Model, rom: &[u8], hash_interval: u64) -> Self {
+        Movie {
+            model,
+            rom_hash: crc32(rom),
+            hash_interval,
+            frames: Vec::new(),
+        }
+    }
+
+    /// Sets up the machine the movie starts on
+    pub fn boot(&self, rom: Vec<u8>) -> Result<CPU, MovieError> {
+        let actual = crc32(&rom);
+        if actual != self.rom_hash {
+            return Err(MovieError::RomMismatch {
+                expected: self.rom_hash,
+                actual,
+            });
+        }
+
+        Ok(create_system(self.model, Cartridge::create(rom)))
+    }
+
+    /// Appends a frame that just ran with `buttons` held
+    pub fn record(&mut self, buttons: Buttons, reset: bool, cpu: &CPU) {
+        let number = self.frames.len() as u64 + 1;
+        let hash = match self.hash_interval {
+            0 => None,
+            n if number.is_multiple_of(n) => Some(frame_hash(&screen(cpu))),
+            _ => None,
+        };
+
+        self.frames.push(MovieFrame {
+            buttons,
+            reset,
+            hash,
+        });
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, MovieError> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i + 1, line.trim()));
+        let error = |line, reason: &str| MovieError::Parse {
+            line,
+            reason: reason.to_string(),
+        };
+
+        match lines.next() {
+            Some((_, MAGIC)) => {}
+            _ => return Err(error(1, "not a movie")),
+        }
+
+        let (mut model, mut rom_hash, mut hash_interval) = (None, None, 0);
+        // Missing header lines are reported where the header ends
+        let mut header_end = 1;
+
+        for (number, line) in lines.by_ref() {
+            header_end = number;
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "model" => model = Some(value.parse().map_err(|e: String| error(number, &e))?),
+                "rom" => {
+                    let hash = u32::from_str_radix(value, 16);
+                    rom_hash = Some(hash.map_err(|_| error(number, "bad ROM hash"))?);
+                }
+                // Save states can't be loaded yet
+                "start" if value == "power-on" => {}
+                "start" => return Err(error(number, "movies can only start at power-on")),
+                "hash-interval" => {
+                    hash_interval = value
+                        .parse()
+                        .map_err(|_| error(number, "bad hash interval"))?
+                }
+                "frames" => break,
+                _ => return Err(error(number, "unknown header line")),
+            }
+        }
+
+        let mut frames = Vec::new();
+        for (number, line) in lines.filter(|(_, line)| !line.is_empty()) {
+            let mut fields = line.split(' ');
+            let buttons = fields.next().unwrap_or_default();
+            if buttons.chars().count() != BUTTON_LETTERS.len() {
+                return Err(error(number, "frames start with 8 buttons"));
+            }
+
+            let mut frame = MovieFrame::default();
+            for (bit, (held, letter)) in buttons.chars().zip(BUTTON_LETTERS).enumerate() {
+                match held {
+                    '.' => {}
+                    held if held == letter => {
+                        frame.buttons = frame.buttons | Buttons::from_bits(1 << bit)
+                    }
+                    _ => return Err(error(number, "bad button")),
+                }
+            }
+            for field in fields {
+                match field.strip_prefix('#') {
+                    _ if field == "reset" => frame.reset = true,
+                    Some(hash) => {
+                        let hash = u32::from_str_radix(hash, 16);
+                        frame.hash = Some(hash.map_err(|_| error(number, "bad screen hash"))?);
+                    }
+                    None => return Err(error(number, "unknown frame field")),
+                }
+            }
+            frames.push(frame);
+        }
+
+        Ok(Movie {
+            model: model.ok_or_else(|| error(header_end, "missing model"))?,
+            rom_hash: rom_hash.ok_or_else(|| error(header_end, "missing ROM hash"))?,
+            hash_interval,
+            frames,
+        })
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{MAGIC}")?;
+        writeln!(f, "model {}", self.model)?;
+        writeln!(f, "rom {:08x}", self.rom_hash)?;
+        writeln!(f, "start power-on")?;
+        writeln!(f, "hash-interval {}", self.hash_interval)?;
+        writeln!(f, "frames")?;
+
+        for frame in &self.frames {
+            for (bit, letter) in BUTTON_LETTERS.into_iter().enumerate() {
+                let held = frame.buttons.bits() & 1 << bit != 0;
+                f.write_char(if held { letter } else { '.' })?;
+            }
+            if frame.reset {
+                f.write_str(" reset")?;
+            }
+            if let Some(hash) = frame.hash {
+                write!(f, " #{hash:08x}")?;
+            }
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Feeds a movie's input to the machine frame by frame and checks the
+// screen against the recorded hashes
+#[derive(Debug)]
+pub struct Playback {
+    movie: Movie,
+    frame: usize,
+    diverged: bool,
+}
+
+impl Playback {
+    pub fn create(movie: Movie) -> Self {
+        Playback {
+            movie,
+            frame: 0,
+            diverged: false,
+        }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// Frames played so far
+    pub fn frames(&self) -> u64 {
+        self.frame as u64
+    }
+
+    /// Input of the frame about to run, None once the movie is over
+    pub fn input(&self) -> Option<MovieFrame> {
+        self.movie.frames.get(self.frame).copied()
+    }
+
+    /// Moves on after a frame ran, reporting the first screen that differs
+    /// from the recording
+    pub fn frame_done(&mut self, cpu: &CPU) -> Option<Divergence> {
+        let frame = self.input()?;
+        self.frame += 1;
+
+        let expected = frame.hash.filter(|_| !self.diverged)?;
+        let actual = frame_hash(&screen(cpu));
+        if actual == expected {
+            return None;
+        }
+
+        self.diverged = true;
+        Some(Divergence {
+            frame: self.frame as u64,
+            expected,
+            actual,
+        })
+    }
+
+    /// Sets up the machine for the next frame, power cycling it if the
+    /// recording did
+    pub fn apply(&self, cpu: &mut CPU, rom: &[u8]) {
+        let Some(frame) = self.input() else {
+            return;
+        };
+
+        if frame.reset {
+            *cpu = create_system(self.movie.model, Cartridge::create(rom.to_vec()));
+        }
+        cpu.bus_mut().set_buttons(frame.buttons);
+    }
+}
+
+/// Runs the CPU until the frame is over, as the main loop does
+pub fn step_frame(cpu: &mut CPU) -> Result<(), String> {
+    let frame = cpu.bus().gpu().frames();
+    let mut cycles = 0;
+    while !frame_over(cpu, frame, cycles) {
+        cycles += cpu.step().map_err(|err| err.to_string())?;
+    }
+    Ok(())
+}
+
+/// Plays a movie without a frontend as fast as possible and returns the
+/// first divergence, if any
+pub fn verify_movie(movie: Movie, rom: Vec<u8>) -> Result<Option<Divergence>, MovieError> {
+    let mut cpu = movie.boot(rom.clone())?;
+    let mut playback = Playback::create(movie);
+
+    while playback.input().is_some() {
+        playback.apply(&mut cpu, &rom);
+
+        step_frame(&mut cpu).map_err(|reason| MovieError::Crashed {
+            frame: playback.frames() + 1,
+            reason,
+        })?;
+
+        if let Some(divergence) = playback.frame_done(&cpu) {
+            return Ok(Some(divergence));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// NOPs, enough for two frames
+    fn rom() -> Vec<u8> {
+        vec![0x00; 0x8000]
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let mut movie = gameboy::Movie::create(Model::CGB, b"rom", 2);
+        movie.frames = vec![
+            gameboy::MovieFrame::default(),
+            gameboy::MovieFrame {
+                buttons: gameboy::Buttons::UP | gameboy::Buttons::A | gameboy::Buttons::START,
+                reset: true,
+                hash: Some(0x1A2B3C4D),
+            },
+        ];
+
+        let text = movie.to_string();
+        assert_eq!(
+            text,
+            "gamerboy-movie 1\nmodel cgb\nrom 79520fa1\nstart power-on\n\
+             hash-interval 2\nframes\n........\n..U.A..S reset #1a2b3c4d\n"
+        );
+        assert_eq!(gameboy::Movie::parse(&text), Ok(movie));
+
+        let bad = text.replace("..U.A..S", "..X.A..S");
+        assert_eq!(
+            gameboy::Movie::parse(&bad),
+            Err(gameboy::MovieError::Parse {
+                line: 8,
+                reason: "bad button".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn rom_and_start_checks() {
+        let movie = gameboy::Movie::create(Model::DMG, &rom(), 0);
+        assert!(movie.boot(rom()).is_ok());
+        assert!(matches!(
+            movie.boot(vec![0xFF; 0x8000]),
+            Err(gameboy::MovieError::RomMismatch { .. })
+        ));
+
+        // Save states can't be loaded yet, missing lines are reported
+        // where the header ends
+        let text = movie.to_string();
+        let error = |line, reason: &str| {
+            Err(gameboy::MovieError::Parse {
+                line,
+                reason: reason.to_string(),
+            })
+        };
+        assert_eq!(
+            gameboy::Movie::parse(&text.replace("power-on", "state 12ab")),
+            error(4, "movies can only start at power-on")
+        );
+        assert_eq!(
+            gameboy::Movie::parse(&text.replace("model dmg\n", "")),
+            error(5, "missing model")
+        );
+    }
+
+    #[test]
+    fn replays_and_finds_divergence() {
+        let mut movie = gameboy::Movie::create(Model::DMG, &rom(), 2);
+        let mut cpu = movie.boot(rom()).expect("boot");
+        for buttons in [gameboy::Buttons::A, gameboy::Buttons::NONE] {
+            cpu.bus_mut().set_buttons(buttons);
+            gameboy::step_frame(&mut cpu).expect("frame");
+            movie.record(buttons, false, &cpu);
+        }
+        assert_eq!(movie.frames[0].hash, None);
+        assert!(movie.frames[1].hash.is_some());
+
+        let text = movie.to_string();
+        let movie = gameboy::Movie::parse(&text).expect("parse");
+        assert_eq!(gameboy::verify_movie(movie.clone(), rom()), Ok(None));
+
+        let mut tampered = movie.clone();
+        tampered.frames[1].hash = Some(0);
+        let divergence = gameboy::verify_movie(tampered, rom())
+            .expect("play")
+            .expect("divergence");
+        assert_eq!(divergence.frame, 2);
+        assert_eq!(divergence.expected, 0);
+    }
+}
@@ -0,0 +1,260 @@
+use crate::bus::Bus as _;
+use crate::cpu::CPU as _;
+use crate::gameboy_cpu::CPU;
+use crate::model::Model;
+use crate::timed::CycleTime;
+
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Cycles from one VBlank to the next at normal speed, about 59.73 Hz on
+/// the DMG clock
+pub const FRAME_CYCLES: u32 = 70224;
+
+/// Falling further behind than this drops the missed time instead of
+/// racing to catch up
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+/// How fast emulation runs relative to the real hardware
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Speed {
+    /// As fast as the host allows
+    Unthrottled,
+    /// A multiple of the hardware speed, 2.0 is fast forward and 0.5 slow
+    /// motion
+    Times(f64),
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Speed::Times(1.0)
+    }
+}
+
+impl fmt::Display for Speed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Speed::Unthrottled => f.write_str("unthrottled"),
+            Speed::Times(multiplier) => write!(f, "{multiplier}x"),
+        }
+    }
+}
+
+impl FromStr for Speed {
+    type Err = String;
+
+    /// Accepts "unthrottled" or a positive multiplier such as "2" or "0.5x"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("unthrottled") {
+            return Ok(Speed::Unthrottled);
+        }
+
+        match s.trim_end_matches('x').parse::<f64>() {
+            Ok(multiplier) if multiplier.is_finite() && multiplier > 0.0 => {
+                Ok(Speed::Times(multiplier))
+            }
+            _ => Err(format!("bad speed {s}")),
+        }
+    }
+}
+
+/// Cycles the CPU runs per frame, twice as many in CGB double speed
+pub fn frame_cycles(cpu: &CPU) -> u32 {
+    (FRAME_CYCLES as u64 * cpu.frequency() as u64 / Model::CYCLE_CLOCK as u64) as u32
+}
+
+/// Whether a frame that started at GPU frame count `frame` and has run for
+/// `cycles` is over. Frames end at VBlank, with the LCD off there is none
+/// and a frame's worth of cycles ends them instead.
+pub fn frame_over(cpu: &CPU, frame: u64, cycles: u32) -> bool {
+    let gpu = cpu.bus().gpu();
+    gpu.frames() != frame || gpu.next_event().is_none() && cycles >= frame_cycles(cpu)
+}
+
+// Keeps emulated time in step with the wall clock. Emulated time is summed
+// up from the start rather than per frame so rounding and oversleeping
+// don't add up.
+#[derive(Debug)]
+pub struct FramePacer {
+    speed: Speed,
+    /// Frames skipped after each shown one
+    frame_skip: u32,
+    frames: u64,
+    start: Instant,
+    /// Wall clock time the frames run so far should have taken
+    emulated: Duration,
+}
+
+impl FramePacer {
+    pub fn create(speed: Speed, frame_skip: u32) -> Self {
+        FramePacer {
+            speed,
+            frame_skip,
+            frames: 0,
+            start: Instant::now(),
+            emulated: Duration::ZERO,
+        }
+    }
+
+    pub fn speed(&self) -> Speed {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: Speed) {
+        self.speed = speed;
+        self.restart();
+    }
+
+    /// Frames completed so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Counts a finished frame, returns whether it should be shown or is
+    /// skipped
+    pub fn frame_done(&mut self) -> bool {
+        let shown = self.frames.is_multiple_of(self.frame_skip as u64 + 1);
+        self.frames += 1;
+        shown
+    }
+
+    /// Sleeps until the wall clock catches up with a frame that took `time`
+    pub fn wait(&mut self, time: CycleTime) {
+        let delay = self.delay(time, self.start.elapsed());
+        if !delay.is_zero() {
+            thread::sleep(delay);
+        }
+    }
+
+    /// Time left to wait after emulating `time`, `elapsed` wall clock time
+    /// after starting
+    fn delay(&mut self, time: CycleTime, elapsed: Duration) -> Duration {
+        let Speed::Times(multiplier) = self.speed else {
+            return Duration::ZERO;
+        };
+
+        self.emulated += Duration::from_nanos(time.nanos()).div_f64(multiplier);
+        if elapsed > self.emulated + MAX_LAG {
+            self.emulated = elapsed;
+        }
+
+        self.emulated.saturating_sub(elapsed)
+    }
+
+    /// Starts counting time afresh, e.g. after a pause
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+        self.emulated = Duration::ZERO;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::time::Duration;
+
+    fn frame() -> CycleTime {
+        CycleTime::new(4194304, gameboy::FRAME_CYCLES as u64)
+    }
+
+    #[test]
+    fn parse_speed() {
+        assert_eq!("2".parse(), Ok(gameboy::Speed::Times(2.0)));
+        assert_eq!("0.5x".parse(), Ok(gameboy::Speed::Times(0.5)));
+        assert_eq!("Unthrottled".parse(), Ok(gameboy::Speed::Unthrottled));
+        assert!("0".parse::<gameboy::Speed>().is_err());
+        assert!("fast".parse::<gameboy::Speed>().is_err());
+    }
+
+    #[test]
+    fn paces_at_frame_rate() {
+        let mut pacer = gameboy::FramePacer::create(gameboy::Speed::default(), 0);
+
+        // 70224 cycles at 4194304 Hz take 16742706 ns, 59.73 Hz
+        assert_eq!(
+            pacer.delay(frame(), Duration::from_micros(2000)),
+            Duration::from_nanos(14_742_706)
+        );
+        // Time is kept from the start, a late frame makes the next one shorter
+        assert_eq!(
+            pacer.delay(frame(), Duration::from_micros(20000)),
+            Duration::from_nanos(13_485_412)
+        );
+        // Far behind the missed time is dropped
+        assert_eq!(pacer.delay(frame(), Duration::from_secs(1)), Duration::ZERO);
+        assert_eq!(
+            pacer.delay(frame(), Duration::from_secs(1)),
+            Duration::from_nanos(16_742_706)
+        );
+    }
+
+    #[test]
+    fn speed_multipliers() {
+        let mut pacer = gameboy::FramePacer::create(gameboy::Speed::Times(2.0), 0);
+        assert_eq!(
+            pacer.delay(frame(), Duration::ZERO),
+            Duration::from_nanos(8_371_353)
+        );
+
+        pacer.set_speed(gameboy::Speed::Times(0.5));
+        assert_eq!(
+            pacer.delay(frame(), Duration::ZERO),
+            Duration::from_nanos(33_485_412)
+        );
+
+        pacer.set_speed(gameboy::Speed::Unthrottled);
+        assert_eq!(pacer.delay(frame(), Duration::ZERO), Duration::ZERO);
+    }
+
+    #[test]
+    fn frame_skip() {
+        let mut pacer = gameboy::FramePacer::create(gameboy::Speed::default(), 2);
+        let shown: Vec<bool> = (0..6).map(|_| pacer.frame_done()).collect();
+        assert_eq!(shown, [true, false, false, true, false, false]);
+        assert_eq!(pacer.frames(), 6);
+    }
+
+    #[test]
+    fn sgb_frames_take_as_many_cycles() {
+        // The SGB clock only shortens frames against the wall clock, the
+        // PPU still needs the same cycles
+        for model in [Model::DMG, Model::SGB] {
+            let cpu = gameboy::create_system(model, gameboy::Cartridge::create(vec![0; 0x8000]));
+            assert_eq!(cpu.frequency(), Model::CYCLE_CLOCK);
+            assert_eq!(gameboy::frame_cycles(&cpu), gameboy::FRAME_CYCLES);
+        }
+    }
+
+    #[test]
+    fn frames_end_at_vblank() {
+        let mut cpu =
+            gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0; 0x8000]));
+        let ly = |cpu: &gameboy::CPU| cpu.bus().read_byte(0xFF44).expect("LY");
+        // Keeps to the NOPs, a frame of them runs through half the ROM
+        let step_frame = |cpu: &mut gameboy::CPU| {
+            cpu.set_register(gameboy::Reg::PC, 0x0150).expect("PC");
+            gameboy::step_frame(cpu).expect("frame");
+        };
+
+        step_frame(&mut cpu);
+        assert_eq!(ly(&cpu), 144);
+
+        // Without the LCD a frame's worth of cycles ends the frame
+        cpu.bus_apply(|bus| bus.write_byte(0xFF40, 0x11).expect("LCD off"));
+        let frames = cpu.bus().gpu().frames();
+        step_frame(&mut cpu);
+        assert_eq!(cpu.bus().gpu().frames(), frames);
+        assert_eq!(ly(&cpu), 0);
+
+        // Turning it back on shifts the GPU's phase, frames still end at
+        // VBlank
+        cpu.bus_apply(|bus| bus.write_byte(0xFF40, 0x91).expect("LCD on"));
+        for _ in 0..2 {
+            step_frame(&mut cpu);
+            assert_eq!(ly(&cpu), 144);
+        }
+        assert_eq!(cpu.bus().gpu().frames(), frames + 2);
+    }
+}
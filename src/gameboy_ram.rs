@@ -1,6 +1,9 @@
 use crate::addressable::{AddressError, Addressable};
+use crate::inspect::Inspect;
 use crate::ram;
 
+use std::ops::Range;
+
 // Gameboy RAM; 16-bit address space, 8-bit memory width
 #[derive(Debug)]
 pub struct RAM<const SIZE: usize> {
@@ -39,8 +42,47 @@ impl<const SIZE: usize> Addressable for RAM<SIZE> {
 
         Ok(())
     }
+
+    fn read_slice(
+        &self,
+        addr: Self::Addr,
+        buf: &mut [Self::Data],
+    ) -> Result<(), AddressError<Self::Addr>> {
+        let range = self.offsets(addr, buf.len())?;
+        buf.copy_from_slice(&self.mem[range]);
+        Ok(())
+    }
+
+    fn write_slice(
+        &mut self,
+        addr: Self::Addr,
+        data: &[Self::Data],
+    ) -> Result<(), AddressError<Self::Addr>> {
+        let range = self.offsets(addr, data.len())?;
+        self.mem[range].copy_from_slice(data);
+        Ok(())
+    }
+}
+
+impl<const SIZE: usize> RAM<SIZE> {
+    /// Offsets into `mem` of `len` bytes starting at `addr`
+    fn offsets(&self, addr: u16, len: usize) -> Result<Range<usize>, AddressError<u16>> {
+        if addr < self.start_addr {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        let start = (addr - self.start_addr) as usize;
+        if start + len > SIZE {
+            // The first address past the end
+            return Err(AddressError::OutOfBounds(self.end_addr.wrapping_add(1)));
+        }
+
+        Ok(start..start + len)
+    }
 }
 
+impl<const SIZE: usize> Inspect for RAM<SIZE> {}
+
 impl<const SIZE: usize> ram::RAM for RAM<SIZE> {
     fn create(start: Self::Addr) -> Self {
         let size: Self::Addr = SIZE.try_into().expect("RAM size overflowed addspace");
@@ -55,3 +97,228 @@ impl<const SIZE: usize> ram::RAM for RAM<SIZE> {
         Vec::from(self.mem)
     }
 }
+
+/// VRAM bank select register, CGB only
+const VBK: u16 = 0xFF4F;
+/// WRAM bank select register, CGB only
+const SVBK: u16 = 0xFF70;
+
+const VRAM_BANK_SIZE: usize = 0x2000;
+const VRAM_BANKS: usize = 2;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
+/// CGB video RAM, two 8 KiB banks switched through VBK
+#[derive(Debug)]
+pub struct BankedVRAM {
+    start_addr: u16,
+    bank: usize,
+    mem: Box<[[u8; VRAM_BANK_SIZE]; VRAM_BANKS]>,
+}
+
+impl Addressable for BankedVRAM {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        match addr {
+            VBK => Ok(0xFE | self.bank as u8),
+            _ => ram::RAM::read_bank(self, self.bank, addr),
+        }
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        if addr == VBK {
+            self.bank = (data & 0x01) as usize;
+            return Ok(());
+        }
+
+        let offset = addr.wrapping_sub(self.start_addr) as usize;
+        if offset >= VRAM_BANK_SIZE {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        self.mem[self.bank][offset] = data;
+
+        Ok(())
+    }
+}
+
+impl Inspect for BankedVRAM {}
+
+impl ram::RAM for BankedVRAM {
+    fn create(start: Self::Addr) -> Self {
+        BankedVRAM {
+            start_addr: start,
+            bank: 0,
+            mem: Box::new([[0; VRAM_BANK_SIZE]; VRAM_BANKS]),
+        }
+    }
+
+    /// Both banks, bank 0 first
+    fn deep_copy(&self) -> Vec<Self::Data> {
+        self.mem.concat()
+    }
+
+    fn banks(&self) -> usize {
+        VRAM_BANKS
+    }
+
+    /// Lets the PPU fetch tile attributes and data independent of VBK
+    fn read_bank(
+        &self,
+        bank: usize,
+        addr: Self::Addr,
+    ) -> Result<Self::Data, AddressError<Self::Addr>> {
+        let offset = addr.wrapping_sub(self.start_addr) as usize;
+        if bank >= VRAM_BANKS || offset >= VRAM_BANK_SIZE {
+            return Err(AddressError::OutOfBounds(addr));
+        }
+
+        Ok(self.mem[bank][offset])
+    }
+}
+
+/// CGB work RAM, eight 4 KiB banks. The first half of the address range is
+/// always bank 0, the second half is switched through SVBK where selecting
+/// bank 0 selects bank 1.
+#[derive(Debug)]
+pub struct BankedWRAM {
+    start_addr: u16,
+    bank: usize,
+    mem: Box<[[u8; WRAM_BANK_SIZE]; WRAM_BANKS]>,
+}
+
+impl BankedWRAM {
+    fn locate(&self, addr: u16) -> Result<(usize, usize), AddressError<u16>> {
+        let offset = addr.wrapping_sub(self.start_addr) as usize;
+
+        match offset / WRAM_BANK_SIZE {
+            0 => Ok((0, offset)),
+            1 => Ok((self.bank, offset - WRAM_BANK_SIZE)),
+            _ => Err(AddressError::OutOfBounds(addr)),
+        }
+    }
+}
+
+impl Addressable for BankedWRAM {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        if addr == SVBK {
+            return Ok(0xF8 | self.bank as u8);
+        }
+
+        let (bank, offset) = self.locate(addr)?;
+        Ok(self.mem[bank][offset])
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        if addr == SVBK {
+            self.bank = ((data & 0x07) as usize).max(1);
+            return Ok(());
+        }
+
+        let (bank, offset) = self.locate(addr)?;
+        self.mem[bank][offset] = data;
+
+        Ok(())
+    }
+}
+
+impl Inspect for BankedWRAM {}
+
+impl ram::RAM for BankedWRAM {
+    fn create(start: Self::Addr) -> Self {
+        BankedWRAM {
+            start_addr: start,
+            bank: 1,
+            mem: Box::new([[0; WRAM_BANK_SIZE]; WRAM_BANKS]),
+        }
+    }
+
+    /// All eight banks in order
+    fn deep_copy(&self) -> Vec<Self::Data> {
+        self.mem.concat()
+    }
+
+    fn banks(&self) -> usize {
+        WRAM_BANKS
+    }
+
+    fn bank(&self, addr: Self::Addr) -> usize {
+        self.locate(addr).map_or(0, |(bank, _)| bank)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn vram_banking() {
+        let mut vram = gameboy::BankedVRAM::create(0x8000);
+
+        vram.write_byte(0x8000, 0xAA).expect("bank 0 write");
+        vram.write_byte(0xFF4F, 0x01).expect("VBK write");
+        assert_eq!(vram.read_byte(0xFF4F).expect("VBK read"), 0xFF);
+        assert_eq!(vram.read_byte(0x8000).expect("bank 1 read"), 0x00);
+
+        vram.write_byte(0x9FFF, 0xBB).expect("bank 1 write");
+        assert_eq!(vram.read_bank(0, 0x8000).expect("bank 0 read"), 0xAA);
+        assert_eq!(vram.read_bank(1, 0x9FFF).expect("bank 1 read"), 0xBB);
+        assert!(vram.read_byte(0xA000).is_err());
+        assert_eq!(vram.deep_copy().len(), 16 * 1024);
+    }
+
+    #[test]
+    fn wram_banking() {
+        let mut wram = gameboy::BankedWRAM::create(0xC000);
+
+        for bank in 1..8 {
+            wram.write_byte(0xFF70, bank).expect("SVBK write");
+            wram.write_byte(0xD000, bank * 0x10).expect("banked write");
+        }
+        wram.write_byte(0xC000, 0x42).expect("bank 0 write");
+
+        wram.write_byte(0xFF70, 0x03).expect("SVBK write");
+        assert_eq!(wram.read_byte(0xD000).expect("bank 3 read"), 0x30);
+        assert_eq!(wram.read_byte(0xC000).expect("bank 0 read"), 0x42);
+
+        // Bank 0 can't be mapped into the switchable half
+        wram.write_byte(0xFF70, 0x00).expect("SVBK write");
+        assert_eq!(wram.read_byte(0xFF70).expect("SVBK read"), 0xF9);
+        assert_eq!(wram.read_byte(0xD000).expect("bank 1 read"), 0x10);
+        assert!(wram.write_byte(0xE000, 0).is_err());
+    }
+
+    #[test]
+    fn words_and_slices() {
+        let mut hram = gameboy::RAM::<0x7F>::create(0xFF80);
+
+        hram.write_word(0xFF80, 0x1234).expect("word write");
+        assert_eq!(hram.read_byte(0xFF80).expect("low byte"), 0x34);
+        assert_eq!(hram.read_word(0xFF80).expect("word read"), 0x1234);
+
+        hram.write_slice(0xFF82, &[1, 2, 3]).expect("slice write");
+        let mut buf = [0; 5];
+        hram.read_slice(0xFF80, &mut buf).expect("slice read");
+        assert_eq!(buf, [0x34, 0x12, 1, 2, 3]);
+        assert_eq!(hram.inspect(0xFF84).expect("inspect"), 3);
+        assert_eq!(hram.peek(0xFF84).expect("peek"), 3);
+
+        // Slices can't run past the end
+        assert!(hram.read_slice(0xFFFC, &mut buf).is_err());
+        assert!(hram.write_slice(0xFF7F, &[0]).is_err());
+        assert!(hram.read_word(0xFFFE).is_err());
+    }
+}
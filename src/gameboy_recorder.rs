@@ -0,0 +1,290 @@
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_gpu::{DMG_SHADES, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::gameboy_pacer::FRAME_CYCLES;
+use crate::gameboy_sgb::{SGB_HEIGHT, SGB_WIDTH};
+use crate::gif::GifWriter;
+use crate::model::Model;
+use crate::wav::WavWriter;
+use crate::y4m::Y4mWriter;
+
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::Path;
+
+/// Shown whenever an audio track is asked for, until the APU exists
+pub const SILENT_AUDIO: &str = "sound isn't emulated yet, the audio track is silent";
+/// Sample rate of the audio track
+const AUDIO_RATE: u32 = 48000;
+const AUDIO_CHANNELS: u16 = 2;
+
+/// Most GIF players show delays below 2/100 s as 1/10 s, so only every
+/// second frame goes into a GIF
+const GIF_FRAME_STEP: u64 = 2;
+
+/// Size of the picture a model shows, SGB frames include the border
+pub fn screen_size(model: Model) -> (usize, usize) {
+    match model {
+        Model::SGB => (SGB_WIDTH, SGB_HEIGHT),
+        _ => (SCREEN_WIDTH, SCREEN_HEIGHT),
+    }
+}
+
+/// How frames are mapped onto the 256 colors a GIF can hold
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GifPalette {
+    /// The four DMG shades, exact for monochrome models
+    Dmg,
+    /// 3 bits of red and green and 2 of blue
+    Rgb332,
+}
+
+impl GifPalette {
+    pub fn for_model(model: Model) -> Self {
+        match model {
+            Model::DMG | Model::MGB => GifPalette::Dmg,
+            _ => GifPalette::Rgb332,
+        }
+    }
+
+    fn colors(self) -> Vec<u16> {
+        match self {
+            GifPalette::Dmg => DMG_SHADES.to_vec(),
+            GifPalette::Rgb332 => (0..=255u8)
+                .map(|index| {
+                    let [r, g, b] = [index >> 5, index >> 2 & 0x07, index & 0x03];
+                    Framebuffer::rgb555(r << 2 | r >> 1, g << 2 | g >> 1, b << 3 | b << 1 | b >> 1)
+                })
+                .collect(),
+        }
+    }
+
+    /// Palette indexes of a frame, DMG shades are taken as they are
+    fn fill(self, indexes: &mut [u8], frame: &Framebuffer, shades: &[u8]) {
+        match self {
+            GifPalette::Dmg => indexes.copy_from_slice(shades),
+            GifPalette::Rgb332 => {
+                for (index, &color) in indexes.iter_mut().zip(frame.pixels()) {
+                    let [r, g, b] = [color & 0x1F, color >> 5 & 0x1F, color >> 10 & 0x1F];
+                    *index = ((r >> 2) << 5 | (g >> 2) << 2 | b >> 3) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Output {
+    Gif {
+        gif: GifWriter<BufWriter<File>>,
+        palette: GifPalette,
+        indexes: Vec<u8>,
+        /// Hundredths of a second of frames written so far, exact and as
+        /// given out in delays
+        time: f64,
+        delays: u64,
+    },
+    Y4m {
+        video: Y4mWriter<BufWriter<File>>,
+        audio: Option<WavWriter<BufWriter<File>>>,
+        /// Audio samples per channel owed to the track, fractional part
+        /// carried over between frames
+        samples: f64,
+    },
+}
+
+// Records frames to an animated GIF or a raw Y4M video with an optional WAV
+// track next to it, to be muxed with e.g. ffmpeg. The APU isn't emulated
+// yet, so the audio track is silence of the right length.
+#[derive(Debug)]
+pub struct Recorder {
+    output: Output,
+    /// Seconds per frame
+    frame_time: f64,
+    frames: u64,
+}
+
+impl Recorder {
+    /// Starts recording to `path`, the format follows the extension. With
+    /// `audio` Y4M recordings get a WAV file of the same name.
+    pub fn create(path: &Path, model: Model, audio: bool) -> io::Result<Self> {
+        let (width, height) = screen_size(model);
+        let file = || File::create(path).map(BufWriter::new);
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+
+        let output = match extension.to_ascii_lowercase().as_str() {
+            "gif" => {
+                let palette = GifPalette::for_model(model);
+                Output::Gif {
+                    gif: GifWriter::create(
+                        file()?,
+                        width as u16,
+                        height as u16,
+                        &palette.colors(),
+                    )?,
+                    palette,
+                    indexes: vec![0; width * height],
+                    time: 0.0,
+                    delays: 0,
+                }
+            }
+            "y4m" => {
+                let audio = match audio {
+                    true => {
+                        let wav = BufWriter::new(File::create(path.with_extension("wav"))?);
+                        Some(WavWriter::create(wav, AUDIO_RATE, AUDIO_CHANNELS)?)
+                    }
+                    false => None,
+                };
+                Output::Y4m {
+                    video: Y4mWriter::create(file()?, width, height, model.clock(), FRAME_CYCLES)?,
+                    audio,
+                    samples: 0.0,
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "recordings are .gif or .y4m files",
+                ))
+            }
+        };
+
+        Ok(Recorder {
+            output,
+            frame_time: FRAME_CYCLES as f64 / model.clock() as f64,
+            frames: 0,
+        })
+    }
+
+    /// Frames recorded so far, including those left out of a GIF
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Records a frame, along with the GPU's shades of it monochrome GIFs
+    /// are written from
+    pub fn record(&mut self, frame: &Framebuffer, shades: &[u8]) -> io::Result<()> {
+        let frame_number = self.frames;
+        self.frames += 1;
+
+        match &mut self.output {
+            Output::Gif {
+                gif,
+                palette,
+                indexes,
+                time,
+                delays,
+            } => {
+                if !frame_number.is_multiple_of(GIF_FRAME_STEP) {
+                    return Ok(());
+                }
+
+                palette.fill(indexes, frame, shades);
+
+                // Rounding the running total keeps the frame rate exact
+                *time += GIF_FRAME_STEP as f64 * self.frame_time * 100.0;
+                let delay = time.round() as u64 - *delays;
+                *delays += delay;
+                gif.write_frame(indexes, delay as u16)
+            }
+            Output::Y4m {
+                video,
+                audio,
+                samples,
+            } => {
+                video.write_frame(frame)?;
+
+                if let Some(audio) = audio {
+                    *samples += self.frame_time * AUDIO_RATE as f64;
+                    let count = samples.floor();
+                    *samples -= count;
+                    audio.write_samples(&vec![0; count as usize * AUDIO_CHANNELS as usize])?;
+                }
+
+                Ok(())
+            }
+        }
+    }
+
+    /// Completes the files, a recording is cut short if this isn't called
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Gif { gif, .. } => gif.finish().map(drop),
+            Output::Y4m { video, audio, .. } => {
+                video.finish()?;
+                if let Some(audio) = audio {
+                    audio.finish()?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::fs;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("gamerboy-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn gif_recording() {
+        let path = temp_path("recording.gif");
+        let mut recorder = gameboy::Recorder::create(&path, Model::DMG, false).expect("create");
+
+        let mut frame = Framebuffer::create(160, 144);
+        frame.set(0, 0, Framebuffer::rgb555(10, 10, 10));
+        let mut shades = vec![0; 160 * 144];
+        shades[0] = 2;
+        for _ in 0..4 {
+            recorder.record(&frame, &shades).expect("record");
+        }
+        assert_eq!(recorder.frames(), 4);
+        recorder.finish().expect("finish");
+
+        let bytes = fs::read(&path).expect("read GIF");
+        fs::remove_file(&path).expect("remove GIF");
+        // 4 color palette, then two frames of two DMG frames each
+        assert_eq!(bytes[10], 0xF1);
+        let delays: Vec<u16> = bytes
+            .windows(4)
+            .enumerate()
+            .filter(|(_, w)| w == &[0x21, 0xF9, 0x04, 0x00])
+            .map(|(i, _)| u16::from_le_bytes([bytes[i + 4], bytes[i + 5]]))
+            .collect();
+        assert_eq!(delays, [3, 4]);
+    }
+
+    #[test]
+    fn y4m_recording_with_audio() {
+        let path = temp_path("recording.y4m");
+        let mut recorder = gameboy::Recorder::create(&path, Model::CGB, true).expect("create");
+
+        let frame = Framebuffer::create(160, 144);
+        let shades = vec![0; 160 * 144];
+        for _ in 0..60 {
+            recorder.record(&frame, &shades).expect("record");
+        }
+        recorder.finish().expect("finish");
+
+        let video = fs::read(&path).expect("read video");
+        let audio = fs::read(path.with_extension("wav")).expect("read audio");
+        fs::remove_file(&path).expect("remove video");
+        fs::remove_file(path.with_extension("wav")).expect("remove audio");
+
+        let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444\n";
+        assert_eq!(video.len(), header.len() + 60 * (6 + 160 * 144 * 3));
+        // 60 frames are just over a second of stereo samples
+        assert_eq!(audio.len(), 44 + 48218 * 4);
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let path = temp_path("recording.avi");
+        assert!(gameboy::Recorder::create(&path, Model::DMG, false).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+use crate::bus::Bus as _;
+use crate::cpu::CPU as _;
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_cartridge::Cartridge;
+use crate::gameboy_cpu::CPU;
+use crate::gameboy_system::{create_system, screen};
+use crate::model::Model;
+
+use std::error::Error;
+use std::fmt;
+
+/// Cycles the DMG takes to draw one frame
+const FRAME_CYCLES: u64 = 70224;
+
+/// Mismatching pixels are painted red in the diff image
+const DIFF_MISMATCH: u16 = Framebuffer::rgb555(31, 0, 0);
+
+#[derive(Debug)]
+pub enum ScreenshotError {
+    /// The CPU stopped before the requested frames were drawn
+    Crashed(String),
+    /// No frame completed in time, usually because the LCD stayed off
+    Timeout,
+}
+
+impl fmt::Display for ScreenshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScreenshotError::Crashed(reason) => write!(f, "CPU stopped: {reason}"),
+            ScreenshotError::Timeout => f.write_str("no frame was drawn, is the LCD off?"),
+        }
+    }
+}
+
+impl Error for ScreenshotError {}
+
+/// Result of comparing a frame against a reference image
+#[derive(Debug)]
+pub struct Comparison {
+    /// Number of pixels that differ, every pixel if the sizes differ
+    pub mismatched: usize,
+    /// The frame with matching pixels dimmed and mismatching ones in red
+    pub diff: Framebuffer,
+}
+
+impl Comparison {
+    pub fn matches(&self) -> bool {
+        self.mismatched == 0
+    }
+}
+
+/// Steps the CPU until the GPU has completed `frames` more frames. Gives up
+/// if a frame takes more than twice as long as it should.
+pub fn run_frames(cpu: &mut CPU, frames: u64) -> Result<(), ScreenshotError> {
+    let target = cpu.bus().gpu().frames() + frames;
+    let mut cycles = 0u64;
+
+    while cpu.bus().gpu().frames() < target {
+        if cycles > (frames + 1) * 2 * FRAME_CYCLES {
+            return Err(ScreenshotError::Timeout);
+        }
+
+        cycles += cpu
+            .step()
+            .map_err(|err| ScreenshotError::Crashed(err.to_string()))? as u64;
+    }
+
+    Ok(())
+}
+
+/// Runs a cartridge on a freshly booted machine for `frames` frames and
+/// returns the last one drawn, composited with the border on the SGB
+pub fn screenshot_rom(
+    model: Model,
+    cartridge: Cartridge,
+    frames: u64,
+) -> Result<Framebuffer, ScreenshotError> {
+    let mut cpu = create_system(model, cartridge);
+
+    run_frames(&mut cpu, frames)?;
+
+    Ok(screen(&cpu))
+}
+
+/// Compares two frames pixel by pixel. References are expected to be
+/// quantized to 15-bit color already, as `decode_png` does.
+pub fn compare(actual: &Framebuffer, reference: &Framebuffer) -> Comparison {
+    let mut diff = Framebuffer::create(actual.width(), actual.height());
+    let same_size = (actual.width(), actual.height()) == (reference.width(), reference.height());
+    let mut mismatched = 0;
+
+    for y in 0..actual.height() {
+        for x in 0..actual.width() {
+            let color = actual.get(x, y);
+
+            if same_size && color == reference.get(x, y) {
+                // Quarter brightness keeps the picture recognizable
+                let [r, g, b] = [color & 0x1F, color >> 5 & 0x1F, color >> 10 & 0x1F];
+                diff.set(
+                    x,
+                    y,
+                    Framebuffer::rgb555(r as u8 / 4, g as u8 / 4, b as u8 / 4),
+                );
+            } else {
+                diff.set(x, y, DIFF_MISMATCH);
+                mismatched += 1;
+            }
+        }
+    }
+
+    if !same_size {
+        mismatched = mismatched.max(reference.width() * reference.height());
+    }
+
+    Comparison { mismatched, diff }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Executes an illegal opcode straight away
+    fn illegal_rom() -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100] = 0xD3;
+        rom
+    }
+
+    #[test]
+    fn compare_frames() {
+        let mut actual = Framebuffer::create(2, 2);
+        let reference = actual.clone();
+        assert!(gameboy::compare(&actual, &reference).matches());
+
+        actual.set(1, 0, Framebuffer::rgb555(31, 31, 31));
+        let comparison = gameboy::compare(&actual, &reference);
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.diff.get(1, 0), Framebuffer::rgb555(31, 0, 0));
+        assert_eq!(comparison.diff.get(0, 0), 0);
+
+        let comparison = gameboy::compare(&actual, &Framebuffer::create(4, 4));
+        assert_eq!(comparison.mismatched, 16);
+    }
+
+    #[test]
+    fn screenshot_after_boot() {
+        // A frame of NOPs stays well within the ROM
+        let cartridge = gameboy::Cartridge::create(vec![0x00; 0x8000]);
+        let frame = gameboy::screenshot_rom(Model::DMG, cartridge, 1).expect("screenshot");
+        assert_eq!((frame.width(), frame.height()), (160, 144));
+
+        // The post-boot palette maps the blank background to white
+        let white = Framebuffer::rgb555(31, 31, 31);
+        assert!(frame.pixels().iter().all(|&p| p == white));
+
+        let png = encode_png(&frame);
+        assert!(gameboy::compare(&frame, &decode_png(&png).expect("decode")).matches());
+    }
+
+    #[test]
+    fn sgb_screenshot_has_border() {
+        let cartridge = gameboy::Cartridge::create(vec![0x00; 0x8000]);
+        let frame = gameboy::screenshot_rom(Model::SGB, cartridge, 1).expect("screenshot");
+        assert_eq!(
+            (frame.width(), frame.height()),
+            (gameboy::SGB_WIDTH, gameboy::SGB_HEIGHT)
+        );
+    }
+
+    #[test]
+    fn crash_before_frame() {
+        let cartridge = gameboy::Cartridge::create(illegal_rom());
+        let result = gameboy::screenshot_rom(Model::DMG, cartridge, 1);
+        assert!(matches!(result, Err(gameboy::ScreenshotError::Crashed(_))));
+    }
+}
@@ -0,0 +1,582 @@
+use crate::addressable::Addressable;
+use crate::bus::{Access, AccessKind};
+use crate::font::draw_text;
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_bus::{AccessHook, Bus};
+use crate::gameboy_cpu::{Reg, CPU};
+use crate::gameboy_joypad::Buttons;
+use crate::inspect::Inspect;
+
+use replace_with::replace_with_or_abort;
+use rhai::{Engine, EvalAltResult, FnPtr, AST, INT};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Overlay text color unless a script picks one
+const TEXT_COLOR: u16 = Framebuffer::rgb555(31, 31, 31);
+
+/// Button names scripts use, in `Buttons` bit order
+const BUTTON_NAMES: [&str; 8] = ["right", "left", "up", "down", "a", "b", "select", "start"];
+
+#[derive(Debug)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Error for ScriptError {}
+
+impl From<Box<EvalAltResult>> for ScriptError {
+    fn from(err: Box<EvalAltResult>) -> Self {
+        ScriptError(err.to_string())
+    }
+}
+
+/// Parses button names separated by spaces or `+`, e.g. `a+right`
+fn parse_buttons(names: &str) -> Result<Buttons, String> {
+    names
+        .split([' ', '+'])
+        .filter(|name| !name.is_empty())
+        .try_fold(Buttons::NONE, |buttons, name| {
+            let bit = BUTTON_NAMES
+                .iter()
+                .position(|button| button.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("unknown button {name}"))?;
+            Ok(buttons | Buttons::from_bits(1 << bit))
+        })
+}
+
+#[derive(Clone, Debug, Default)]
+struct Hooks {
+    frame: Vec<FnPtr>,
+    exec: BTreeMap<u16, Vec<FnPtr>>,
+    read: BTreeMap<u16, Vec<FnPtr>>,
+    write: BTreeMap<u16, Vec<FnPtr>>,
+}
+
+#[derive(Clone, Debug)]
+struct Text {
+    x: i32,
+    y: i32,
+    text: String,
+    color: u16,
+}
+
+/// State the functions scripts call work on
+#[derive(Debug)]
+struct Shared {
+    /// The machine while a script runs
+    cpu: Option<CPU>,
+    /// Just the bus while an access callback runs in the middle of a step
+    bus: Option<Bus>,
+    hooks: Hooks,
+    /// Watchpoints need to be handed to the bus
+    hooks_changed: bool,
+    /// Buttons the script asked to hold, taken like the frontend's input
+    buttons: Option<Buttons>,
+    /// First error of an access callback, reported after the step
+    error: Option<ScriptError>,
+    /// Addresses access callbacks wrote behind the CPU's back
+    written: Vec<u16>,
+    /// Text drawn during this frame and the last one
+    drawing: Vec<Text>,
+    shown: Vec<Text>,
+    frames: u64,
+    exit: Option<i32>,
+}
+
+impl Shared {
+    fn bus(&self) -> ScriptResult<&Bus> {
+        match (&self.bus, &self.cpu) {
+            (Some(bus), _) => Ok(bus),
+            (None, Some(cpu)) => Ok(cpu.bus()),
+            (None, None) => Err("no machine to script".into()),
+        }
+    }
+
+    fn cpu(&mut self) -> ScriptResult<&mut CPU> {
+        self.cpu
+            .as_mut()
+            .ok_or_else(|| "registers can't be used during a memory access".into())
+    }
+}
+
+type Context = Rc<RefCell<Shared>>;
+
+/// What the functions scripts call return when they can fail
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+fn address(addr: INT) -> ScriptResult<u16> {
+    u16::try_from(addr).map_err(|_| format!("bad address {addr}").into())
+}
+
+fn byte(data: INT) -> ScriptResult<u8> {
+    u8::try_from(data).map_err(|_| format!("bad data {data}").into())
+}
+
+fn position(pos: INT) -> ScriptResult<i32> {
+    i32::try_from(pos).map_err(|_| format!("bad position {pos}").into())
+}
+
+/// RGB555 colors, as `rgb` makes them
+fn color(color: INT) -> ScriptResult<u16> {
+    match u16::try_from(color) {
+        Ok(color) if color <= 0x7FFF => Ok(color),
+        _ => Err(format!("bad color {color}").into()),
+    }
+}
+
+fn register_hook(
+    context: &Context,
+    engine: &mut Engine,
+    name: &str,
+    hooks: fn(&mut Hooks) -> &mut BTreeMap<u16, Vec<FnPtr>>,
+) {
+    let context = context.clone();
+    engine.register_fn(name, move |addr: INT, hook: FnPtr| -> ScriptResult<()> {
+        let mut shared = context.borrow_mut();
+        hooks(&mut shared.hooks)
+            .entry(address(addr)?)
+            .or_default()
+            .push(hook);
+        shared.hooks_changed = true;
+        Ok(())
+    });
+}
+
+fn call_hooks(engine: &Engine, ast: &AST, hooks: &[FnPtr], args: Vec<INT>) -> ScriptResult<()> {
+    hooks.iter().try_for_each(|hook| {
+        hook.call::<rhai::Dynamic>(engine, ast, args.clone())
+            .map(drop)
+    })
+}
+
+/// Makes the emulator available to scripts
+fn create_engine(context: &Context) -> Engine {
+    let mut engine = Engine::new();
+
+    let shared = context.clone();
+    engine.register_fn("on_frame", move |hook: FnPtr| {
+        shared.borrow_mut().hooks.frame.push(hook);
+    });
+    register_hook(context, &mut engine, "on_exec", |hooks| &mut hooks.exec);
+    register_hook(context, &mut engine, "on_read", |hooks| &mut hooks.read);
+    register_hook(context, &mut engine, "on_write", |hooks| &mut hooks.write);
+
+    let shared = context.clone();
+    engine.register_fn("read", move |addr: INT| -> ScriptResult<INT> {
+        let data = shared.borrow().bus()?.inspect(address(addr)?);
+        data.map(INT::from).map_err(|err| err.to_string().into())
+    });
+    let shared = context.clone();
+    engine.register_fn("write", move |addr: INT, data: INT| -> ScriptResult<()> {
+        let (addr, data) = (address(addr)?, byte(data)?);
+        let shared = &mut *shared.borrow_mut();
+        let result = match (&mut shared.bus, &mut shared.cpu) {
+            (Some(bus), _) => {
+                shared.written.push(addr);
+                bus.write_byte(addr, data)
+            }
+            (None, Some(cpu)) => cpu.write_byte(addr, data),
+            (None, None) => return Err("no machine to script".into()),
+        };
+        result.map_err(|err| err.to_string().into())
+    });
+
+    let shared = context.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        let reg: Reg = name.parse()?;
+        Ok(INT::from(shared.borrow_mut().cpu()?.register(reg)))
+    });
+    let shared = context.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: INT| -> ScriptResult<()> {
+            let reg: Reg = name.parse()?;
+            let max = match reg {
+                Reg::A | Reg::B | Reg::C | Reg::D | Reg::E | Reg::F | Reg::H | Reg::L => 0xFF,
+                _ => 0xFFFF,
+            };
+            if !(0..=max).contains(&value) {
+                return Err(format!("bad value {value} for {reg}").into());
+            }
+            let result = shared.borrow_mut().cpu()?.set_register(reg, value as u16);
+            result.map_err(|err| err.to_string().into())
+        },
+    );
+
+    let shared = context.clone();
+    engine.register_fn("buttons", move |names: &str| -> ScriptResult<()> {
+        shared.borrow_mut().buttons = Some(parse_buttons(names)?);
+        Ok(())
+    });
+
+    let shared = context.clone();
+    engine.register_fn(
+        "text",
+        move |x: INT, y: INT, text: &str| -> ScriptResult<()> {
+            shared.borrow_mut().drawing.push(Text {
+                x: position(x)?,
+                y: position(y)?,
+                text: text.to_string(),
+                color: TEXT_COLOR,
+            });
+            Ok(())
+        },
+    );
+    let shared = context.clone();
+    engine.register_fn(
+        "text",
+        move |x: INT, y: INT, text: &str, color: INT| -> ScriptResult<()> {
+            shared.borrow_mut().drawing.push(Text {
+                x: position(x)?,
+                y: position(y)?,
+                text: text.to_string(),
+                color: self::color(color)?,
+            });
+            Ok(())
+        },
+    );
+    engine.register_fn("rgb", |r: INT, g: INT, b: INT| -> ScriptResult<INT> {
+        let component = |c: INT| match u8::try_from(c) {
+            Ok(c) if c <= 31 => Ok(c),
+            _ => Err(format!("bad color component {c}")),
+        };
+        let (r, g, b) = (component(r)?, component(g)?, component(b)?);
+        Ok(INT::from(Framebuffer::rgb555(r, g, b)))
+    });
+
+    let shared = context.clone();
+    engine.register_fn("frame", move || shared.borrow().frames as INT);
+    let shared = context.clone();
+    engine.register_fn("exit", move |code: INT| -> ScriptResult<()> {
+        let code = i32::try_from(code).map_err(|_| format!("bad exit code {code}"))?;
+        shared.borrow_mut().exit = Some(code);
+        Ok(())
+    });
+
+    engine
+}
+
+// Rhai script driving the emulator, e.g. to automate level tests. Scripts
+// register callbacks with `on_frame(|| ...)`, `on_exec(addr, |addr| ...)`,
+// `on_read(addr, |addr, data| ...)` and `on_write(addr, |addr, data| ...)`
+// and can call `read`, `write`, `reg`, `set_reg`, `buttons`, `text`, `rgb`,
+// `frame` and `exit`.
+//
+// The functions scripts call can't borrow the machine, so it is moved into
+// the script's state for as long as a script runs. Access callbacks run in
+// the middle of a step from the bus's access hook, with just the bus moved
+// in.
+#[derive(Debug)]
+pub struct Script {
+    engine: Rc<Engine>,
+    ast: Rc<AST>,
+    context: Context,
+    /// Addresses with an `on_exec` callback, checked before every step
+    exec: BTreeSet<u16>,
+    /// Whether reads or writes are watched
+    watching: bool,
+}
+
+impl Script {
+    /// Compiles a script and runs its top level, which registers callbacks
+    pub fn create(source: &str, cpu: &mut CPU) -> Result<Self, ScriptError> {
+        let context = Rc::new(RefCell::new(Shared {
+            cpu: None,
+            bus: None,
+            hooks: Hooks::default(),
+            hooks_changed: false,
+            buttons: None,
+            error: None,
+            written: Vec::new(),
+            drawing: Vec::new(),
+            shown: Vec::new(),
+            frames: 0,
+            exit: None,
+        }));
+        let engine = create_engine(&context);
+        let ast = engine
+            .compile(source)
+            .map_err(|err| ScriptError(err.to_string()))?;
+
+        let mut script = Script {
+            engine: Rc::new(engine),
+            ast: Rc::new(ast),
+            context,
+            exec: BTreeSet::new(),
+            watching: false,
+        };
+        script.with_cpu(cpu, |script| script.engine.run_ast(&script.ast))?;
+
+        Ok(script)
+    }
+
+    pub fn load(path: &Path, cpu: &mut CPU) -> Result<Self, Box<dyn Error>> {
+        Ok(Script::create(&fs::read_to_string(path)?, cpu)?)
+    }
+
+    /// Buttons a script asked to hold with `buttons` since the last call
+    pub fn take_buttons(&mut self) -> Option<Buttons> {
+        self.context.borrow_mut().buttons.take()
+    }
+
+    /// Exit code a script asked for with `exit`
+    pub fn exit_code(&self) -> Option<i32> {
+        self.context.borrow().exit
+    }
+
+    /// Sets up the watchpoints on a new machine
+    pub fn install(&mut self, cpu: &mut CPU) {
+        let shared = self.context.borrow();
+        let hooks = &shared.hooks;
+        let reads: BTreeSet<u16> = hooks.read.keys().copied().collect();
+        let writes: BTreeSet<u16> = hooks.write.keys().copied().collect();
+
+        self.exec = hooks.exec.keys().copied().collect();
+        self.watching = !hooks.read.is_empty() || !hooks.write.is_empty();
+        let bus = cpu.bus_mut();
+        bus.set_watchpoints(reads, writes);
+        bus.set_access_hook(self.watching.then(|| self.access_hook()));
+    }
+
+    /// Runs `on_read` and `on_write` callbacks from the bus. It doesn't keep
+    /// the script alive, and the script's own accesses aren't reported as
+    /// its state is borrowed while it runs.
+    fn access_hook(&self) -> AccessHook {
+        let context = self.context.clone();
+        let (engine, ast) = (Rc::downgrade(&self.engine), Rc::downgrade(&self.ast));
+        AccessHook(Box::new(move |bus: &mut Bus, access: Access<u16, u8>| {
+            let (Some(engine), Some(ast)) = (engine.upgrade(), ast.upgrade()) else {
+                return;
+            };
+            let hooks = match context.try_borrow() {
+                // Stops after the first error
+                Ok(shared) if shared.error.is_none() => {
+                    let hooks = match access.kind {
+                        AccessKind::Read => &shared.hooks.read,
+                        AccessKind::Write => &shared.hooks.write,
+                    };
+                    hooks.get(&access.addr).cloned().unwrap_or_default()
+                }
+                _ => return,
+            };
+            if hooks.is_empty() {
+                return;
+            }
+
+            replace_with_or_abort(bus, |bus| {
+                context.borrow_mut().bus = Some(bus);
+                let args = vec![access.addr as INT, access.data as INT];
+                let result = call_hooks(&engine, &ast, &hooks, args);
+
+                let mut shared = context.borrow_mut();
+                if let Err(err) = result {
+                    shared.error = Some(err.into());
+                }
+                shared.bus.take().expect("bus handed back")
+            });
+        }))
+    }
+
+    /// Runs with the machine in the script's state, installing callbacks
+    /// the script registered in the meantime
+    fn with_cpu<R>(
+        &mut self,
+        cpu: &mut CPU,
+        run: impl FnOnce(&Self) -> Result<R, Box<EvalAltResult>>,
+    ) -> Result<R, ScriptError> {
+        let mut result = None;
+        replace_with_or_abort(cpu, |cpu| {
+            self.context.borrow_mut().cpu = Some(cpu);
+            result = Some(run(self));
+            self.context
+                .borrow_mut()
+                .cpu
+                .take()
+                .expect("machine handed back")
+        });
+
+        if mem::take(&mut self.context.borrow_mut().hooks_changed) {
+            self.install(cpu);
+        }
+
+        Ok(result.expect("script ran")?)
+    }
+
+    fn call(
+        &mut self,
+        cpu: &mut CPU,
+        hooks: Vec<FnPtr>,
+        args: Vec<INT>,
+    ) -> Result<(), ScriptError> {
+        self.with_cpu(cpu, |script| {
+            call_hooks(&script.engine, &script.ast, &hooks, args)
+        })
+    }
+
+    /// Error of an access callback since the last call
+    fn take_error(&mut self) -> Result<(), ScriptError> {
+        self.context.borrow_mut().error.take().map_or(Ok(()), Err)
+    }
+
+    /// Runs `on_exec` callbacks for the instruction about to execute
+    pub fn before_step(&mut self, cpu: &mut CPU) -> Result<(), ScriptError> {
+        self.take_error()?;
+
+        let pc = cpu.register(Reg::PC);
+        if !self.exec.contains(&pc) {
+            return Ok(());
+        }
+
+        let hooks = self.context.borrow().hooks.exec[&pc].clone();
+        self.call(cpu, hooks, vec![pc as INT])
+    }
+
+    /// Runs `on_read` callbacks for reads at the end of the last step, the
+    /// other access callbacks ran as their access happened, and reports
+    /// their errors
+    pub fn after_step(&mut self, cpu: &mut CPU) -> Result<(), ScriptError> {
+        if self.watching {
+            cpu.bus_mut().report_accesses();
+        }
+        for addr in mem::take(&mut self.context.borrow_mut().written) {
+            cpu.invalidate(addr);
+        }
+
+        self.take_error()
+    }
+
+    /// Runs `on_frame` callbacks, text they draw is shown until the next
+    /// frame ends
+    pub fn frame_end(&mut self, cpu: &mut CPU) -> Result<(), ScriptError> {
+        self.take_error()?;
+        self.context.borrow_mut().frames += 1;
+
+        let hooks = self.context.borrow().hooks.frame.clone();
+        let result = self.call(cpu, hooks, Vec::new());
+
+        let mut shared = self.context.borrow_mut();
+        shared.shown = mem::take(&mut shared.drawing);
+        result
+    }
+
+    /// Draws the text of the last frame onto a frame, in its coordinates
+    pub fn draw_overlay(&self, frame: &mut Framebuffer) {
+        for text in &self.context.borrow().shown {
+            draw_text(frame, text.x, text.y, &text.text, text.color);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn cpu() -> gameboy::CPU {
+        gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0x00; 0x8000]))
+    }
+
+    #[test]
+    fn callbacks_and_memory() {
+        let mut cpu = cpu();
+        let mut script = gameboy::Script::create(
+            r#"
+            write(0xC000, 0x12);
+            set_reg("B", read(0xC000) + 1);
+            buttons("a+start");
+
+            on_exec(0x0102, |addr| write(0xC001, addr & 0xFF));
+            on_read(0xC002, |addr, data| write(0xC003, data + 1));
+            on_write(0xC004, |addr, data| write(0xC005, data * 2));
+            on_frame(|| {
+                text(1, 2, "frame " + frame());
+                if frame() == 2 { exit(3); }
+            });
+            "#,
+            &mut cpu,
+        )
+        .expect("script");
+
+        assert_eq!(cpu.bus().read_byte(0xC000).expect("read"), 0x12);
+        assert_eq!(cpu.register(gameboy::Reg::B), 0x13);
+        // Held buttons are left to the caller, like the frontend's input
+        assert_eq!(
+            script.take_buttons(),
+            Some(gameboy::Buttons::A | gameboy::Buttons::START)
+        );
+        assert_eq!(script.take_buttons(), None);
+
+        // Hooks fire for the NOPs and the accesses around them
+        while cpu.register(gameboy::Reg::PC) < 0x0104 {
+            script.before_step(&mut cpu).expect("exec hooks");
+            cpu.step().expect("step");
+            script.after_step(&mut cpu).expect("access hooks");
+        }
+        assert_eq!(cpu.bus().read_byte(0xC001).expect("read"), 0x02);
+
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xC002, 0x40).expect("write");
+            bus.read_byte(0xC002).expect("read");
+            bus.write_byte(0xC004, 0x21).expect("write");
+            // Callbacks run at the access, not after the step
+            assert_eq!(bus.inspect(0xC003).expect("read"), 0x41);
+            assert_eq!(bus.inspect(0xC005).expect("read"), 0x42);
+        });
+        script.after_step(&mut cpu).expect("access hooks");
+
+        script.frame_end(&mut cpu).expect("frame hooks");
+        assert_eq!(script.exit_code(), None);
+        let mut frame = Framebuffer::create(40, 10);
+        script.draw_overlay(&mut frame);
+        // The F of "frame"
+        assert_eq!(frame.get(1, 2), Framebuffer::rgb555(31, 31, 31));
+        assert_eq!(frame.get(3, 3), 0);
+
+        script.frame_end(&mut cpu).expect("frame hooks");
+        assert_eq!(script.exit_code(), Some(3));
+    }
+
+    #[test]
+    fn errors() {
+        let mut cpu = cpu();
+        assert!(gameboy::Script::create("let x = ;", &mut cpu).is_err());
+        assert!(gameboy::Script::create(r#"reg("Q")"#, &mut cpu).is_err());
+        assert!(gameboy::Script::create(r#"buttons("turbo")"#, &mut cpu).is_err());
+
+        let mut script =
+            gameboy::Script::create("on_frame(|| read(0x10000));", &mut cpu).expect("script");
+        let err = script.frame_end(&mut cpu).expect_err("bad address");
+        assert!(err.to_string().contains("bad address"));
+
+        for (source, error) in [
+            ("write(0xC000, 0x100)", "bad data"),
+            ("write(0xC000, -1)", "bad data"),
+            (r#"set_reg("A", 0x100)"#, "bad value"),
+            (r#"set_reg("HL", 0x10000)"#, "bad value"),
+            (r#"text(0, 0, "x", 0x8000)"#, "bad color"),
+            ("rgb(32, 0, 0)", "bad color component"),
+        ] {
+            let err = gameboy::Script::create(source, &mut cpu).expect_err(source);
+            assert!(err.to_string().contains(error), "{source}: {err}");
+        }
+
+        // Errors of access callbacks are reported after the step
+        let mut script =
+            gameboy::Script::create(r#"on_write(0xC000, |addr, data| reg("A"));"#, &mut cpu)
+                .expect("script");
+        cpu.bus_apply(|bus| bus.write_byte(0xC000, 0x01).expect("write"));
+        let err = script.after_step(&mut cpu).expect_err("registers");
+        assert!(err.to_string().contains("memory access"));
+    }
+}
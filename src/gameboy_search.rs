@@ -0,0 +1,374 @@
+use crate::bus::{Bus, CopyOf};
+
+use std::fmt;
+use std::str::FromStr;
+
+const WRAM_START: u16 = 0xC000;
+/// Start of the switchable WRAM bank, and the size of each bank
+const WRAM_BANK_START: u16 = 0xD000;
+const WRAM_BANK_SIZE: usize = 0x1000;
+const HRAM_START: u16 = 0xFF80;
+const SRAM_START: u16 = 0xA000;
+
+/// Memory a search covers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Region {
+    /// Work RAM with all of its banks
+    WRAM,
+    HRAM,
+    /// Cartridge RAM
+    SRAM,
+}
+
+impl Region {
+    pub const ALL: [Region; 3] = [Region::WRAM, Region::HRAM, Region::SRAM];
+
+    fn copy_of(self) -> CopyOf {
+        match self {
+            Region::WRAM => CopyOf::RAM,
+            Region::HRAM => CopyOf::HRAM,
+            Region::SRAM => CopyOf::SRAM,
+        }
+    }
+}
+
+/// A byte in a snapshot
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub region: Region,
+    /// Offset into the region, WRAM banks follow each other
+    pub offset: usize,
+}
+
+impl Location {
+    /// Bank and CPU address the byte shows up at
+    pub fn address(&self) -> (usize, u16) {
+        match self.region {
+            Region::WRAM if self.offset < WRAM_BANK_SIZE => (0, WRAM_START + self.offset as u16),
+            Region::WRAM => (
+                self.offset / WRAM_BANK_SIZE,
+                WRAM_BANK_START + (self.offset % WRAM_BANK_SIZE) as u16,
+            ),
+            Region::HRAM => (0, HRAM_START + self.offset as u16),
+            Region::SRAM => (0, SRAM_START + self.offset as u16),
+        }
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address() {
+            (bank, addr) if self.region == Region::WRAM && bank > 0 => {
+                write!(f, "{bank}:{addr:04X}")
+            }
+            (_, addr) => write!(f, "{addr:04X}"),
+        }
+    }
+}
+
+/// Size of the values searched for, words are little-endian
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Width {
+    #[default]
+    Byte,
+    Word,
+}
+
+impl Width {
+    pub fn bytes(self) -> usize {
+        match self {
+            Width::Byte => 1,
+            Width::Word => 2,
+        }
+    }
+
+    fn read(self, data: &[u8], offset: usize) -> u16 {
+        match self {
+            Width::Byte => data[offset] as u16,
+            Width::Word => u16::from_le_bytes([data[offset], data[offset + 1]]),
+        }
+    }
+}
+
+impl FromStr for Width {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8" => Ok(Width::Byte),
+            "16" => Ok(Width::Word),
+            _ => Err(format!("widths are 8 or 16 bits, not {s}")),
+        }
+    }
+}
+
+/// How a value has to relate to the previous snapshot to stay a candidate
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Predicate {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    EqualTo(u16),
+}
+
+impl Predicate {
+    fn matches(self, previous: u16, current: u16) -> bool {
+        match self {
+            Predicate::Equal => current == previous,
+            Predicate::Changed => current != previous,
+            Predicate::Increased => current > previous,
+            Predicate::Decreased => current < previous,
+            Predicate::EqualTo(value) => current == value,
+        }
+    }
+}
+
+/// Copy of the searched memory at one point in time
+#[derive(Clone, Debug, Default)]
+pub struct Snapshot {
+    regions: Vec<(Region, Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn take(bus: &dyn Bus<Addr = u16, Data = u8>) -> Self {
+        Snapshot {
+            regions: Region::ALL
+                .into_iter()
+                .map(|region| (region, bus.copy_of(region.copy_of())))
+                .collect(),
+        }
+    }
+
+    fn data(&self, region: Region) -> &[u8] {
+        self.regions
+            .iter()
+            .find(|(r, _)| *r == region)
+            .map_or(&[], |(_, data)| data)
+    }
+
+    /// Value at a location
+    pub fn value(&self, location: Location, width: Width) -> u16 {
+        width.read(self.data(location.region), location.offset)
+    }
+}
+
+// Cheat finder. Starts out with every location in RAM and narrows them down
+// by comparing snapshots taken between frames, e.g. with `Decreased` after
+// losing a life.
+#[derive(Debug)]
+pub struct MemorySearch {
+    width: Width,
+    snapshot: Snapshot,
+    candidates: Vec<Location>,
+}
+
+impl MemorySearch {
+    pub fn create(bus: &dyn Bus<Addr = u16, Data = u8>, width: Width) -> Self {
+        let snapshot = Snapshot::take(bus);
+
+        let candidates = Region::ALL
+            .into_iter()
+            .flat_map(|region| {
+                let len = snapshot.data(region).len();
+                (0..(len + 1).saturating_sub(width.bytes()))
+                    .map(move |offset| Location { region, offset })
+            })
+            // Words don't span two switchable WRAM banks
+            .filter(|location| {
+                width == Width::Byte
+                    || location.region != Region::WRAM
+                    || location.offset < WRAM_BANK_SIZE
+                    || (location.offset + 1) % WRAM_BANK_SIZE != 0
+            })
+            .collect();
+
+        MemorySearch {
+            width,
+            snapshot,
+            candidates,
+        }
+    }
+
+    pub fn width(&self) -> Width {
+        self.width
+    }
+
+    pub fn candidates(&self) -> &[Location] {
+        &self.candidates
+    }
+
+    /// Value of a candidate when the last snapshot was taken
+    pub fn value(&self, location: Location) -> u16 {
+        self.snapshot.value(location, self.width)
+    }
+
+    /// Takes a new snapshot and keeps the candidates that match against the
+    /// previous one, returns how many are left
+    pub fn narrow(&mut self, bus: &dyn Bus<Addr = u16, Data = u8>, predicate: Predicate) -> usize {
+        let current = Snapshot::take(bus);
+        let width = self.width;
+        let previous = &self.snapshot;
+
+        self.candidates.retain(|&location| {
+            predicate.matches(
+                previous.value(location, width),
+                current.value(location, width),
+            )
+        });
+        self.snapshot = current;
+
+        self.candidates.len()
+    }
+}
+
+/// A value shown in a RAM watch
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Watch {
+    pub address: u16,
+    pub width: Width,
+    pub name: String,
+}
+
+impl FromStr for Watch {
+    type Err = String;
+
+    /// Parses `ADDR[:16][=name]` with a hexadecimal address
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (location, name) = s.split_once('=').unwrap_or((s, ""));
+        let (address, width) = match location.split_once(':') {
+            Some((address, width)) => (address, width.parse()?),
+            None => (location, Width::Byte),
+        };
+        let hex = address.trim_start_matches("0x");
+        let address = u16::from_str_radix(hex, 16).map_err(|_| format!("bad address {address}"))?;
+
+        Ok(Watch {
+            address,
+            width,
+            name: name.to_string(),
+        })
+    }
+}
+
+// Values to keep an eye on while a game runs, read through the bus so banked
+// memory shows the selected bank
+#[derive(Clone, Debug, Default)]
+pub struct RamWatch {
+    watches: Vec<Watch>,
+}
+
+impl RamWatch {
+    pub fn create() -> Self {
+        RamWatch::default()
+    }
+
+    pub fn add(&mut self, watch: Watch) {
+        self.watches.push(watch);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    /// The watched values on one line, e.g. `lives=03 C0A2=1F40`
+    pub fn line(&self, bus: &dyn Bus<Addr = u16, Data = u8>) -> String {
+        let words: Vec<String> = self
+            .watches
+            .iter()
+            .map(|watch| {
+                let read = |addr: u16| bus.inspect(addr).unwrap_or(0xFF);
+                let value = match watch.width {
+                    Width::Byte => format!("{:02X}", read(watch.address)),
+                    Width::Word => format!(
+                        "{:04X}",
+                        u16::from_le_bytes([
+                            read(watch.address),
+                            read(watch.address.wrapping_add(1))
+                        ])
+                    ),
+                };
+                match watch.name.as_str() {
+                    "" => format!("{:04X}={value}", watch.address),
+                    name => format!("{name}={value}"),
+                }
+            })
+            .collect();
+
+        words.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn cgb() -> gameboy::CPU {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = 0x80;
+        gameboy::create_system(Model::CGB, gameboy::Cartridge::create(rom))
+    }
+
+    #[test]
+    fn narrows_candidates() {
+        let mut cpu = cgb();
+        let mut search = gameboy::MemorySearch::create(cpu.bus(), gameboy::Width::Byte);
+        // 32K of WRAM and 127 bytes of HRAM, no cartridge RAM
+        assert_eq!(search.candidates().len(), 0x8000 + 127);
+
+        let write = |cpu: &mut gameboy::CPU, addr, data| {
+            cpu.bus_apply(|bus| bus.write_byte(addr, data).expect("write"));
+        };
+        write(&mut cpu, 0xC010, 5);
+        write(&mut cpu, 0xFF90, 5);
+        write(&mut cpu, 0xD020, 9);
+        assert_eq!(search.narrow(cpu.bus(), gameboy::Predicate::Changed), 3);
+
+        write(&mut cpu, 0xC010, 4);
+        write(&mut cpu, 0xFF90, 6);
+        assert_eq!(search.narrow(cpu.bus(), gameboy::Predicate::Decreased), 1);
+        let location = search.candidates()[0];
+        assert_eq!(location.to_string(), "C010");
+        assert_eq!(search.value(location), 4);
+
+        assert_eq!(search.narrow(cpu.bus(), gameboy::Predicate::Equal), 1);
+        assert_eq!(search.narrow(cpu.bus(), gameboy::Predicate::EqualTo(5)), 0);
+    }
+
+    #[test]
+    fn words_and_banks() {
+        let mut cpu = cgb();
+        let mut search = gameboy::MemorySearch::create(cpu.bus(), gameboy::Width::Word);
+
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xFF70, 0x03).expect("SVBK");
+            bus.write_byte(0xD100, 0x34).expect("write");
+            bus.write_byte(0xD101, 0x12).expect("write");
+        });
+        assert_eq!(
+            search.narrow(cpu.bus(), gameboy::Predicate::EqualTo(0x1234)),
+            1
+        );
+        let location = search.candidates()[0];
+        assert_eq!(location.address(), (3, 0xD100));
+        assert_eq!(location.to_string(), "3:D100");
+    }
+
+    #[test]
+    fn ram_watch() {
+        let mut cpu = cgb();
+        cpu.bus_apply(|bus| {
+            bus.write_byte(0xC000, 0x03).expect("write");
+            bus.write_byte(0xFF80, 0x40).expect("write");
+            bus.write_byte(0xFF81, 0x1F).expect("write");
+        });
+
+        let mut watch = gameboy::RamWatch::create();
+        watch.add("C000=lives".parse().expect("watch"));
+        watch.add("0xFF80:16".parse().expect("watch"));
+        assert_eq!(watch.line(cpu.bus()), "lives=03 FF80=1F40");
+
+        assert!("C000:32".parse::<gameboy::Watch>().is_err());
+        assert!("G000".parse::<gameboy::Watch>().is_err());
+    }
+}
@@ -0,0 +1,175 @@
+use crate::addressable::{AddressError, Addressable};
+use crate::inspect::Inspect;
+use crate::model::Model;
+use crate::timed::{CycleTime, Timed};
+
+use std::ops::RangeInclusive;
+
+/// Serial transfer data register
+const SB: u16 = 0xFF01;
+/// Serial transfer control register
+const SC: u16 = 0xFF02;
+
+pub const SERIAL_REGISTERS: RangeInclusive<u16> = SB..=SC;
+
+/// Counter cycles a bit takes with the internal clock, 8192 Hz
+const BIT_CYCLES: u64 = 512;
+
+// Gameboy serial port without a link partner, shifting out SB a bit at a
+// time with the internal clock and shifting in 0xFF. The CGB's fast clock
+// isn't emulated.
+#[derive(Debug, Default)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    /// Every byte sent so far, kept as its transfer starts
+    output: Vec<u8>,
+    /// Bits left to shift in the running transfer
+    bits: u8,
+    /// Counter cycles into the current bit
+    elapsed: u64,
+    double_speed: bool,
+    /// Partial counter cycles carried over between catchups
+    leftover: u64,
+    /// Whether a transfer finished since the last `take_interrupt`
+    finished: bool,
+}
+
+impl Serial {
+    pub fn create() -> Self {
+        Serial::default()
+    }
+
+    pub fn output(&self) -> &[u8] {
+        &self.output
+    }
+
+    /// Follows the CPU's speed, the port has to be caught up first
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /// Whether the serial interrupt was raised since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.finished)
+    }
+
+    /// Time until the next bit is shifted, if a transfer is running
+    pub fn next_bit(&self) -> Option<CycleTime> {
+        (self.bits > 0).then(|| CycleTime::new(self.frequency(), BIT_CYCLES - self.elapsed))
+    }
+
+    fn frequency(&self) -> u32 {
+        Model::CYCLE_CLOCK << self.double_speed as u32
+    }
+
+    fn advance(&mut self, mut cycles: u64) {
+        while self.bits > 0 && cycles > 0 {
+            let step = cycles.min(BIT_CYCLES - self.elapsed);
+            self.elapsed += step;
+            cycles -= step;
+            if self.elapsed < BIT_CYCLES {
+                return;
+            }
+
+            // Nothing drives the line, so it reads high
+            self.sb = self.sb << 1 | 1;
+            self.elapsed = 0;
+            self.bits -= 1;
+            if self.bits == 0 {
+                self.sc &= 0x7F;
+                self.finished = true;
+            }
+        }
+    }
+}
+
+impl Addressable for Serial {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        match addr {
+            SB => Ok(self.sb),
+            // Unused control bits read back as set
+            SC => Ok(self.sc | 0x7E),
+            _ => Err(AddressError::OutOfBounds(addr)),
+        }
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            SB => self.sb = data,
+            SC => {
+                self.sc = data;
+                // Transfer start using the internal clock
+                if data & 0x81 == 0x81 {
+                    self.output.push(self.sb);
+                    self.bits = 8;
+                    self.elapsed = 0;
+                } else {
+                    self.bits = 0;
+                }
+            }
+            _ => return Err(AddressError::OutOfBounds(addr)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for Serial {
+    /// Stores the registers without starting a transfer
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            SB => self.sb = data,
+            SC => self.sc = data,
+            _ => return Err(AddressError::OutOfBounds(addr)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Timed for Serial {
+    fn catchup(&mut self, time: CycleTime) {
+        // Partial cycles carry over so catching up in small steps adds up
+        let scaled = time.cycles() * self.frequency() as u64 + self.leftover;
+        self.leftover = scaled % time.frequency() as u64;
+        self.advance(scaled / time.frequency() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn transfer_takes_eight_bits() {
+        let mut serial = gameboy::Serial::create();
+        serial.write_byte(0xFF01, 0x42).expect("SB");
+        serial.write_byte(0xFF02, 0x81).expect("SC");
+        assert_eq!(serial.output(), [0x42]);
+
+        let bit = serial.next_bit().expect("transferring");
+        assert_eq!(bit, CycleTime::new(Model::CYCLE_CLOCK, 512));
+        serial.catchup(CycleTime::new(Model::CYCLE_CLOCK, 512 * 8 - 1));
+        assert_eq!(serial.read_byte(0xFF01).expect("SB"), 0x7F);
+        assert_eq!(serial.read_byte(0xFF02).expect("SC"), 0xFF);
+        assert!(!serial.take_interrupt());
+
+        serial.catchup(CycleTime::new(Model::CYCLE_CLOCK, 1));
+        assert_eq!(serial.read_byte(0xFF01).expect("SB"), 0xFF);
+        assert_eq!(serial.read_byte(0xFF02).expect("SC"), 0x7F);
+        assert!(serial.take_interrupt());
+        assert_eq!(serial.next_bit(), None);
+    }
+}
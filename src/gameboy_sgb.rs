@@ -0,0 +1,606 @@
+use crate::framebuffer::Framebuffer;
+
+/// Size of the composited SNES picture
+pub const SGB_WIDTH: usize = 256;
+pub const SGB_HEIGHT: usize = 224;
+
+/// Top left corner of the Game Boy screen within the border
+const SCREEN_X: usize = 48;
+const SCREEN_Y: usize = 40;
+const SCREEN_WIDTH: usize = 160;
+const SCREEN_HEIGHT: usize = 144;
+
+/// Palette attributes are set per 8x8 cell of the Game Boy screen
+const CELLS_X: usize = SCREEN_WIDTH / 8;
+const CELLS_Y: usize = SCREEN_HEIGHT / 8;
+
+/// Border map size in 8x8 tiles
+const BORDER_COLUMNS: usize = SGB_WIDTH / 8;
+const BORDER_ROWS: usize = SGB_HEIGHT / 8;
+/// 256 tiles of 4bpp SNES tile data
+const BORDER_TILE_SIZE: usize = 32;
+const BORDER_TILES_SIZE: usize = 256 * BORDER_TILE_SIZE;
+/// PCT_TRN data holds the border map followed by palettes 4-7
+const BORDER_PALETTES_OFFSET: usize = 0x800;
+
+/// Bytes copied out of VRAM by CHR_TRN and PCT_TRN
+pub const SGB_TRANSFER_SIZE: usize = 0x1000;
+
+const PACKET_SIZE: usize = 16;
+const PACKET_BITS: usize = PACKET_SIZE * 8;
+
+/// Command codes, the upper 5 bits of the first packet byte
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+/// Joypad select lines as written to JOYP, set bits are released
+const P14: u8 = 1 << 0;
+const P15: u8 = 1 << 1;
+const RELEASED: u8 = P14 | P15;
+
+/// Palette 1-A the SGB starts up with
+const DEFAULT_PALETTE: [u16; 4] = [
+    Framebuffer::from_rgb888([0xF8, 0xE8, 0xC8]),
+    Framebuffer::from_rgb888([0xD8, 0x90, 0x48]),
+    Framebuffer::from_rgb888([0xA8, 0x28, 0x20]),
+    Framebuffer::from_rgb888([0x30, 0x18, 0x50]),
+];
+
+/// What MASK_EN shows in place of the Game Boy screen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mask {
+    #[default]
+    Cancel,
+    /// Keeps showing the screen from when the mask was set
+    Freeze,
+    Black,
+    /// Fills the screen with color 0
+    Color0,
+}
+
+/// VRAM transfers a command is waiting for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transfer {
+    /// CHR_TRN, border tiles 0x00-0x7F or 0x80-0xFF
+    Tiles(u8),
+    /// PCT_TRN, border map and palettes
+    Border,
+}
+
+/// Work a command needs from the rest of the machine
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Request {
+    Transfer(Transfer),
+    /// The current frame is needed for MASK_EN freeze
+    Freeze,
+}
+
+// Super Game Boy. Commands arrive as 16 byte packets pulsed over the joypad
+// select lines, the bus forwards JOYP writes here and fulfills the requests
+// for VRAM data.
+#[derive(Debug)]
+pub struct Sgb {
+    /// Select lines at the last JOYP write
+    lines: u8,
+    /// Bits of the current packet received so far, None between packets
+    received: Option<usize>,
+    packet: [u8; PACKET_SIZE],
+    /// Packets of a multi-packet command
+    command: Vec<u8>,
+    request: Option<Request>,
+    /// Game Boy screen palettes 0-3, color 0 is shared
+    palettes: [[u16; 4]; 4],
+    /// Palette of each 8x8 cell of the screen
+    attributes: [u8; CELLS_X * CELLS_Y],
+    border_tiles: Vec<u8>,
+    /// SNES tile map entries, tile number, palette and flips
+    border_map: [u16; BORDER_COLUMNS * BORDER_ROWS],
+    /// Border palettes 4-7, color 0 is transparent
+    border_palettes: [[u16; 16]; 4],
+    mask: Mask,
+    /// Shades of the frame kept while frozen
+    frozen: Option<Vec<u8>>,
+    /// Controllers enabled by MLT_REQ and the one currently selected
+    players: u8,
+    player: u8,
+}
+
+impl Sgb {
+    pub fn create() -> Self {
+        Sgb {
+            lines: RELEASED,
+            received: None,
+            packet: [0; PACKET_SIZE],
+            command: Vec::new(),
+            request: None,
+            palettes: [DEFAULT_PALETTE; 4],
+            attributes: [0; CELLS_X * CELLS_Y],
+            border_tiles: vec![0; BORDER_TILES_SIZE],
+            border_map: [0; BORDER_COLUMNS * BORDER_ROWS],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::default(),
+            frozen: None,
+            players: 1,
+            player: 0,
+        }
+    }
+
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    /// Decodes a JOYP write. Pulling both lines low starts a packet, then
+    /// each bit is a pulse on P14 for 0 or P15 for 1.
+    pub fn write_joypad(&mut self, data: u8) {
+        let lines = data >> 4 & RELEASED;
+        let previous = std::mem::replace(&mut self.lines, lines);
+
+        match lines {
+            0 => {
+                self.received = Some(0);
+                self.packet = [0; PACKET_SIZE];
+            }
+            P14 | P15 if previous == RELEASED => {
+                if let Some(count) = self.received {
+                    self.receive_bit(count, lines == P14);
+                }
+            }
+            // Releasing P15 selects the next controller
+            RELEASED if previous & P15 == 0 && self.received.is_none() => {
+                self.player = (self.player + 1) % self.players;
+            }
+            _ => {}
+        }
+    }
+
+    /// Low nibble of JOYP with both lines released, identifies the selected
+    /// controller
+    pub fn joypad_id(&self) -> u8 {
+        0x0F - self.player
+    }
+
+    /// Takes the work the last command left for the bus
+    pub fn take_request(&mut self) -> Option<Request> {
+        self.request.take()
+    }
+
+    /// Completes a transfer with the 4K of VRAM data it asked for
+    pub fn transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Tiles(half) => {
+                let start = half as usize * SGB_TRANSFER_SIZE;
+                self.border_tiles[start..start + SGB_TRANSFER_SIZE]
+                    .copy_from_slice(&data[..SGB_TRANSFER_SIZE]);
+            }
+            Transfer::Border => {
+                for (entry, bytes) in self.border_map.iter_mut().zip(data.chunks_exact(2)) {
+                    *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+
+                let colors = data[BORDER_PALETTES_OFFSET..].chunks_exact(2);
+                for (color, bytes) in self.border_palettes.iter_mut().flatten().zip(colors) {
+                    *color = u16::from_le_bytes([bytes[0], bytes[1]]) & 0x7FFF;
+                }
+            }
+        }
+    }
+
+    /// Keeps the frame shown while frozen
+    pub fn freeze(&mut self, shades: Vec<u8>) {
+        self.frozen = Some(shades);
+    }
+
+    /// Draws the Game Boy screen, given as the GPU's shades, with the SGB
+    /// palettes inside the border
+    pub fn composite(&self, shades: &[u8]) -> Framebuffer {
+        let mut frame = Framebuffer::create(SGB_WIDTH, SGB_HEIGHT);
+        self.composite_into(shades, &mut frame);
+        frame
+    }
+
+    /// Like `composite`, drawing into an existing frame
+    pub fn composite_into(&self, shades: &[u8], frame: &mut Framebuffer) {
+        if (frame.width(), frame.height()) != (SGB_WIDTH, SGB_HEIGHT) {
+            *frame = Framebuffer::create(SGB_WIDTH, SGB_HEIGHT);
+        }
+
+        let backdrop = self.palettes[0][0];
+        let shades = match (self.mask, &self.frozen) {
+            (Mask::Freeze, Some(frozen)) => frozen,
+            _ => shades,
+        };
+
+        for y in 0..SGB_HEIGHT {
+            for x in 0..SGB_WIDTH {
+                frame.set(x, y, backdrop);
+            }
+        }
+
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => 0,
+                    Mask::Color0 => backdrop,
+                    Mask::Cancel | Mask::Freeze => {
+                        let palette = self.attributes[y / 8 * CELLS_X + x / 8] as usize;
+                        self.palettes[palette][shades[y * SCREEN_WIDTH + x] as usize]
+                    }
+                };
+                frame.set(SCREEN_X + x, SCREEN_Y + y, color);
+            }
+        }
+
+        self.draw_border(frame);
+    }
+
+    /// Border tiles cover the screen wherever they aren't transparent
+    fn draw_border(&self, frame: &mut Framebuffer) {
+        for (index, &entry) in self.border_map.iter().enumerate() {
+            let tile = (entry & 0xFF) as usize * BORDER_TILE_SIZE;
+            let tile = &self.border_tiles[tile..tile + BORDER_TILE_SIZE];
+            let palette = &self.border_palettes[(entry >> 10 & 0x03) as usize];
+            let x_flip = entry & 1 << 14 != 0;
+            let y_flip = entry & 1 << 15 != 0;
+
+            for row in 0..8 {
+                let line = if y_flip { 7 - row } else { row };
+                // Bitplanes 0 and 1 come first, then 2 and 3
+                let planes = [
+                    tile[line * 2],
+                    tile[line * 2 + 1],
+                    tile[16 + line * 2],
+                    tile[16 + line * 2 + 1],
+                ];
+
+                for column in 0..8 {
+                    let bit = if x_flip { column } else { 7 - column };
+                    let color = planes
+                        .iter()
+                        .enumerate()
+                        .fold(0, |color, (plane, byte)| color | (byte >> bit & 1) << plane);
+
+                    if color != 0 {
+                        let x = index % BORDER_COLUMNS * 8 + column;
+                        let y = index / BORDER_COLUMNS * 8 + row;
+                        frame.set(x, y, palette[color as usize]);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Packets go out LSB first and end with a 0 stop bit
+    fn receive_bit(&mut self, count: usize, bit: bool) {
+        if count == PACKET_BITS {
+            self.received = None;
+            self.receive_packet();
+            return;
+        }
+
+        self.packet[count / 8] |= (bit as u8) << (count % 8);
+        self.received = Some(count + 1);
+    }
+
+    /// The low 3 bits of the first byte give the number of packets
+    fn receive_packet(&mut self) {
+        self.command.extend_from_slice(&self.packet);
+
+        let packets = (self.command[0] & 0x07).max(1) as usize;
+        if self.command.len() >= packets * PACKET_SIZE {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palettes(0, 1, &data[1..]),
+            PAL23 => self.set_palettes(2, 3, &data[1..]),
+            PAL03 => self.set_palettes(0, 3, &data[1..]),
+            PAL12 => self.set_palettes(1, 2, &data[1..]),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            MLT_REQ => {
+                self.players = match data[1] & 0x03 {
+                    1 => 2,
+                    2 => 3,
+                    3 => 4,
+                    _ => 1,
+                };
+                self.player = 0;
+            }
+            CHR_TRN => self.request = Some(Request::Transfer(Transfer::Tiles(data[1] & 0x01))),
+            PCT_TRN => self.request = Some(Request::Transfer(Transfer::Border)),
+            MASK_EN => {
+                self.mask = match data[1] & 0x03 {
+                    1 => Mask::Freeze,
+                    2 => Mask::Black,
+                    3 => Mask::Color0,
+                    _ => Mask::Cancel,
+                };
+                self.frozen = None;
+                if self.mask == Mask::Freeze {
+                    self.request = Some(Request::Freeze);
+                }
+            }
+            // Sound, SNES program and the remaining palette commands
+            _ => {}
+        }
+    }
+
+    /// Sets color 0 of every palette and colors 1-3 of two of them
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let color =
+            |index: usize| u16::from_le_bytes([data[index * 2], data[index * 2 + 1]]) & 0x7FFF;
+
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color(0);
+        }
+        for index in 1..4 {
+            self.palettes[first][index] = color(index);
+            self.palettes[second][index] = color(index + 3);
+        }
+    }
+
+    /// Colors rectangles. Each data set has flags for the inside, the
+    /// surrounding line and the outside, their palettes and the corners.
+    fn attr_blk(&mut self, data: &[u8]) {
+        for set in data[2..].chunks_exact(6).take(data[1] as usize) {
+            let control = set[0] & 0x07;
+            let [inside, line, outside] = [0, 2, 4].map(|shift| set[1] >> shift & 0x03);
+            // A lone inside or outside flag colors the line as well
+            let line = match control {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                _ if control & 0b010 != 0 => Some(line),
+                _ => None,
+            };
+            let [x1, y1, x2, y2] = [2, 3, 4, 5].map(|index| (set[index] & 0x1F) as usize);
+
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let palette = if x > x1 && x < x2 && y > y1 && y < y2 {
+                        (control & 0b001 != 0).then_some(inside)
+                    } else if x < x1 || x > x2 || y < y1 || y > y2 {
+                        (control & 0b100 != 0).then_some(outside)
+                    } else {
+                        line
+                    };
+
+                    if let Some(palette) = palette {
+                        self.attributes[y * CELLS_X + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Colors whole rows or columns, one byte per line
+    fn attr_lin(&mut self, data: &[u8]) {
+        for &set in data[2..].iter().take(data[1] as usize) {
+            let line = (set & 0x1F) as usize;
+            let palette = set >> 5 & 0x03;
+
+            if set & 0x80 != 0 && line < CELLS_Y {
+                self.attributes[line * CELLS_X..(line + 1) * CELLS_X].fill(palette);
+            } else if set & 0x80 == 0 && line < CELLS_X {
+                for y in 0..CELLS_Y {
+                    self.attributes[y * CELLS_X + line] = palette;
+                }
+            }
+        }
+    }
+
+    /// Splits the screen in two along a row or column
+    fn attr_div(&mut self, data: &[u8]) {
+        let [after, before, on] = [0, 2, 4].map(|shift| data[1] >> shift & 0x03);
+        let horizontal = data[1] & 0x40 != 0;
+        let split = (data[2] & 0x1F) as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * CELLS_X + x] = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    /// Sets cells one by one from a starting cell, 2 bits each with the
+    /// first cell in the top bits
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x01 != 0;
+
+        for index in 0..count.min(CELLS_X * CELLS_Y) {
+            let Some(byte) = data.get(6 + index / 4) else {
+                break;
+            };
+            if x < CELLS_X && y < CELLS_Y {
+                self.attributes[y * CELLS_X + x] = byte >> (6 - index % 4 * 2) & 0x03;
+            }
+
+            if vertical {
+                y += 1;
+                if y >= CELLS_Y {
+                    y = 0;
+                    x = (x + 1) % CELLS_X;
+                }
+            } else {
+                x += 1;
+                if x >= CELLS_X {
+                    x = 0;
+                    y = (y + 1) % CELLS_Y;
+                }
+            }
+        }
+    }
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Sgb::create()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn setup_bus() -> gameboy::Bus {
+        let ram = Box::new(gameboy::RAM::<0x2000>::create(0xC000));
+        let vram = Box::new(gameboy::RAM::<0x2000>::create(0x8000));
+        let gpu = Box::new(gameboy::GPU::create(Model::SGB, vram));
+        let cartridge = Box::new(gameboy::Cartridge::create(Vec::new()));
+        gameboy::Bus::create(Model::SGB, cartridge, ram, gpu)
+    }
+
+    /// Pulses a command over JOYP, `data` is padded to whole packets
+    fn send(bus: &mut gameboy::Bus, data: &[u8]) {
+        for packet in data.chunks(16) {
+            let mut bytes = [0; 16];
+            bytes[..packet.len()].copy_from_slice(packet);
+
+            bus.write_byte(0xFF00, 0x00).expect("reset pulse");
+            bus.write_byte(0xFF00, 0x30).expect("release");
+            for bit in 0..128 {
+                let one = bytes[bit / 8] >> (bit % 8) & 1 != 0;
+                bus.write_byte(0xFF00, if one { 0x10 } else { 0x20 })
+                    .expect("bit pulse");
+                bus.write_byte(0xFF00, 0x30).expect("release");
+            }
+            bus.write_byte(0xFF00, 0x20).expect("stop bit");
+            bus.write_byte(0xFF00, 0x30).expect("release");
+        }
+    }
+
+    fn screen(shade: u8) -> Vec<u8> {
+        vec![shade; 160 * 144]
+    }
+
+    const RED: u16 = Framebuffer::rgb555(31, 0, 0);
+    const GREEN: u16 = Framebuffer::rgb555(0, 31, 0);
+    const BLUE: u16 = Framebuffer::rgb555(0, 0, 31);
+
+    #[test]
+    fn palettes_and_attributes() {
+        let mut bus = setup_bus();
+        let sgb = |bus: &gameboy::Bus| bus.sgb().expect("SGB model").composite(&screen(3));
+
+        // PAL01, color 0 blue, palette 0 all red and palette 1 all green
+        let mut pal01 = vec![0x01];
+        for color in [BLUE, RED, RED, RED, GREEN, GREEN, GREEN] {
+            pal01.extend_from_slice(&color.to_le_bytes());
+        }
+        send(&mut bus, &pal01);
+
+        let frame = sgb(&bus);
+        assert_eq!((frame.width(), frame.height()), (256, 224));
+        assert_eq!(frame.get(0, 0), BLUE);
+        // The screen is all the darkest shade
+        assert_eq!(frame.get(48, 40), RED);
+
+        // ATTR_BLK, inside and line of cells 1,1 to 3,3 use palette 1
+        send(&mut bus, &[0x04 << 3 | 1, 1, 0b011, 0b0101, 1, 1, 3, 3]);
+        let frame = sgb(&bus);
+        assert_eq!(frame.get(48 + 7, 40 + 8), RED);
+        assert_eq!(frame.get(48 + 8, 40 + 8), GREEN);
+        assert_eq!(frame.get(48 + 31, 40 + 31), GREEN);
+        assert_eq!(frame.get(48 + 32, 40 + 31), RED);
+
+        // ATTR_DIV, rows below 2 use palette 1 and the rest palette 0
+        send(&mut bus, &[0x06 << 3 | 1, 0b100_0100, 2]);
+        let frame = sgb(&bus);
+        assert_eq!(frame.get(100, 40 + 15), GREEN);
+        assert_eq!(frame.get(100, 40 + 16), RED);
+
+        // ATTR_LIN, column 0 uses palette 1
+        send(&mut bus, &[0x05 << 3 | 1, 1, 0b0010_0000]);
+        assert_eq!(sgb(&bus).get(48, 40 + 100), GREEN);
+
+        // ATTR_CHR, two cells from 5,0 going right
+        send(&mut bus, &[0x07 << 3 | 1, 5, 0, 2, 0, 0, 0b0100_0000]);
+        let frame = sgb(&bus);
+        assert_eq!(frame.get(48 + 40, 40 + 100), RED);
+        assert_eq!(frame.get(48 + 40, 40), GREEN);
+        assert_eq!(frame.get(48 + 48, 40), RED);
+
+        // MASK_EN black
+        send(&mut bus, &[0x17 << 3 | 1, 2]);
+        assert_eq!(sgb(&bus).get(48, 40), 0);
+    }
+
+    #[test]
+    fn multiplayer_ids() {
+        let mut bus = setup_bus();
+        let joyp = |bus: &gameboy::Bus| bus.read_byte(0xFF00).expect("JOYP read") & 0x0F;
+
+        // MLT_REQ, two players
+        send(&mut bus, &[0x11 << 3 | 1, 1]);
+        assert_eq!(joyp(&bus), 0x0F);
+
+        // A full button read cycle moves on to the next controller
+        for data in [0x20, 0x10, 0x30] {
+            bus.write_byte(0xFF00, data).expect("JOYP write");
+        }
+        assert_eq!(joyp(&bus), 0x0E);
+
+        for data in [0x20, 0x10, 0x30] {
+            bus.write_byte(0xFF00, data).expect("JOYP write");
+        }
+        assert_eq!(joyp(&bus), 0x0F);
+    }
+
+    #[test]
+    fn border_transfer() {
+        let mut bus = setup_bus();
+        // Tile data at 0x8000
+        bus.write_byte(0xFF40, 0x91).expect("LCDC write");
+
+        // Tile 0 uses color 1 everywhere
+        for row in 0..8 {
+            bus.write_byte(0x8000 + row * 2, 0xFF).expect("VRAM write");
+        }
+        send(&mut bus, &[0x13 << 3 | 1, 0]);
+
+        // Only the top left tile uses tile 0 with palette 4, the rest is
+        // the blank tile 1
+        bus.write_byte(0x8000, 0x00).expect("VRAM write");
+        bus.write_byte(0x8001, 0x10).expect("VRAM write");
+        for entry in 1..32 * 28 {
+            bus.write_byte(0x8000 + entry * 2, 0x01)
+                .expect("VRAM write");
+            bus.write_byte(0x8001 + entry * 2, 0x00)
+                .expect("VRAM write");
+        }
+        for (offset, byte) in GREEN.to_le_bytes().into_iter().enumerate() {
+            bus.write_byte(0x8802 + offset as u16, byte)
+                .expect("VRAM write");
+        }
+        send(&mut bus, &[0x14 << 3 | 1]);
+
+        let frame = bus.sgb().expect("SGB model").composite(&screen(0));
+        assert_eq!(frame.get(0, 0), GREEN);
+        assert_eq!(frame.get(7, 7), GREEN);
+        assert_eq!(
+            frame.get(8, 0),
+            Framebuffer::from_rgb888([0xF8, 0xE8, 0xC8])
+        );
+    }
+}
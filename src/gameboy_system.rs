@@ -0,0 +1,257 @@
+use crate::bus::Bus as _;
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_bus::Bus;
+use crate::gameboy_cartridge::Cartridge;
+use crate::gameboy_cpu::CPU;
+use crate::gameboy_gpu::GPU;
+use crate::gameboy_ram::{BankedVRAM, BankedWRAM, RAM};
+use crate::gpu::GPU as _;
+use crate::model::Model;
+use crate::ram;
+use crate::ram::RAM as _;
+
+use std::error::Error;
+use std::fmt;
+
+const WRAM_START: u16 = 0xC000;
+const VRAM_START: u16 = 0x8000;
+/// Work RAM banks the CGB maps, bank 0 and seven switchable ones
+const CGB_WRAM_BANKS: usize = 8;
+
+type Memory = Box<dyn ram::RAM<Addr = u16, Data = u8>>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SystemError {
+    /// The work RAM has fewer banks than the model maps
+    WorkRamBanks { model: Model, banks: usize },
+}
+
+impl fmt::Display for SystemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SystemError::WorkRamBanks { model, banks } => write!(
+                f,
+                "{model} needs {CGB_WRAM_BANKS} work RAM banks, not {banks}"
+            ),
+        }
+    }
+}
+
+impl Error for SystemError {}
+
+/// Wires up the memory a model has around a cartridge and boots it
+pub fn create_system(model: Model, cartridge: Cartridge) -> CPU {
+    let ram: Memory = if model.is_cgb() {
+        Box::new(BankedWRAM::create(WRAM_START))
+    } else {
+        Box::new(RAM::<{ 8 * 1024 }>::create(WRAM_START))
+    };
+
+    let bus = Box::new(Bus::create(
+        model,
+        Box::new(cartridge),
+        ram,
+        create_gpu(model),
+    ));
+    let mut cpu = CPU::create(model, bus);
+    cpu.boot();
+    cpu
+}
+
+/// Like `create_system` with the work RAM type picked by the caller, e.g.
+/// `BankedWRAM` for the CGB, so the CPU reaches the cartridge and work RAM
+/// without dynamic dispatch. The GPU stays boxed behind its trait. CGB
+/// models need all eight work RAM banks.
+pub fn create_static_system<R>(
+    model: Model,
+    cartridge: Cartridge,
+) -> Result<CPU<Bus<Cartridge, R>>, SystemError>
+where
+    R: ram::RAM<Addr = u16, Data = u8> + 'static,
+{
+    let ram = Box::new(R::create(WRAM_START));
+    if model.is_cgb() && ram.banks() < CGB_WRAM_BANKS {
+        return Err(SystemError::WorkRamBanks {
+            model,
+            banks: ram.banks(),
+        });
+    }
+    let bus = Bus::with_devices(model, Box::new(cartridge), ram, create_gpu(model));
+    let mut cpu = CPU::with_bus(model, Box::new(bus));
+    cpu.boot();
+    Ok(cpu)
+}
+
+fn create_gpu(model: Model) -> Box<GPU> {
+    let vram: Memory = if model.is_cgb() {
+        Box::new(BankedVRAM::create(VRAM_START))
+    } else {
+        Box::new(RAM::<{ 8 * 1024 }>::create(VRAM_START))
+    };
+
+    Box::new(GPU::create(model, vram))
+}
+
+/// The picture the machine currently shows, composited with the border on
+/// the SGB
+pub fn screen(cpu: &CPU) -> Framebuffer {
+    let mut frame = Framebuffer::create(0, 0);
+    draw_screen(cpu, &mut frame);
+    frame
+}
+
+/// Like `screen`, drawing into an existing frame without allocating
+pub fn draw_screen(cpu: &CPU, frame: &mut Framebuffer) {
+    let gpu = cpu.bus().gpu();
+    match cpu.bus().sgb() {
+        Some(sgb) => sgb.composite_into(gpu.shades(), frame),
+        None => frame.copy_from(gpu.framebuffer()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn rom_with_header(cgb_flag: u8, sgb_flag: u8) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0143] = cgb_flag;
+        // Header bytes are executed by the NOP sled, only fill in what's
+        // needed
+        if sgb_flag != 0 {
+            rom[0x0146] = sgb_flag;
+            rom[0x014B] = 0x33;
+        }
+        rom
+    }
+
+    #[test]
+    fn model_from_header() {
+        let model = |cgb, sgb| gameboy::Cartridge::create(rom_with_header(cgb, sgb)).model();
+
+        assert_eq!(model(0x00, 0x00), Model::DMG);
+        assert_eq!(model(0x00, 0x03), Model::SGB);
+        assert_eq!(model(0x80, 0x03), Model::CGB);
+        assert_eq!(model(0xC0, 0x00), Model::CGB);
+        assert_eq!("AGB".parse::<Model>(), Ok(Model::AGB));
+        assert!("gba".parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn boot_state_per_model() {
+        let boot = |model, cgb_flag| {
+            let cartridge = gameboy::Cartridge::create(rom_with_header(cgb_flag, 0));
+            let cpu = gameboy::create_system(model, cartridge);
+            [
+                gameboy::Reg::AF,
+                gameboy::Reg::BC,
+                gameboy::Reg::DE,
+                gameboy::Reg::HL,
+            ]
+            .map(|reg| cpu.register(reg))
+        };
+
+        assert_eq!(boot(Model::DMG, 0x00), [0x01B0, 0x0013, 0x00D8, 0x014D]);
+        assert_eq!(boot(Model::MGB, 0x00), [0xFFB0, 0x0013, 0x00D8, 0x014D]);
+        assert_eq!(boot(Model::SGB, 0x00), [0x0100, 0x0014, 0x0000, 0xC060]);
+        assert_eq!(boot(Model::CGB, 0x80), [0x1180, 0x0000, 0xFF56, 0x000D]);
+        assert_eq!(boot(Model::AGB, 0x80), [0x1100, 0x0100, 0xFF56, 0x000D]);
+    }
+
+    #[test]
+    fn cgb_compatibility_mode() {
+        let mut cgb = gameboy::create_system(
+            Model::CGB,
+            gameboy::Cartridge::create(rom_with_header(0x80, 0)),
+        );
+        assert_eq!(cgb.bus().read_byte(0xFF70).expect("SVBK"), 0xF9);
+        // KEY0 is locked once the boot ROM is unmapped
+        cgb.bus_apply(|bus| bus.write_byte(0xFF4C, 0x04).expect("KEY0"));
+        assert_eq!(cgb.bus().read_byte(0xFF4D).expect("KEY1"), 0x7E);
+
+        let mut compat = gameboy::create_system(
+            Model::CGB,
+            gameboy::Cartridge::create(rom_with_header(0x00, 0)),
+        );
+        // CGB registers are locked away
+        assert_eq!(compat.bus().read_byte(0xFF4D).expect("KEY1"), 0xFF);
+        compat.bus_apply(|bus| {
+            bus.write_byte(0xD000, 0x11).expect("WRAM");
+            bus.write_byte(0xFF70, 0x02).expect("SVBK");
+        });
+        assert_eq!(compat.bus().read_byte(0xD000).expect("WRAM"), 0x11);
+
+        // A frame of NOPs shows the compatibility palette's white
+        gameboy::run_frames(&mut compat, 1).expect("frame");
+        let pixel = compat.bus().gpu().framebuffer().get(0, 0);
+        assert_eq!(pixel, gameboy::CGB_COMPAT_PALETTE[0]);
+
+        // BGP selects from the compatibility palette
+        compat.bus_apply(|bus| bus.write_byte(0xFF47, 0x01).expect("BGP"));
+        gameboy::run_frames(&mut compat, 1).expect("frame");
+        let pixel = compat.bus().gpu().framebuffer().get(0, 0);
+        assert_eq!(pixel, gameboy::CGB_COMPAT_PALETTE[1]);
+    }
+
+    #[test]
+    fn static_cgb_needs_banked_wram() {
+        let system = gameboy::create_static_system::<gameboy::RAM<{ 8 * 1024 }>>(
+            Model::CGB,
+            gameboy::Cartridge::create(rom_with_header(0x80, 0)),
+        );
+        let err = system.expect_err("unbanked CGB work RAM");
+        assert_eq!(
+            err,
+            gameboy::SystemError::WorkRamBanks {
+                model: Model::CGB,
+                banks: 1
+            }
+        );
+        assert_eq!(err.to_string(), "cgb needs 8 work RAM banks, not 1");
+    }
+
+    #[test]
+    fn static_system_matches_dynamic() {
+        // Stores each bank's number into it, from bank 7 down, then into
+        // bank 0, and runs on into the NOP sled
+        let mut program = Vec::new();
+        for bank in (1..8).rev() {
+            program.extend([0x3E, bank]); // LD A,bank
+            program.extend([0xEA, 0x70, 0xFF]); // LD (FF70h),A
+            program.extend([0xEA, 0x00, 0xD0]); // LD (D000h),A
+        }
+        program.extend([0xEA, 0x00, 0xC0]); // LD (C000h),A
+        let mut rom = rom_with_header(0x80, 0);
+        rom[0x0150..0x0150 + program.len()].copy_from_slice(&program);
+
+        let mut dynamic =
+            gameboy::create_system(Model::CGB, gameboy::Cartridge::create(rom.clone()));
+        let mut fixed = gameboy::create_static_system::<gameboy::BankedWRAM>(
+            Model::CGB,
+            gameboy::Cartridge::create(rom),
+        )
+        .expect("static system");
+
+        for _ in 0..1000 {
+            assert_eq!(dynamic.step().expect("step"), fixed.step().expect("step"));
+        }
+        assert_eq!(
+            dynamic.register(gameboy::Reg::PC),
+            fixed.register(gameboy::Reg::PC)
+        );
+        // Same LY and WRAM bank
+        for addr in [0xFF44, 0xFF70] {
+            assert_eq!(
+                dynamic.bus().read_byte(addr).expect("read"),
+                fixed.bus().read_byte(addr).expect("read")
+            );
+        }
+
+        let wram = fixed.bus().copy_of(CopyOf::RAM);
+        assert_eq!(wram, dynamic.bus().copy_of(CopyOf::RAM));
+        for bank in 1..8 {
+            assert_eq!(wram[bank * 0x1000], bank as u8);
+        }
+        assert_eq!(wram[0], 1);
+    }
+}
@@ -0,0 +1,278 @@
+use crate::bus::{Bus as _, CopyOf};
+use crate::cartridge::Cartridge as _;
+use crate::cpu::CPU as _;
+use crate::gameboy_cartridge::Cartridge;
+use crate::gameboy_cpu::{Reg, CPU};
+use crate::gameboy_system::create_system;
+use crate::inspect::Inspect;
+
+use std::any::Any;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+
+/// Mooneye test ROMs execute `LD B,B` once they have finished
+const LD_B_B: u8 = 0x40;
+/// Fibonacci sequence left in B, C, D, E, H and L by a passing Mooneye test
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+/// Cycles between scans of the serial output, one frame
+const SERIAL_POLL_CYCLES: u64 = 70224;
+
+/// File in the test ROM directory listing the ROMs expected to pass
+pub const EXPECTED_PASSING: &str = "passing.txt";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Passed,
+    Failed,
+    /// No termination condition was met within the time limit
+    Timeout,
+    /// The emulator stopped before the ROM finished, carries the reason
+    Crashed(String),
+    /// The ROM needs hardware that isn't emulated, carries what
+    Unsupported(String),
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Passed => f.write_str("pass"),
+            Verdict::Failed => f.write_str("fail"),
+            Verdict::Timeout => f.write_str("timeout"),
+            Verdict::Crashed(_) => f.write_str("crash"),
+            Verdict::Unsupported(_) => f.write_str("skip"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestReport {
+    pub verdict: Verdict,
+    /// Cycles run before the verdict was reached
+    pub cycles: u64,
+    /// Everything the ROM sent over the serial port
+    pub serial: String,
+}
+
+fn mooneye_verdict(cpu: &CPU) -> Option<Verdict> {
+    let regs = [Reg::B, Reg::C, Reg::D, Reg::E, Reg::H, Reg::L].map(|r| cpu.register(r) as u8);
+
+    match regs {
+        MOONEYE_PASSED => Some(Verdict::Passed),
+        MOONEYE_FAILED => Some(Verdict::Failed),
+        _ => None,
+    }
+}
+
+/// Blargg test ROMs report their result as text over the serial port
+fn blargg_verdict(serial: &str) -> Option<Verdict> {
+    if serial.contains("Passed") {
+        Some(Verdict::Passed)
+    } else if serial.contains("Failed") {
+        Some(Verdict::Failed)
+    } else {
+        None
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => match payload.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "panic".to_string(),
+        },
+    }
+}
+
+fn serial_output(cpu: &CPU) -> String {
+    String::from_utf8_lossy(&cpu.bus().copy_of(CopyOf::Serial)).into_owned()
+}
+
+/// Runs the CPU until the test ROM reports a result or `timeout` cycles pass
+pub fn run_test(cpu: &mut CPU, timeout: u64) -> TestReport {
+    let mut cycles = 0u64;
+    let mut next_serial_poll = 0u64;
+
+    let verdict = loop {
+        if cycles >= next_serial_poll {
+            next_serial_poll = cycles + SERIAL_POLL_CYCLES;
+            if let Some(verdict) = blargg_verdict(&serial_output(cpu)) {
+                break verdict;
+            }
+        }
+
+        if cycles >= timeout {
+            break Verdict::Timeout;
+        }
+
+        let pc = cpu.register(Reg::PC);
+        let opcode = cpu.bus().inspect(pc);
+
+        // Unimplemented instructions panic, report them like any other crash
+        match panic::catch_unwind(panic::AssertUnwindSafe(|| cpu.step())) {
+            Ok(Ok(spent)) => cycles += spent as u64,
+            Ok(Err(err)) => break Verdict::Crashed(err.to_string()),
+            Err(payload) => break Verdict::Crashed(panic_message(payload)),
+        }
+
+        if let Ok(LD_B_B) = opcode {
+            if let Some(verdict) = mooneye_verdict(cpu) {
+                break verdict;
+            }
+        }
+    };
+
+    TestReport {
+        verdict,
+        cycles,
+        serial: serial_output(cpu),
+    }
+}
+
+/// Runs a test ROM headless on the model its header asks for, with the
+/// block cache if `block_cache` is set. ROMs needing a memory bank
+/// controller are rejected without running.
+pub fn run_test_rom(rom: Vec<u8>, timeout: u64, block_cache: bool) -> TestReport {
+    let cartridge = Cartridge::create(rom);
+    if let Some(kind) = cartridge.bank_controller() {
+        return TestReport {
+            verdict: Verdict::Unsupported(format!(
+                "cartridge type {kind:#04X} needs a memory bank controller"
+            )),
+            cycles: 0,
+            serial: String::new(),
+        };
+    }
+
+    let mut cpu = create_system(cartridge.model(), cartridge);
+    cpu.set_block_cache(block_cache);
+
+    run_test(&mut cpu, timeout)
+}
+
+/// Recursively collects every `.gb` and `.gbc` file below `dir`, sorted
+pub fn find_test_roms(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut roms = Vec::new();
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            roms.extend(find_test_roms(&path)?);
+        } else if matches!(
+            path.extension().and_then(|e| e.to_str()),
+            Some("gb" | "gbc")
+        ) {
+            roms.push(path);
+        }
+    }
+
+    roms.sort();
+    Ok(roms)
+}
+
+/// Reads the ROM paths, relative to `dir`, that are expected to pass. A
+/// missing expectation file means no ROM is expected to pass yet.
+pub fn expected_passing(dir: &Path) -> io::Result<BTreeSet<PathBuf>> {
+    match fs::read_to_string(dir.join(EXPECTED_PASSING)) {
+        Ok(list) => Ok(list
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(PathBuf::from)
+            .collect()),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(BTreeSet::new()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    const TIMEOUT: u64 = 100_000;
+
+    fn rom_with(code: &[u8]) -> Vec<u8> {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0100 + code.len()].copy_from_slice(code);
+        rom
+    }
+
+    /// LD B,b; LD C,c; LD D,d; LD E,e; LD H,h; LD L,l; LD B,B
+    fn mooneye_signature(regs: [u8; 6]) -> Vec<u8> {
+        let [b, c, d, e, h, l] = regs;
+        rom_with(&[0x06, b, 0x0E, c, 0x16, d, 0x1E, e, 0x26, h, 0x2E, l, 0x40])
+    }
+
+    #[test]
+    fn mooneye_pass_and_fail() {
+        for cached in [false, true] {
+            let passing = mooneye_signature([3, 5, 8, 13, 21, 34]);
+            let report = gameboy::run_test_rom(passing, TIMEOUT, cached);
+            assert_eq!(report.verdict, gameboy::Verdict::Passed);
+
+            let report = gameboy::run_test_rom(mooneye_signature([0x42; 6]), TIMEOUT, cached);
+            assert_eq!(report.verdict, gameboy::Verdict::Failed);
+        }
+    }
+
+    #[test]
+    fn opcode_checks_are_not_reads() {
+        let rom = mooneye_signature([3, 5, 8, 13, 21, 34]);
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(rom));
+        cpu.bus_mut()
+            .set_watchpoints([0x0100].into(), Default::default());
+
+        let report = gameboy::run_test(&mut cpu, TIMEOUT);
+        assert_eq!(report.verdict, gameboy::Verdict::Passed);
+        // Only the CPU's own fetch
+        assert_eq!(cpu.bus_mut().take_accesses().len(), 1);
+    }
+
+    #[test]
+    fn timeout_and_crash() {
+        let report = gameboy::run_test_rom(rom_with(&[]), TIMEOUT, false);
+        assert_eq!(report.verdict, gameboy::Verdict::Timeout);
+        assert!(report.cycles >= TIMEOUT);
+
+        let report = gameboy::run_test_rom(rom_with(&[0xD3]), TIMEOUT, false);
+        assert!(matches!(report.verdict, gameboy::Verdict::Crashed(_)));
+    }
+
+    #[test]
+    fn bank_controller_rejected() {
+        // MBC1
+        let mut rom = rom_with(&[]);
+        rom[0x0147] = 0x01;
+
+        let report = gameboy::run_test_rom(rom, TIMEOUT, false);
+        assert_eq!(
+            report.verdict,
+            gameboy::Verdict::Unsupported(
+                "cartridge type 0x01 needs a memory bank controller".to_string()
+            )
+        );
+        assert_eq!(report.cycles, 0);
+    }
+
+    #[test]
+    fn blargg_serial_output() {
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(rom_with(&[])));
+
+        cpu.bus_apply(|bus| {
+            for &b in b"cpu_instrs\n\nPassed\n" {
+                bus.write_byte(0xFF01, b).expect("SB write");
+                bus.write_byte(0xFF02, 0x81).expect("SC write");
+            }
+        });
+
+        let report = gameboy::run_test(&mut cpu, TIMEOUT);
+        assert_eq!(report.verdict, gameboy::Verdict::Passed);
+        assert_eq!(report.serial, "cpu_instrs\n\nPassed\n");
+    }
+}
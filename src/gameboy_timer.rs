@@ -0,0 +1,257 @@
+use crate::addressable::{AddressError, Addressable};
+use crate::inspect::Inspect;
+use crate::model::Model;
+use crate::timed::{CycleTime, Timed};
+
+use std::ops::RangeInclusive;
+
+/// Divider, the upper byte of the system counter
+const DIV: u16 = 0xFF04;
+/// Timer counter, reloaded from TMA when it overflows
+const TIMA: u16 = 0xFF05;
+const TMA: u16 = 0xFF06;
+/// Timer control, bit 2 enables TIMA and bits 0-1 select its rate
+const TAC: u16 = 0xFF07;
+const TAC_ENABLE: u8 = 1 << 2;
+
+pub const TIMER_REGISTERS: RangeInclusive<u16> = DIV..=TAC;
+
+/// Counter cycles between TIMA increments for each TAC rate
+const TIMA_PERIODS: [u64; 4] = [1024, 16, 64, 256];
+/// Counter cycles between frame sequencer steps, the falling edges of
+/// counter bit 12
+const FRAME_STEP_PERIOD: u64 = 1 << 13;
+
+// Gameboy timer: the system counter, whose upper byte is DIV, and TIMA
+// counting at a rate TAC selects. The counter runs at the CPU's clock and
+// also steps the APU's frame sequencer. An overflowing TIMA is reloaded and
+// raises its interrupt straight away rather than a cycle later.
+#[derive(Debug, Default)]
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    double_speed: bool,
+    /// Partial counter cycles carried over between catchups
+    leftover: u64,
+    /// Whether TIMA overflowed since the last `take_interrupt`
+    overflowed: bool,
+    /// Frame sequencer steps so far, which clock the APU's length, sweep
+    /// and envelope units
+    frame_steps: u64,
+}
+
+impl Timer {
+    pub fn create() -> Self {
+        Timer::default()
+    }
+
+    /// Follows the CPU's speed, the timer has to be caught up first
+    pub fn set_double_speed(&mut self, double_speed: bool) {
+        self.double_speed = double_speed;
+    }
+
+    /// Whether the timer interrupt was raised since the last call
+    pub fn take_interrupt(&mut self) -> bool {
+        std::mem::take(&mut self.overflowed)
+    }
+
+    pub fn frame_steps(&self) -> u64 {
+        self.frame_steps
+    }
+
+    /// Time until TIMA overflows, if it is counting
+    pub fn next_overflow(&self) -> Option<CycleTime> {
+        if self.tac & TAC_ENABLE == 0 {
+            return None;
+        }
+
+        let period = self.tima_period();
+        let cycles = (0x100 - self.tima as u64) * period - self.counter as u64 % period;
+        Some(self.cycles(cycles))
+    }
+
+    /// Time until the frame sequencer steps next
+    pub fn next_frame_step(&self) -> CycleTime {
+        let period = self.frame_step_period();
+        self.cycles(period - self.counter as u64 % period)
+    }
+
+    fn frequency(&self) -> u32 {
+        Model::CYCLE_CLOCK << self.double_speed as u32
+    }
+
+    fn cycles(&self, cycles: u64) -> CycleTime {
+        CycleTime::new(self.frequency(), cycles)
+    }
+
+    fn tima_period(&self) -> u64 {
+        TIMA_PERIODS[(self.tac & 0x03) as usize]
+    }
+
+    /// Double speed mode steps on bit 13 instead, keeping it at 512 Hz
+    fn frame_step_period(&self) -> u64 {
+        FRAME_STEP_PERIOD << self.double_speed as u32
+    }
+
+    fn count(&mut self, mut increments: u64) {
+        while increments > 0 {
+            let to_overflow = 0x100 - self.tima as u64;
+            if increments < to_overflow {
+                self.tima += increments as u8;
+                return;
+            }
+
+            increments -= to_overflow;
+            self.tima = self.tma;
+            self.overflowed = true;
+        }
+    }
+
+    /// Moves the counter on, counting the edges of the bits TIMA and the
+    /// frame sequencer follow. The counter wraps at a multiple of both.
+    fn advance(&mut self, cycles: u64) {
+        let from = self.counter as u64;
+        let to = from + cycles;
+        let edges = |period: u64| to / period - from / period;
+
+        if self.tac & TAC_ENABLE != 0 {
+            self.count(edges(self.tima_period()));
+        }
+        self.frame_steps += edges(self.frame_step_period());
+        self.counter = to as u16;
+    }
+
+    /// Resetting the counter is a falling edge for the bits that were set
+    fn reset_counter(&mut self) {
+        let counter = self.counter as u64;
+        let falling = |period: u64| counter % period >= period / 2;
+
+        if self.tac & TAC_ENABLE != 0 && falling(self.tima_period()) {
+            self.count(1);
+        }
+        if falling(self.frame_step_period()) {
+            self.frame_steps += 1;
+        }
+        self.counter = 0;
+    }
+}
+
+impl Addressable for Timer {
+    type Addr = u16;
+    type Data = u8;
+
+    fn read_byte(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        match addr {
+            DIV => Ok((self.counter >> 8) as u8),
+            TIMA => Ok(self.tima),
+            TMA => Ok(self.tma),
+            // Unused control bits read back as set
+            TAC => Ok(self.tac | 0xF8),
+            _ => Err(AddressError::OutOfBounds(addr)),
+        }
+    }
+
+    fn write_byte(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            // Any write clears the counter
+            DIV => self.reset_counter(),
+            TIMA => self.tima = data,
+            TMA => self.tma = data,
+            TAC => self.tac = data & 0x07,
+            _ => return Err(AddressError::OutOfBounds(addr)),
+        }
+
+        Ok(())
+    }
+}
+
+impl Inspect for Timer {
+    /// Stores DIV as the counter's upper byte rather than clearing it
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        match addr {
+            DIV => self.counter = (data as u16) << 8,
+            _ => return self.write_byte(addr, data),
+        }
+
+        Ok(())
+    }
+}
+
+impl Timed for Timer {
+    fn catchup(&mut self, time: CycleTime) {
+        // Partial cycles carry over so catching up in small steps adds up
+        let scaled = time.cycles() * self.frequency() as u64 + self.leftover;
+        self.leftover = scaled % time.frequency() as u64;
+        self.advance(scaled / time.frequency() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    fn second() -> CycleTime {
+        CycleTime::new(Model::CYCLE_CLOCK, Model::CYCLE_CLOCK as u64)
+    }
+
+    #[test]
+    fn div_and_frame_sequencer() {
+        let mut timer = gameboy::Timer::create();
+        timer.catchup(CycleTime::new(Model::CYCLE_CLOCK, 0x1234));
+        assert_eq!(timer.read_byte(0xFF04).expect("DIV"), 0x12);
+
+        timer.write_byte(0xFF04, 0x77).expect("DIV");
+        assert_eq!(timer.read_byte(0xFF04).expect("DIV"), 0x00);
+        let steps = timer.frame_steps();
+        timer.catchup(second());
+        assert_eq!(timer.frame_steps() - steps, 512);
+
+        // Double speed runs the counter twice as fast but not the sequencer
+        timer.set_double_speed(true);
+        let steps = timer.frame_steps();
+        timer.catchup(second());
+        assert_eq!(timer.frame_steps() - steps, 512);
+        assert_eq!(
+            timer.next_frame_step(),
+            CycleTime::new(Model::CYCLE_CLOCK, 8192)
+        );
+    }
+
+    #[test]
+    fn tima_overflow() {
+        let mut timer = gameboy::Timer::create();
+        timer.write_byte(0xFF06, 0xF0).expect("TMA");
+        timer.write_byte(0xFF05, 0xFE).expect("TIMA");
+        // 16 cycles a step
+        timer.write_byte(0xFF07, 0x05).expect("TAC");
+        assert_eq!(timer.read_byte(0xFF07).expect("TAC"), 0xFD);
+
+        let overflow = timer.next_overflow().expect("counting");
+        assert_eq!(overflow, CycleTime::new(Model::CYCLE_CLOCK, 32));
+        timer.catchup(CycleTime::new(Model::CYCLE_CLOCK, 31));
+        assert!(!timer.take_interrupt());
+        assert_eq!(timer.read_byte(0xFF05).expect("TIMA"), 0xFF);
+        timer.catchup(CycleTime::new(Model::CYCLE_CLOCK, 1));
+        assert!(timer.take_interrupt());
+        assert!(!timer.take_interrupt());
+        assert_eq!(timer.read_byte(0xFF05).expect("TIMA"), 0xF0);
+
+        // Reloads keep counting from TMA
+        timer.catchup(CycleTime::new(Model::CYCLE_CLOCK, 16 * 16 + 8));
+        assert!(timer.take_interrupt());
+        assert_eq!(timer.read_byte(0xFF05).expect("TIMA"), 0xF0);
+
+        timer.write_byte(0xFF07, 0x00).expect("TAC");
+        assert_eq!(timer.next_overflow(), None);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::gameboy_cpu::{Reg, CPU};
+use crate::inspect::Inspect;
+
+use std::io;
+use std::io::{BufWriter, Write};
+
+/// Logs the CPU state before every instruction in the Gameboy Doctor format,
+/// `A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02`
+#[derive(Debug)]
+pub struct Tracer<W: Write> {
+    out: BufWriter<W>,
+    /// Number of instructions traced so far
+    count: u64,
+    /// Stop tracing after this many instructions
+    limit: Option<u64>,
+    /// Stop tracing once PC reaches this address
+    until_pc: Option<u16>,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn create(out: W) -> Self {
+        Tracer {
+            out: BufWriter::new(out),
+            count: 0,
+            limit: None,
+            until_pc: None,
+        }
+    }
+
+    pub fn stop_after(&mut self, instructions: u64) {
+        self.limit = Some(instructions);
+    }
+
+    pub fn stop_at(&mut self, pc: u16) {
+        self.until_pc = Some(pc);
+    }
+
+    /// Number of instructions traced so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Logs the instruction about to be executed. Returns false once a stop
+    /// condition is reached, in which case nothing is logged.
+    pub fn trace(&mut self, cpu: &CPU) -> io::Result<bool> {
+        let pc = cpu.register(Reg::PC);
+
+        if self.limit.is_some_and(|limit| self.count >= limit) || self.until_pc == Some(pc) {
+            return Ok(false);
+        }
+
+        let reg = |r| cpu.register(r);
+        // Unmapped memory reads as 0xFF, as on hardware
+        let mem = |offset| cpu.bus().inspect(pc.wrapping_add(offset)).unwrap_or(0xFF);
+
+        writeln!(
+            self.out,
+            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} \
+             SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+            reg(Reg::A),
+            reg(Reg::F),
+            reg(Reg::B),
+            reg(Reg::C),
+            reg(Reg::D),
+            reg(Reg::E),
+            reg(Reg::H),
+            reg(Reg::L),
+            reg(Reg::SP),
+            pc,
+            mem(0),
+            mem(1),
+            mem(2),
+            mem(3),
+        )?;
+
+        self.count += 1;
+
+        Ok(true)
+    }
+
+    /// Flushes buffered lines and returns the underlying writer
+    pub fn finish(self) -> io::Result<W> {
+        self.out.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn doctor_format() {
+        let mut rom = vec![0x00; 0x8000];
+        rom[0x0100..0x0104].copy_from_slice(&[0x00, 0xC3, 0x13, 0x02]);
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(rom));
+
+        let mut tracer = gameboy::Tracer::create(Vec::new());
+        tracer.stop_after(2);
+
+        assert!(tracer.trace(&cpu).expect("trace"));
+        cpu.step().expect("NOP step");
+        assert!(tracer.trace(&cpu).expect("trace"));
+        assert!(!tracer.trace(&cpu).expect("trace"));
+
+        let log = String::from_utf8(tracer.finish().expect("flush")).expect("utf8");
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02",
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:C3,13,02,00",
+            ]
+        );
+    }
+
+    #[test]
+    fn flags_after_add() {
+        let mut rom = vec![0x00; 0x8000];
+        // ADD A,FFh; ADD A,00h
+        rom[0x0100..0x0104].copy_from_slice(&[0xC6, 0xFF, 0xC6, 0x00]);
+        let mut cpu = gameboy::create_system(Model::DMG, gameboy::Cartridge::create(rom));
+
+        let mut tracer = gameboy::Tracer::create(Vec::new());
+        for _ in 0..2 {
+            tracer.trace(&cpu).expect("trace");
+            cpu.step().expect("ADD step");
+        }
+        tracer.trace(&cpu).expect("trace");
+
+        // As Gameboy Doctor logs it: Z, H and C, then Z alone
+        let log = String::from_utf8(tracer.finish().expect("flush")).expect("utf8");
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:C6,FF,C6,00",
+                "A:00 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0102 PCMEM:C6,00,00,00",
+                "A:00 F:80 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0104 PCMEM:00,00,00,00",
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_at_pc() {
+        let cpu =
+            gameboy::create_system(Model::DMG, gameboy::Cartridge::create(vec![0x00; 0x8000]));
+
+        let mut tracer = gameboy::Tracer::create(std::io::sink());
+        tracer.stop_at(0x0100);
+
+        assert!(!tracer.trace(&cpu).expect("trace"));
+        assert_eq!(tracer.count(), 0);
+    }
+}
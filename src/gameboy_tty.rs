@@ -0,0 +1,350 @@
+use crate::framebuffer::Framebuffer;
+use crate::gameboy_frontend::Control;
+use crate::gameboy_joypad::Buttons;
+use crate::triple_buffer::TripleReader;
+
+use std::io;
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Upper half block, drawn with the top pixel as foreground and the bottom
+/// one as background
+const HALF_BLOCK: &str = "\u{2580}";
+
+/// Terminals only report presses, so a button stays held this long after
+/// its last one to bridge the gaps until key repeat kicks in
+const HOLD: Duration = Duration::from_millis(250);
+
+/// How often input is polled while waiting for frames
+const POLL: Duration = Duration::from_millis(4);
+
+/// Keyboard input of the terminal frontend
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Key {
+    Button(Buttons),
+    /// Toggles pause
+    Pause,
+    Reset,
+    /// Toggles a cheat, counting from 0
+    Cheat(usize),
+    Quit,
+}
+
+/// Decodes raw mode keyboard input. Arrows are the d-pad, x and z are A and
+/// B, enter is start and space select, 1-9 toggle cheats. Unknown keys are
+/// dropped.
+pub fn decode_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut bytes = bytes.iter();
+
+    while let Some(&byte) = bytes.next() {
+        let key = match byte {
+            // Arrows are ESC [ or ESC O followed by a letter
+            0x1B => match (bytes.next(), bytes.next()) {
+                (Some(b'[' | b'O'), Some(b'A')) => Key::Button(Buttons::UP),
+                (Some(b'[' | b'O'), Some(b'B')) => Key::Button(Buttons::DOWN),
+                (Some(b'[' | b'O'), Some(b'C')) => Key::Button(Buttons::RIGHT),
+                (Some(b'[' | b'O'), Some(b'D')) => Key::Button(Buttons::LEFT),
+                _ => continue,
+            },
+            b'x' | b'X' => Key::Button(Buttons::A),
+            b'z' | b'Z' => Key::Button(Buttons::B),
+            b'\r' | b'\n' => Key::Button(Buttons::START),
+            b' ' => Key::Button(Buttons::SELECT),
+            b'p' | b'P' => Key::Pause,
+            b'r' | b'R' => Key::Reset,
+            b'1'..=b'9' => Key::Cheat((byte - b'1') as usize),
+            // Ctrl-C doesn't raise a signal in raw mode
+            b'q' | b'Q' | 0x03 => Key::Quit,
+            _ => continue,
+        };
+        keys.push(key);
+    }
+
+    keys
+}
+
+/// Turns key presses into held buttons
+#[derive(Debug, Default)]
+pub struct HeldButtons {
+    /// Last press of each button, in `Buttons` bit order
+    pressed: [Option<Instant>; 8],
+}
+
+impl HeldButtons {
+    pub fn press(&mut self, buttons: Buttons, now: Instant) {
+        for (bit, pressed) in self.pressed.iter_mut().enumerate() {
+            if buttons.bits() & 1 << bit != 0 {
+                *pressed = Some(now);
+            }
+        }
+    }
+
+    pub fn held(&self, now: Instant) -> Buttons {
+        let bits = self
+            .pressed
+            .iter()
+            .enumerate()
+            .filter(|(_, pressed)| pressed.is_some_and(|at| now.duration_since(at) < HOLD))
+            .fold(0, |bits, (bit, _)| bits | 1 << bit);
+        Buttons::from_bits(bits)
+    }
+}
+
+// Draws frames with two pixels per character cell in 24-bit color. Cells are
+// remembered so a frame only sends the ones that changed.
+#[derive(Debug, Default)]
+pub struct TtyRenderer {
+    /// Top and bottom color of each cell on screen
+    cells: Vec<(u16, u16)>,
+    width: usize,
+    rows: usize,
+    /// Escape sequences of the frame being drawn, kept to reuse the buffer
+    output: Vec<u8>,
+}
+
+impl TtyRenderer {
+    pub fn create() -> Self {
+        TtyRenderer::default()
+    }
+
+    pub fn render(&mut self, frame: &Framebuffer, out: &mut impl Write) -> io::Result<()> {
+        let (width, rows) = (frame.width(), frame.height().div_ceil(2));
+        let full = (width, rows) != (self.width, self.rows);
+
+        self.output.clear();
+        if full {
+            self.cells = vec![(0, 0); width * rows];
+            (self.width, self.rows) = (width, rows);
+            self.output.extend_from_slice(b"\x1b[2J");
+        }
+
+        let mut cursor = None;
+        let (mut fg, mut bg) = (None, None);
+
+        for row in 0..rows {
+            for x in 0..width {
+                let top = frame.get(x, row * 2);
+                let bottom = match row * 2 + 1 {
+                    y if y < frame.height() => frame.get(x, y),
+                    _ => 0,
+                };
+
+                let cell = &mut self.cells[row * width + x];
+                if !full && *cell == (top, bottom) {
+                    continue;
+                }
+                *cell = (top, bottom);
+
+                if cursor != Some((x, row)) {
+                    write!(self.output, "\x1b[{};{}H", row + 1, x + 1)?;
+                }
+                if fg != Some(top) {
+                    let [r, g, b] = Framebuffer::to_rgb888(top);
+                    write!(self.output, "\x1b[38;2;{r};{g};{b}m")?;
+                    fg = Some(top);
+                }
+                if bg != Some(bottom) {
+                    let [r, g, b] = Framebuffer::to_rgb888(bottom);
+                    write!(self.output, "\x1b[48;2;{r};{g};{b}m")?;
+                    bg = Some(bottom);
+                }
+                self.output.extend_from_slice(HALF_BLOCK.as_bytes());
+                cursor = Some((x + 1, row));
+            }
+        }
+
+        if self.output.is_empty() {
+            return Ok(());
+        }
+
+        self.output.extend_from_slice(b"\x1b[0m");
+        out.write_all(&self.output)?;
+        out.flush()
+    }
+}
+
+/// Runs `stty` on the terminal and returns what it prints
+fn stty(args: &[&str]) -> io::Result<String> {
+    let output = Command::new("stty")
+        .args(args)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other("stty failed, is stdin a terminal?"));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Raw mode on the alternate screen, restored when dropped
+struct RawTerminal {
+    saved: String,
+}
+
+impl RawTerminal {
+    fn enter() -> io::Result<Self> {
+        let saved = stty(&["-g"])?;
+        stty(&["raw", "-echo"])?;
+
+        let mut stdout = io::stdout();
+        stdout.write_all(b"\x1b[?1049h\x1b[?25l")?;
+        stdout.flush()?;
+
+        Ok(RawTerminal { saved })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        let _ = stdout.write_all(b"\x1b[0m\x1b[?25h\x1b[?1049l");
+        let _ = stdout.flush();
+        let _ = stty(&[&self.saved]);
+    }
+}
+
+/// Shows frames in the terminal and sends keyboard input back until the
+/// emulator stops or the user quits
+pub fn run_tty(mut frames: TripleReader<Framebuffer>, control: Sender<Control>) -> io::Result<()> {
+    let _terminal = RawTerminal::enter()?;
+
+    // Reads block, so they get a thread of their own
+    let (key_tx, key_rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut stdin = io::stdin();
+        let mut buf = [0; 64];
+        while let Ok(n @ 1..) = stdin.read(&mut buf) {
+            if key_tx.send(decode_keys(&buf[..n])).is_err() {
+                return;
+            }
+        }
+    });
+
+    let mut renderer = TtyRenderer::create();
+    let mut held = HeldButtons::default();
+    let mut buttons = Buttons::NONE;
+    let mut paused = false;
+    let mut stdout = io::stdout().lock();
+
+    while !frames.is_closed() {
+        let now = Instant::now();
+
+        for key in key_rx.try_iter().flatten() {
+            // The emulator may be gone already, the frames tell
+            let _ = match key {
+                Key::Button(button) => {
+                    held.press(button, now);
+                    continue;
+                }
+                Key::Pause => {
+                    paused = !paused;
+                    control.send(if paused {
+                        Control::Pause
+                    } else {
+                        Control::Resume
+                    })
+                }
+                Key::Reset => control.send(Control::Reset),
+                Key::Cheat(index) => control.send(Control::ToggleCheat(index)),
+                Key::Quit => {
+                    let _ = control.send(Control::Quit);
+                    return Ok(());
+                }
+            };
+        }
+
+        let current = held.held(now);
+        if current != buttons {
+            buttons = current;
+            let _ = control.send(Control::Input(buttons));
+        }
+
+        if let Some(result) = frames.read(|frame| renderer.render(frame, &mut stdout)) {
+            result?;
+        }
+
+        thread::sleep(POLL);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn redraws_changed_cells() {
+        let mut renderer = gameboy::TtyRenderer::create();
+        let mut frame = Framebuffer::create(4, 3);
+        let mut out = Vec::new();
+
+        renderer.render(&frame, &mut out).expect("render");
+        let text = String::from_utf8(out).expect("UTF-8");
+        // 4 columns and 2 rows, the last one with a black bottom half
+        assert_eq!(text.matches('\u{2580}').count(), 8);
+        assert!(text.starts_with("\x1b[2J\x1b[1;1H\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m"));
+
+        let mut out = Vec::new();
+        renderer.render(&frame, &mut out).expect("render");
+        assert!(out.is_empty());
+
+        frame.set(2, 1, Framebuffer::rgb555(31, 0, 0));
+        let mut out = Vec::new();
+        renderer.render(&frame, &mut out).expect("render");
+        assert_eq!(
+            String::from_utf8(out).expect("UTF-8"),
+            "\x1b[1;3H\x1b[38;2;0;0;0m\x1b[48;2;255;0;0m\u{2580}\x1b[0m"
+        );
+    }
+
+    #[test]
+    fn keys() {
+        use gameboy::Key;
+
+        assert_eq!(
+            gameboy::decode_keys(b"\x1b[A\x1bOCxz\r ?p3q\x03"),
+            [
+                Key::Button(gameboy::Buttons::UP),
+                Key::Button(gameboy::Buttons::RIGHT),
+                Key::Button(gameboy::Buttons::A),
+                Key::Button(gameboy::Buttons::B),
+                Key::Button(gameboy::Buttons::START),
+                Key::Button(gameboy::Buttons::SELECT),
+                Key::Pause,
+                Key::Cheat(2),
+                Key::Quit,
+                Key::Quit,
+            ]
+        );
+    }
+
+    #[test]
+    fn held_buttons_time_out() {
+        let start = Instant::now();
+        let mut held = gameboy::HeldButtons::default();
+
+        held.press(gameboy::Buttons::A, start);
+        held.press(gameboy::Buttons::LEFT, start + Duration::from_millis(200));
+        assert_eq!(
+            held.held(start + Duration::from_millis(210)),
+            gameboy::Buttons::A | gameboy::Buttons::LEFT
+        );
+        assert_eq!(
+            held.held(start + Duration::from_millis(300)),
+            gameboy::Buttons::LEFT
+        );
+        assert_eq!(
+            held.held(start + Duration::from_secs(1)),
+            gameboy::Buttons::NONE
+        );
+    }
+}
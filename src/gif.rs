@@ -0,0 +1,288 @@
+use crate::framebuffer::Framebuffer;
+
+use std::collections::HashMap;
+use std::io;
+use std::io::Write;
+
+/// LZW codes are at most 12 bits wide
+const MAX_CODE: u16 = 4095;
+const MAX_CODE_WIDTH: u8 = 12;
+/// Image data is split into sub-blocks of at most this size
+const SUB_BLOCK_SIZE: usize = 255;
+
+// Animated GIF writer with a single global palette. Frames are given as
+// palette indexes and compressed with LZW.
+#[derive(Debug)]
+pub struct GifWriter<W: Write> {
+    out: W,
+    width: u16,
+    height: u16,
+    /// Bits per palette index, the palette holds 2^bits colors
+    bits: u8,
+}
+
+impl<W: Write> GifWriter<W> {
+    /// Writes the header, palette and looping extension. The palette takes
+    /// up to 256 colors and is padded with black to a power of two.
+    pub fn create(mut out: W, width: u16, height: u16, palette: &[u16]) -> io::Result<Self> {
+        if palette.is_empty() || palette.len() > 256 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "GIF palettes hold 1 to 256 colors",
+            ));
+        }
+
+        let bits = (usize::BITS - (palette.len() - 1).leading_zeros()).max(1) as u8;
+
+        out.write_all(b"GIF89a")?;
+        out.write_all(&width.to_le_bytes())?;
+        out.write_all(&height.to_le_bytes())?;
+        // Global palette present, 8 bits per channel
+        out.write_all(&[0x80 | 0x70 | (bits - 1), 0, 0])?;
+        for index in 0..1 << bits {
+            let color = palette.get(index).copied().unwrap_or(0);
+            out.write_all(&Framebuffer::to_rgb888(color))?;
+        }
+
+        // Loop forever
+        out.write_all(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00")?;
+
+        Ok(GifWriter {
+            out,
+            width,
+            height,
+            bits,
+        })
+    }
+
+    /// Adds a frame shown for `delay` hundredths of a second
+    pub fn write_frame(&mut self, indexes: &[u8], delay: u16) -> io::Result<()> {
+        if indexes.len() != self.width as usize * self.height as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame size differs from the GIF size",
+            ));
+        }
+
+        // Graphic control extension with the delay
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&delay.to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole screen
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&self.width.to_le_bytes())?;
+        self.out.write_all(&self.height.to_le_bytes())?;
+        self.out.write_all(&[0x00])?;
+
+        // LZW needs at least 2 bit codes
+        let min_code_size = self.bits.max(2);
+        let data = lzw_encode(indexes, min_code_size);
+        self.out.write_all(&[min_code_size])?;
+        for block in data.chunks(SUB_BLOCK_SIZE) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+
+    /// Writes the trailer and hands back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+/// Packs codes LSB first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, width: u8) {
+        self.buffer |= (code as u32) << self.count;
+        self.count += width;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// Variable width LZW as GIF uses it. The dictionary starts over with a
+/// clear code once all 12 bit codes are used.
+fn lzw_encode(indexes: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+
+    let mut bits = BitWriter::default();
+    let mut dictionary: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next = end + 1;
+    let mut width = min_code_size + 1;
+    let mut prefix: Option<u16> = None;
+
+    bits.write(clear, width);
+
+    for &index in indexes {
+        let Some(code) = prefix else {
+            prefix = Some(index as u16);
+            continue;
+        };
+
+        if let Some(&longer) = dictionary.get(&(code, index)) {
+            prefix = Some(longer);
+            continue;
+        }
+
+        bits.write(code, width);
+        dictionary.insert((code, index), next);
+        next += 1;
+
+        // The decoder adds its entries a code later, so widths change once
+        // the code after next would no longer fit
+        if next > 1 << width && width < MAX_CODE_WIDTH {
+            width += 1;
+        }
+        if next > MAX_CODE {
+            bits.write(clear, width);
+            dictionary.clear();
+            next = end + 1;
+            width = min_code_size + 1;
+        }
+
+        prefix = Some(index as u16);
+    }
+
+    if let Some(code) = prefix {
+        bits.write(code, width);
+        // The decoder adds an entry for the last code too
+        if next == 1 << width && width < MAX_CODE_WIDTH {
+            width += 1;
+        }
+    }
+    bits.write(end, width);
+
+    bits.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// Minimal GIF LZW decoder to check the encoder against
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1u16 << min_code_size;
+        let end = clear + 1;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let reset = |table: &mut Vec<Vec<u8>>| {
+            table.clear();
+            table.extend((0..clear).map(|i| vec![i as u8]));
+            table.extend([Vec::new(), Vec::new()]);
+        };
+        reset(&mut table);
+
+        let mut width = min_code_size + 1;
+        let (mut buffer, mut count, mut pos) = (0u32, 0u8, 0);
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+
+        loop {
+            while count < width {
+                buffer |= (data[pos] as u32) << count;
+                pos += 1;
+                count += 8;
+            }
+            let code = (buffer & ((1 << width) - 1)) as u16;
+            buffer >>= width;
+            count -= width;
+
+            if code == clear {
+                reset(&mut table);
+                width = min_code_size + 1;
+                previous = None;
+                continue;
+            }
+            if code == end {
+                return output;
+            }
+
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => {
+                    let mut entry = previous.clone();
+                    entry.push(previous[0]);
+                    entry
+                }
+                (None, None) => panic!("bad first code {code}"),
+            };
+            if let Some(mut previous) = previous.take() {
+                if table.len() < 4096 {
+                    previous.push(entry[0]);
+                    table.push(previous);
+                }
+            }
+            if table.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+
+            output.extend_from_slice(&entry);
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        let noise: Vec<u8> = (0..40000u32)
+            .map(|i| (i.wrapping_mul(2654435761) >> 13) as u8 & 0x0F)
+            .collect();
+        let runs: Vec<u8> = (0..20000u32).map(|i| (i / 300) as u8 & 0x03).collect();
+
+        for (indexes, min_code_size) in [
+            (vec![0], 2),
+            (vec![1, 1, 1, 1, 1, 1, 1], 2),
+            (vec![0, 1, 2, 3, 0, 1, 2, 3, 0, 1], 2),
+            (noise, 4),
+            (runs, 2),
+        ] {
+            let data = super::lzw_encode(&indexes, min_code_size);
+            assert_eq!(lzw_decode(&data, min_code_size), indexes);
+        }
+    }
+
+    #[test]
+    fn animated_gif() {
+        let palette = [0x0000, 0x7FFF, 0x001F];
+        let mut gif = GifWriter::create(Vec::new(), 2, 2, &palette).expect("header");
+        gif.write_frame(&[0, 1, 2, 0], 3).expect("frame");
+        assert!(gif.write_frame(&[0], 3).is_err());
+        let bytes = gif.finish().expect("trailer");
+
+        assert_eq!(&bytes[..6], b"GIF89a");
+        // 2x2 with a 4 color palette
+        assert_eq!(&bytes[6..11], &[2, 0, 2, 0, 0xF1]);
+        assert_eq!(
+            &bytes[13..25],
+            &[0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(
+            &bytes[25..44],
+            b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00"
+        );
+        assert_eq!(&bytes[44..52], &[0x21, 0xF9, 0x04, 0x00, 3, 0, 0, 0]);
+        assert_eq!(bytes.last(), Some(&0x3B));
+
+        assert!(GifWriter::create(Vec::new(), 1, 1, &[]).is_err());
+    }
+}
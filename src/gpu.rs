@@ -1,11 +1,30 @@
-use crate::addressable::Addressable;
+use crate::framebuffer::Framebuffer;
+use crate::inspect::Inspect;
+use crate::model::Model;
 use crate::ram::RAM;
-use crate::timed::Timed;
+use crate::timed::{CycleTime, Timed};
 
-pub trait GPU: Addressable + Timed + std::fmt::Debug {
-    fn create(vram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>) -> Self
+pub trait GPU: Inspect + Timed + std::fmt::Debug {
+    fn create(model: Model, vram: Box<dyn RAM<Addr = Self::Addr, Data = Self::Data>>) -> Self
     where
         Self: Sized;
 
     fn deep_copy(&self) -> Vec<Self::Data>;
+
+    /// The most recently rendered pixels
+    fn framebuffer(&self) -> &Framebuffer;
+
+    /// DMG shade, 0 for lightest, each framebuffer pixel was drawn in, row
+    /// by row. Meaningless in CGB mode.
+    fn shades(&self) -> &[u8];
+
+    /// Number of frames completed since creation
+    fn frames(&self) -> u64;
+
+    /// Number of horizontal blanking periods entered since creation
+    fn hblanks(&self) -> u64;
+
+    /// Time until the next mode change, when the GPU next needs to catch
+    /// up. None while the LCD is off.
+    fn next_event(&self) -> Option<CycleTime>;
 }
@@ -0,0 +1,22 @@
+use crate::addressable::{AddressError, Addressable};
+
+// Debugger access beside the CPU's. Inspecting reads what the CPU would see
+// without any of the side effects of a read, patching stores data as is,
+// ROM included, without the side effects of a write such as starting a
+// transfer. Debuggers, tracers and memory viewers go through here so they
+// never disturb emulation.
+pub trait Inspect: Addressable {
+    /// Defaults to a CPU read, for devices whose reads have no side effects
+    fn inspect(&self, addr: Self::Addr) -> Result<Self::Data, AddressError<Self::Addr>> {
+        self.read_byte(addr)
+    }
+
+    /// Defaults to a CPU write, for devices whose writes only store data
+    fn patch(
+        &mut self,
+        addr: Self::Addr,
+        data: Self::Data,
+    ) -> Result<(), AddressError<Self::Addr>> {
+        self.write_byte(addr, data)
+    }
+}
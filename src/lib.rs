@@ -1,26 +1,90 @@
 mod addressable;
+mod font;
+mod framebuffer;
+mod gif;
+mod inspect;
+mod model;
+mod png;
+mod scheduler;
 mod timed;
+mod triple_buffer;
+mod wav;
+mod y4m;
 pub use addressable::*;
+pub use font::*;
+pub use framebuffer::*;
+pub use gif::*;
+pub use inspect::*;
+pub use model::*;
+pub use png::*;
+pub use scheduler::*;
 pub use timed::*;
+pub use triple_buffer::*;
+pub use wav::*;
+pub use y4m::*;
 
 mod bus;
+mod cartridge;
 mod cpu;
 mod gpu;
 mod ram;
 pub use bus::*;
+pub use cartridge::*;
 pub use cpu::*;
 pub use gpu::*;
 pub use ram::*;
 
+mod gameboy_block;
 mod gameboy_bus;
+mod gameboy_cartridge;
+mod gameboy_cheats;
 mod gameboy_cpu;
 mod gameboy_cpu_inst;
+mod gameboy_disasm;
+mod gameboy_frontend;
+mod gameboy_gdb;
 mod gameboy_gpu;
+mod gameboy_hdma;
+mod gameboy_joypad;
+mod gameboy_movie;
+mod gameboy_pacer;
 mod gameboy_ram;
+mod gameboy_recorder;
+mod gameboy_screenshot;
+mod gameboy_script;
+mod gameboy_search;
+mod gameboy_serial;
+mod gameboy_sgb;
+mod gameboy_system;
+mod gameboy_testrom;
+mod gameboy_timer;
+mod gameboy_trace;
+mod gameboy_tty;
 
 pub mod gameboy {
     pub use crate::gameboy_bus::*;
+    pub use crate::gameboy_cartridge::*;
+    pub use crate::gameboy_cheats::*;
     pub use crate::gameboy_cpu::*;
+    pub use crate::gameboy_cpu_inst::*;
+    pub use crate::gameboy_disasm::*;
+    pub use crate::gameboy_frontend::*;
+    pub use crate::gameboy_gdb::*;
     pub use crate::gameboy_gpu::*;
+    pub use crate::gameboy_hdma::*;
+    pub use crate::gameboy_joypad::*;
+    pub use crate::gameboy_movie::*;
+    pub use crate::gameboy_pacer::*;
     pub use crate::gameboy_ram::*;
+    pub use crate::gameboy_recorder::*;
+    pub use crate::gameboy_screenshot::*;
+    pub use crate::gameboy_script::*;
+    pub use crate::gameboy_search::*;
+    pub use crate::gameboy_serial::*;
+    pub use crate::gameboy_sgb::*;
+    pub use crate::gameboy_system::*;
+    pub use crate::gameboy_testrom::*;
+    pub use crate::gameboy_timer::*;
+    pub use crate::gameboy_trace::*;
+    pub use crate::gameboy_tty::*;
 }
@@ -1,46 +1,722 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::net::TcpListener;
+use std::panic;
+use std::path::Path;
+use std::process;
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
+use std::sync::mpsc::TryRecvError;
 use std::thread;
 
 use gamerboy::*;
 
-enum GUIData {
-    VRAMBuf(Vec<u8>),
-    Shutdown,
+const USAGE: &str = "usage: gamerboy [options] <rom.gb>
+       gamerboy disasm <rom.gb>
+       gamerboy test [--timeout <seconds>] [--block-cache] [dir]
+       gamerboy screenshot [--model <model>] [--frames <n>] [--reference <ref.png>] <rom.gb> <out.png>
+       gamerboy verify <movie> <rom.gb>
+
+options:
+    --model <model>        emulate dmg, mgb, sgb, cgb or agb instead of the
+                           model the cartridge header asks for
+    --speed <n>            run at n times normal speed, e.g. 2 or 0.5, or
+                           unthrottled
+    --frame-skip <n>       skip n frames after each one shown
+    --frontend <name>      show frames with none or tty, the terminal
+                           frontend takes arrows, x, z, enter and space as
+                           the joypad, p to pause, r to reset, 1-9 to
+                           toggle cheats and q to quit
+    --cheats <file>        load Game Genie and GameShark codes from file
+                           instead of the .cht file next to the ROM
+    --record <file>        record frames to an animated .gif or a raw .y4m
+                           video, also available as `monitor record` in GDB
+    --record-audio         write a .wav track next to a .y4m recording, silent
+                           until sound is emulated
+    --record-frames <n>    stop recording after n frames
+    --movie-record <file>  record the joypad input of every frame to a movie,
+                           not together with cheats, --script or --gdb
+    --movie-play <file>    replay a movie, reporting the first frame whose
+                           screen differs from the recording, not together
+                           with cheats, --script or --gdb
+    --movie-hash-interval <n>
+                           hash the screen every n frames while recording a
+                           movie, 60 by default and 0 for never
+    --script <file>        run a Rhai script with callbacks on frames,
+                           executed addresses and memory accesses
+    --watch <addr>[:16][=name]
+                           print the byte or little-endian word at the
+                           hexadecimal address addr after every frame, may
+                           be repeated
+    --block-cache          predecode straight-line code ahead of time, faster
+                           but opcode and immediate operand fetches bypass
+                           --script read callbacks
+    --trace <file>         log every instruction in the Gameboy Doctor format
+    --trace-limit <n>      stop after tracing n instructions
+    --trace-until <pc>     stop once PC reaches the hexadecimal address pc
+    --gdb <port>           wait for a GDB remote debugger on localhost:port,
+                           `monitor search` finds values in RAM";
+
+/// Command line options of the emulator
+#[derive(Debug, Default)]
+struct Options {
+    rom: String,
+    /// Overrides the model picked from the cartridge header
+    model: Option<Model>,
+    speed: gameboy::Speed,
+    frame_skip: u32,
+    frontend: gameboy::Frontend,
+    /// Cheat file, defaults to the one next to the ROM
+    cheats: Option<String>,
+    /// Video recording destination
+    record: Option<String>,
+    record_audio: bool,
+    record_frames: Option<u64>,
+    /// Input movie destination
+    movie_record: Option<String>,
+    movie_play: Option<String>,
+    movie_hash_interval: u64,
+    /// Rhai script run alongside the game
+    script: Option<String>,
+    watch: gameboy::RamWatch,
+    block_cache: bool,
+    /// Gameboy Doctor log destination
+    trace: Option<String>,
+    trace_limit: Option<u64>,
+    trace_until: Option<u16>,
+    /// Local port to serve the GDB remote protocol on
+    gdb: Option<u16>,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        movie_hash_interval: 60,
+        ..Options::default()
+    };
+    let mut rom = None;
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+
+        match arg.as_str() {
+            "--model" => options.model = Some(value()?.parse()?),
+            "--speed" => options.speed = value()?.parse()?,
+            "--frame-skip" => {
+                let n = value()?;
+                options.frame_skip = n.parse().map_err(|_| format!("bad frame count {n}"))?;
+            }
+            "--frontend" => options.frontend = value()?.parse()?,
+            "--cheats" => options.cheats = Some(value()?.clone()),
+            "--record" => options.record = Some(value()?.clone()),
+            "--record-audio" => options.record_audio = true,
+            "--record-frames" => {
+                let n = value()?;
+                options.record_frames =
+                    Some(n.parse().map_err(|_| format!("bad frame count {n}"))?);
+            }
+            "--movie-record" => options.movie_record = Some(value()?.clone()),
+            "--movie-play" => options.movie_play = Some(value()?.clone()),
+            "--movie-hash-interval" => {
+                let n = value()?;
+                options.movie_hash_interval =
+                    n.parse().map_err(|_| format!("bad frame count {n}"))?;
+            }
+            "--script" => options.script = Some(value()?.clone()),
+            "--watch" => options.watch.add(value()?.parse()?),
+            "--block-cache" => options.block_cache = true,
+            "--trace" => options.trace = Some(value()?.clone()),
+            "--trace-limit" => {
+                let n = value()?;
+                options.trace_limit = Some(n.parse().map_err(|_| format!("bad count {n}"))?);
+            }
+            "--trace-until" => {
+                let pc = value()?;
+                let hex = pc.trim_start_matches("0x");
+                options.trace_until =
+                    Some(u16::from_str_radix(hex, 16).map_err(|_| format!("bad address {pc}"))?);
+            }
+            "--gdb" => {
+                let port = value()?;
+                options.gdb = Some(port.parse().map_err(|_| format!("bad port {port}"))?);
+            }
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}")),
+            path => rom = Some(path.to_string()),
+        }
+    }
+
+    options.rom = rom.ok_or("missing ROM path")?;
+    if options.movie_record.is_some() && options.movie_play.is_some() {
+        return Err("a movie can't be recorded while another one plays".to_string());
+    }
+    let movie = options.movie_record.is_some() || options.movie_play.is_some();
+    // Movies only hold joypad input, anything else changing the machine
+    // would be missing on replay or make it diverge
+    if movie && options.script.is_some() {
+        return Err("movies can't be recorded or played while a script runs".to_string());
+    }
+    // The debugger runs the machine before the movie starts
+    if movie && options.gdb.is_some() {
+        return Err("movies can't be recorded or played with a debugger".to_string());
+    }
+
+    Ok(options)
+}
+
+/// Opens the trace log requested on the command line, if any
+fn tracer(options: &Options) -> Result<Option<gameboy::Tracer<fs::File>>, Box<dyn Error>> {
+    let Some(path) = &options.trace else {
+        return Ok(None);
+    };
+
+    let mut tracer = gameboy::Tracer::create(fs::File::create(path)?);
+    if let Some(n) = options.trace_limit {
+        tracer.stop_after(n);
+    }
+    if let Some(pc) = options.trace_until {
+        tracer.stop_at(pc);
+    }
+
+    Ok(Some(tracer))
+}
+
+/// Hands control of the CPU to a remote debugger until it detaches
+fn debug_session(cpu: &mut gameboy::CPU, port: u16) -> Result<(), Box<dyn Error>> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    eprintln!("waiting for debugger on {}", listener.local_addr()?);
+
+    let (stream, peer) = listener.accept()?;
+    eprintln!("debugger attached from {peer}");
+    gameboy::GdbStub::create().serve(cpu, stream)?;
+    eprintln!("debugger detached");
+
+    Ok(())
+}
+
+/// Completes a recording started with --record
+fn finish_recording(recorder: Option<gameboy::Recorder>) {
+    let Some(recorder) = recorder else {
+        return;
+    };
+
+    let frames = recorder.frames();
+    match recorder.finish() {
+        Ok(()) => eprintln!("recorded {frames} frames"),
+        Err(err) => eprintln!("record: {err}"),
+    }
+}
+
+/// Runs part of a --script, a script that fails is reported and stopped
+fn run_script(
+    script: &mut Option<gameboy::Script>,
+    run: impl FnOnce(&mut gameboy::Script) -> Result<(), gameboy::ScriptError>,
+) {
+    if let Some(err) = script.as_mut().and_then(|active| run(active).err()) {
+        eprintln!("script: {err}");
+        *script = None;
+    }
+}
+
+/// Holds the buttons a --script asked for, as if they came from the
+/// frontend
+fn script_input(
+    script: &mut Option<gameboy::Script>,
+    buttons: &mut gameboy::Buttons,
+    cpu: &mut gameboy::CPU,
+) {
+    let held = script.as_mut().and_then(|active| active.take_buttons());
+    if let Some(held) = held {
+        *buttons = held;
+        cpu.bus_mut().set_buttons(held);
+    }
+}
+
+/// Saves a movie started with --movie-record
+fn save_movie(path: &str, movie: &gameboy::Movie) {
+    match fs::write(path, movie.to_string()) {
+        Ok(()) => eprintln!("recorded {} frames to {path}", movie.frames.len()),
+        Err(err) => eprintln!("{path}: {err}"),
+    }
+}
+
+/// Plays a movie as fast as possible and reports whether the screens match
+/// the recording
+fn verify(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let [movie, rom] = args else {
+        return Err("expected <movie> <rom.gb>".into());
+    };
+
+    let movie = gameboy::Movie::parse(&fs::read_to_string(movie)?)?;
+    let frames = movie.frames.len();
+    let checked = movie.frames.iter().filter(|f| f.hash.is_some()).count();
+
+    match gameboy::verify_movie(movie, fs::read(rom)?)? {
+        Some(divergence) => {
+            println!("{divergence}");
+            Ok(false)
+        }
+        None => {
+            println!("{frames} frames played, {checked} screens match");
+            Ok(true)
+        }
+    }
+}
+
+/// Prints a labelled recursive-descent disassembly of a ROM file
+fn disasm(path: &str) -> Result<(), Box<dyn Error>> {
+    let cartridge = gameboy::Cartridge::create(fs::read(path)?);
+    let listing = gameboy::Listing::walk(&cartridge, &gameboy::ENTRY_POINTS);
+
+    println!("; {}", cartridge.title());
+    print!("{listing}");
+
+    Ok(())
+}
+
+/// Runs every test ROM below a directory, defaulting to $GAMERBOY_TEST_ROMS,
+/// and returns whether all ROMs expected to pass still do
+fn test(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let mut timeout_secs = 120u64;
+    let mut block_cache = false;
+    let mut dir = env::var("GAMERBOY_TEST_ROMS").unwrap_or_else(|_| "test-roms".to_string());
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--timeout" => {
+                let secs = args.next().ok_or("missing value for --timeout")?;
+                timeout_secs = secs.parse().map_err(|_| format!("bad timeout {secs}"))?;
+            }
+            "--block-cache" => block_cache = true,
+            path => dir = path.to_string(),
+        }
+    }
+
+    let dir = Path::new(&dir);
+    let expected = gameboy::expected_passing(dir)?;
+    let roms = gameboy::find_test_roms(dir)?;
+    let timeout = timeout_secs * Model::CYCLE_CLOCK as u64;
+
+    let mut passed = 0;
+    let mut regressions = Vec::new();
+
+    for rom in &roms {
+        let name = rom.strip_prefix(dir).unwrap_or(rom);
+        let report = gameboy::run_test_rom(fs::read(rom)?, timeout, block_cache);
+
+        println!(
+            "{:<8} {:>12} {}",
+            report.verdict,
+            report.cycles,
+            name.display()
+        );
+        if let gameboy::Verdict::Crashed(reason) | gameboy::Verdict::Unsupported(reason) =
+            &report.verdict
+        {
+            println!("         {reason}");
+        }
+
+        match report.verdict {
+            gameboy::Verdict::Passed => passed += 1,
+            _ if expected.contains(name) => regressions.push(name),
+            _ => {}
+        }
+    }
+
+    println!("{passed}/{} passed", roms.len());
+    for name in &regressions {
+        println!("regression: {}", name.display());
+    }
+
+    Ok(regressions.is_empty())
+}
+
+/// Writes the frame a ROM shows after a number of frames to a PNG and
+/// compares it against a reference image if given. Returns whether the
+/// screenshot matched, a diff image is written next to it if not.
+fn screenshot(args: &[String]) -> Result<bool, Box<dyn Error>> {
+    let mut frames = 60u64;
+    let mut model = None;
+    let mut reference = None;
+    let mut paths = Vec::new();
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {arg}"))
+        };
+
+        match arg.as_str() {
+            "--frames" => {
+                let n = value()?;
+                frames = n.parse().map_err(|_| format!("bad frame count {n}"))?;
+            }
+            "--model" => model = Some(value()?.parse::<Model>()?),
+            "--reference" => reference = Some(value()?.clone()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {flag}").into()),
+            path => paths.push(path),
+        }
+    }
+
+    let [rom, out] = paths[..] else {
+        return Err("expected <rom.gb> <out.png>".into());
+    };
+
+    let cartridge = gameboy::Cartridge::create(fs::read(rom)?);
+    let model = model.unwrap_or(cartridge.model());
+    let frame = gameboy::screenshot_rom(model, cartridge, frames)?;
+    fs::write(out, encode_png(&frame))?;
+
+    let Some(reference) = reference else {
+        return Ok(true);
+    };
+
+    let comparison = gameboy::compare(&frame, &decode_png(&fs::read(&reference)?)?);
+    if comparison.matches() {
+        println!("{out} matches {reference}");
+        return Ok(true);
+    }
+
+    let diff = Path::new(out).with_extension("diff.png");
+    fs::write(&diff, encode_png(&comparison.diff))?;
+    println!(
+        "{out} differs from {reference} in {} pixels, see {}",
+        comparison.mismatched,
+        diff.display()
+    );
+
+    Ok(false)
 }
 
 // Gameboy EMU
 fn main() {
-    let ram = Box::new(gameboy::RAM::<{ 8 * 1024 }>::create(0xC000));
-    let vram = Box::new(gameboy::RAM::<{ 8 * 1024 }>::create(0x8000));
-    let gpu = Box::new(gameboy::GPU::create(vram));
-    let bus = Box::new(gameboy::Bus::create(ram, gpu));
-    let mut cpu = gameboy::CPU::create(4194304, bus);
-
-    let (tx, rx): (Sender<GUIData>, Receiver<GUIData>) = mpsc::channel();
-
-    thread::spawn(move || loop {
-        let data = rx.recv().unwrap();
-        let vram = match data {
-            GUIData::Shutdown => return,
-            GUIData::VRAMBuf(vram) => vram,
+    let args: Vec<String> = env::args().collect();
+
+    if let Some("disasm") = args.get(1).map(String::as_str) {
+        let Some(path) = args.get(2) else {
+            eprintln!("usage: {} disasm <rom.gb>", args[0]);
+            process::exit(2);
         };
 
-        dbg!(vram);
+        if let Err(err) = disasm(path) {
+            eprintln!("disasm: {err}");
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let Some("test") = args.get(1).map(String::as_str) {
+        // Crashes are part of the report, keep panic messages off stderr
+        panic::set_hook(Box::new(|_| {}));
+
+        match test(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("test: {err}");
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Some("screenshot") = args.get(1).map(String::as_str) {
+        match screenshot(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("screenshot: {err}\n{USAGE}");
+                process::exit(2);
+            }
+        }
+    }
+
+    if let Some("verify") = args.get(1).map(String::as_str) {
+        match verify(&args[2..]) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                eprintln!("verify: {err}\n{USAGE}");
+                process::exit(2);
+            }
+        }
+    }
+
+    let options = parse_options(&args[1..]).unwrap_or_else(|err| {
+        eprintln!("{err}\n{USAGE}");
+        process::exit(2);
+    });
+    let rom = fs::read(&options.rom).unwrap_or_else(|err| {
+        eprintln!("{}: {err}", options.rom);
+        process::exit(1);
+    });
+    let mut tracer = tracer(&options).unwrap_or_else(|err| {
+        eprintln!("trace: {err}");
+        process::exit(1);
     });
 
-    loop {
-        // TODO: Handle interrupts before stepping
+    let mut playback = options.movie_play.as_ref().map(|path| {
+        fs::read_to_string(path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| gameboy::Movie::parse(&text).map_err(|err| err.to_string()))
+            .map(gameboy::Playback::create)
+            .unwrap_or_else(|err| {
+                eprintln!("{path}: {err}");
+                process::exit(1);
+            })
+    });
 
-        let cycles = match cpu.step() {
-            Err(_) => break,
-            Ok(c) => c,
-        };
-        dbg!(cycles);
+    let cartridge = gameboy::Cartridge::create(rom.clone());
+    let (model, mut cpu) = match &playback {
+        Some(playback) => {
+            let movie = playback.movie();
+            let cpu = movie.boot(rom.clone()).unwrap_or_else(|err| {
+                eprintln!("movie: {err}");
+                process::exit(1);
+            });
+            (movie.model, cpu)
+        }
+        None => {
+            let model = options.model.unwrap_or(cartridge.model());
+            (model, gameboy::create_system(model, cartridge))
+        }
+    };
 
-        // Send vram to GUI thread
-        tx.send(GUIData::VRAMBuf(vec![0x00])).unwrap();
+    let cheats_path = match &options.cheats {
+        Some(path) => Path::new(path).to_path_buf(),
+        None => gameboy::Cheats::path_for(Path::new(&options.rom)),
+    };
+    let mut cheats = gameboy::Cheats::load(&cheats_path).unwrap_or_else(|err| {
+        eprintln!("{}: {err}", cheats_path.display());
+        process::exit(1);
+    });
+    let movie = options.movie_record.is_some() || options.movie_play.is_some();
+    if movie && !cheats.cheats().is_empty() {
+        eprintln!(
+            "{}: movies can't be recorded or played with cheats",
+            cheats_path.display()
+        );
+        process::exit(1);
+    }
+    if !cheats.cheats().is_empty() {
+        eprintln!(
+            "loaded {} cheats from {}",
+            cheats.cheats().len(),
+            cheats_path.display()
+        );
     }
+    cheats.install(&mut cpu);
+    cpu.set_block_cache(options.block_cache);
+
+    let mut script = options.script.as_ref().map(|path| {
+        gameboy::Script::load(Path::new(path), &mut cpu).unwrap_or_else(|err| {
+            eprintln!("{path}: {err}");
+            process::exit(1);
+        })
+    });
+
+    let mut movie = options
+        .movie_record
+        .as_ref()
+        .map(|_| gameboy::Movie::create(model, &rom, options.movie_hash_interval));
 
-    tx.send(GUIData::Shutdown).unwrap();
+    if let Some(port) = options.gdb {
+        if let Err(err) = debug_session(&mut cpu, port) {
+            eprintln!("gdb: {err}");
+            process::exit(1);
+        }
+    }
+
+    let (mut frames, frames_reader) = triple_buffer(Framebuffer::create(0, 0));
+    let (control_tx, control_rx) = mpsc::channel();
+    let frontend = options.frontend;
+    let display = thread::spawn(move || frontend.run(frames_reader, control_tx));
+
+    if options.record.is_some() && options.record_audio {
+        eprintln!("warning: {}", gameboy::SILENT_AUDIO);
+    }
+    let mut recorder = options.record.as_ref().and_then(|path| {
+        gameboy::Recorder::create(Path::new(path), model, options.record_audio)
+            .map_err(|err| eprintln!("{path}: {err}"))
+            .ok()
+    });
+    let mut recorded = Framebuffer::create(0, 0);
+
+    let mut pacer = gameboy::FramePacer::create(options.speed, options.frame_skip);
+    let clock = cpu.model().clock();
+
+    let mut paused = false;
+    let mut buttons = gameboy::Buttons::NONE;
+    let mut reset = false;
+
+    'frames: loop {
+        // Blocks for the next request while paused
+        loop {
+            let control = if paused {
+                match control_rx.recv() {
+                    Ok(control) => control,
+                    Err(_) => break 'frames,
+                }
+            } else {
+                match control_rx.try_recv() {
+                    Ok(control) => control,
+                    Err(TryRecvError::Empty) => break,
+                    // The frontend is gone
+                    Err(TryRecvError::Disconnected) => break 'frames,
+                }
+            };
+
+            match control {
+                gameboy::Control::Pause => paused = true,
+                gameboy::Control::Resume => {
+                    paused = false;
+                    pacer.restart();
+                }
+                // A playing movie has the machine to itself
+                gameboy::Control::Reset
+                | gameboy::Control::Input(_)
+                | gameboy::Control::ToggleCheat(_)
+                    if playback.is_some() => {}
+                gameboy::Control::Reset => {
+                    cpu = gameboy::create_system(model, gameboy::Cartridge::create(rom.clone()));
+                    cpu.bus_mut().set_buttons(buttons);
+                    cheats.install(&mut cpu);
+                    cpu.set_block_cache(options.block_cache);
+                    if let Some(active) = script.as_mut() {
+                        active.install(&mut cpu);
+                    }
+                    reset = true;
+                }
+                gameboy::Control::SaveState => eprintln!("save states are not supported yet"),
+                gameboy::Control::Input(held) => {
+                    buttons = held;
+                    cpu.bus_mut().set_buttons(buttons);
+                }
+                gameboy::Control::ToggleCheat(index) => {
+                    if let Some(enabled) = cheats.toggle(index) {
+                        let cheat = &cheats.cheats()[index];
+                        let state = if enabled { "on" } else { "off" };
+                        eprintln!("cheat {} {} {state}", cheat.code, cheat.name);
+                    }
+                }
+                gameboy::Control::Quit => break 'frames,
+            }
+        }
+
+        if let Some(active) = &playback {
+            active.apply(&mut cpu, &rom);
+            // Movie resets bring up a fresh machine
+            if active.input().is_some_and(|frame| frame.reset) {
+                cpu.set_block_cache(options.block_cache);
+            }
+        }
+        cheats.apply(&mut cpu);
+
+        let frame = cpu.bus().gpu().frames();
+        let mut cycles = 0;
+
+        while !gameboy::frame_over(&cpu, frame, cycles) {
+            // TODO: Handle interrupts before stepping
+
+            if let Some(tracer) = tracer.as_mut() {
+                match tracer.trace(&cpu) {
+                    Ok(true) => {}
+                    Ok(false) => break 'frames,
+                    Err(err) => {
+                        eprintln!("trace: {err}");
+                        break 'frames;
+                    }
+                }
+            }
+
+            run_script(&mut script, |active| active.before_step(&mut cpu));
+            script_input(&mut script, &mut buttons, &mut cpu);
+            cycles += match cpu.step() {
+                Err(err) => {
+                    eprintln!("cpu: {err}");
+                    break 'frames;
+                }
+                Ok(c) => c,
+            };
+            run_script(&mut script, |active| active.after_step(&mut cpu));
+            script_input(&mut script, &mut buttons, &mut cpu);
+        }
+
+        if let Some(active) = playback.as_mut() {
+            if let Some(divergence) = active.frame_done(&cpu) {
+                eprintln!("movie {divergence}");
+            }
+            if active.input().is_none() {
+                eprintln!("movie ended after {} frames", active.frames());
+                playback = None;
+                cpu.bus_mut().set_buttons(buttons);
+            }
+        }
+        if let Some(movie) = movie.as_mut() {
+            movie.record(buttons, reset, &cpu);
+            reset = false;
+        }
+
+        run_script(&mut script, |active| active.frame_end(&mut cpu));
+        script_input(&mut script, &mut buttons, &mut cpu);
+
+        if !options.watch.is_empty() {
+            eprintln!("{}", options.watch.line(cpu.bus()));
+        }
+
+        if pacer.frame_done() {
+            frames.write(|frame| {
+                gameboy::draw_screen(&cpu, frame);
+                if let Some(active) = &script {
+                    active.draw_overlay(frame);
+                }
+            });
+        }
+
+        if let Some(active) = recorder.as_mut() {
+            gameboy::draw_screen(&cpu, &mut recorded);
+            if let Some(script) = &script {
+                script.draw_overlay(&mut recorded);
+            }
+            if let Err(err) = active.record(&recorded, cpu.bus().gpu().shades()) {
+                eprintln!("record: {err}");
+                recorder = None;
+            } else if options.record_frames == Some(active.frames()) {
+                finish_recording(recorder.take());
+            }
+        }
+
+        if script
+            .as_ref()
+            .is_some_and(|active| active.exit_code().is_some())
+        {
+            break;
+        }
+
+        pacer.wait(CycleTime::new(clock, gameboy::FRAME_CYCLES as u64));
+    }
+
+    drop(frames);
+    if let Err(err) = display.join().unwrap() {
+        eprintln!("{frontend}: {err}");
+    }
+    finish_recording(recorder);
+    if let (Some(path), Some(movie)) = (&options.movie_record, &movie) {
+        save_movie(path, movie);
+    }
+
+    if let Some(tracer) = tracer {
+        let traced = tracer.count();
+        if let Err(err) = tracer.finish() {
+            eprintln!("trace: {err}");
+        }
+        eprintln!("traced {traced} instructions");
+    }
+
+    if let Some(code) = script.and_then(|active| active.exit_code()) {
+        process::exit(code);
+    }
 }
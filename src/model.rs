@@ -0,0 +1,65 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Game Boy hardware revisions, they differ in boot state, clock and
+/// features
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Model {
+    /// Original Game Boy
+    #[default]
+    DMG,
+    /// Game Boy Pocket
+    MGB,
+    /// Super Game Boy, runs off the SNES clock
+    SGB,
+    /// Game Boy Color
+    CGB,
+    /// Game Boy Advance running Game Boy software
+    AGB,
+}
+
+impl Model {
+    pub const ALL: [Model; 5] = [Model::DMG, Model::MGB, Model::SGB, Model::CGB, Model::AGB];
+
+    /// Clock in Hz every model's cycles are counted in, also the PPU's dot
+    /// clock. The SGB runs the same cycles faster, see `clock`.
+    pub const CYCLE_CLOCK: u32 = 4194304;
+
+    /// CPU clock in Hz at normal speed, how fast the hardware runs against
+    /// the wall clock
+    pub fn clock(self) -> u32 {
+        match self {
+            Model::SGB => 4295454,
+            _ => 4194304,
+        }
+    }
+
+    /// Whether the hardware has the CGB features, e.g. banked memory and
+    /// color palettes
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::CGB | Model::AGB)
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Model::DMG => f.write_str("dmg"),
+            Model::MGB => f.write_str("mgb"),
+            Model::SGB => f.write_str("sgb"),
+            Model::CGB => f.write_str("cgb"),
+            Model::AGB => f.write_str("agb"),
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Model::ALL
+            .into_iter()
+            .find(|model| model.to_string().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown model {s}"))
+    }
+}
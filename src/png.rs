@@ -0,0 +1,575 @@
+use crate::framebuffer::Framebuffer;
+
+use std::error::Error;
+use std::fmt;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+/// Largest payload of an uncompressed deflate block
+const STORED_BLOCK_SIZE: usize = 0xFFFF;
+
+const COLOR_GRAY: u8 = 0;
+const COLOR_RGB: u8 = 2;
+const COLOR_PALETTE: u8 = 3;
+const COLOR_GRAY_ALPHA: u8 = 4;
+const COLOR_RGBA: u8 = 6;
+
+#[derive(Debug)]
+pub enum PngError {
+    /// The data is not a PNG file or is damaged
+    Corrupt(&'static str),
+    /// Valid PNG features the decoder does not implement
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for PngError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PngError::Corrupt(what) => write!(f, "Corrupt PNG: {what}"),
+            PngError::Unsupported(what) => write!(f, "Unsupported PNG: {what}"),
+        }
+    }
+}
+
+impl Error for PngError {}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, &b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let (a, b) = bytes.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Encodes a frame as an 8-bit RGB PNG. Image data is stored without
+/// compression, which keeps the encoder trivial at the cost of file size.
+pub fn encode_png(frame: &Framebuffer) -> Vec<u8> {
+    let mut raw = Vec::with_capacity(frame.height() * (frame.width() * 3 + 1));
+    for row in frame.pixels().chunks(frame.width().max(1)) {
+        // Filter type None
+        raw.push(0);
+        for &color in row {
+            raw.extend_from_slice(&Framebuffer::to_rgb888(color));
+        }
+    }
+
+    // zlib stream of stored deflate blocks
+    let mut zlib = vec![0x78, 0x01];
+    let mut blocks = raw.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        zlib.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        zlib.push(last as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(frame.width() as u32).to_be_bytes());
+    header.extend_from_slice(&(frame.height() as u32).to_be_bytes());
+    // 8-bit RGB, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, COLOR_RGB, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib);
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Least-significant-bit first reader over a deflate stream
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit: u32,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, count: u32) -> Result<u32, PngError> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or(PngError::Corrupt("truncated image data"))?;
+            value |= ((byte >> self.bit) as u32 & 1) << i;
+            self.bit += 1;
+            if self.bit == 8 {
+                self.bit = 0;
+                self.pos += 1;
+            }
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        if self.bit != 0 {
+            self.bit = 0;
+            self.pos += 1;
+        }
+    }
+}
+
+/// Canonical Huffman code stored as code counts per length and the symbols
+/// ordered by code
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn create(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, PngError> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+
+        for len in 1..16 {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+
+        Err(PngError::Corrupt("bad Huffman code"))
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// Order in which code length code lengths are stored in dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+fn inflate_block(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    lengths: &Huffman,
+    distances: &Huffman,
+) -> Result<(), PngError> {
+    loop {
+        let symbol = lengths.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let index = symbol - 257;
+                if index >= LENGTH_BASE.len() {
+                    return Err(PngError::Corrupt("bad length symbol"));
+                }
+                let len =
+                    LENGTH_BASE[index] as usize + reader.bits(LENGTH_EXTRA[index] as u32)? as usize;
+
+                let index = distances.decode(reader)? as usize;
+                if index >= DIST_BASE.len() {
+                    return Err(PngError::Corrupt("bad distance symbol"));
+                }
+                let dist =
+                    DIST_BASE[index] as usize + reader.bits(DIST_EXTRA[index] as u32)? as usize;
+                if dist > out.len() {
+                    return Err(PngError::Corrupt("distance before start of data"));
+                }
+
+                let start = out.len() - dist;
+                for i in 0..len {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+}
+
+fn dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), PngError> {
+    let literals = reader.bits(5)? as usize + 257;
+    let distances = reader.bits(5)? as usize + 1;
+    let code_lengths = reader.bits(4)? as usize + 4;
+
+    let mut lengths = [0u8; 19];
+    for &index in &CODE_LENGTH_ORDER[..code_lengths] {
+        lengths[index] = reader.bits(3)? as u8;
+    }
+    let code_length_code = Huffman::create(&lengths);
+
+    let mut lengths = Vec::with_capacity(literals + distances);
+    while lengths.len() < literals + distances {
+        let (value, repeat) = match code_length_code.decode(reader)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or(PngError::Corrupt("repeat without length"))?;
+                (prev, 3 + reader.bits(2)?)
+            }
+            17 => (0, 3 + reader.bits(3)?),
+            _ => (0, 11 + reader.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literals + distances {
+        return Err(PngError::Corrupt("code lengths overflow"));
+    }
+
+    Ok((
+        Huffman::create(&lengths[..literals]),
+        Huffman::create(&lengths[literals..]),
+    ))
+}
+
+/// Decompresses a zlib stream
+fn inflate(zlib: &[u8]) -> Result<Vec<u8>, PngError> {
+    if zlib.len() < 2 || zlib[0] & 0x0F != 8 || zlib[1] & 0x20 != 0 {
+        return Err(PngError::Corrupt("bad zlib header"));
+    }
+
+    let mut reader = BitReader {
+        data: &zlib[2..],
+        pos: 0,
+        bit: 0,
+    };
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+
+        match reader.bits(2)? {
+            0 => {
+                reader.align();
+                let header = reader
+                    .data
+                    .get(reader.pos..reader.pos + 4)
+                    .ok_or(PngError::Corrupt("truncated stored block"))?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                let start = reader.pos + 4;
+                let block = reader
+                    .data
+                    .get(start..start + len)
+                    .ok_or(PngError::Corrupt("truncated stored block"))?;
+                out.extend_from_slice(block);
+                reader.pos = start + len;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(
+                    &mut reader,
+                    &mut out,
+                    &Huffman::create(&lengths),
+                    &Huffman::create(&[5; 30]),
+                )?;
+            }
+            2 => {
+                let (lengths, distances) = dynamic_tables(&mut reader)?;
+                inflate_block(&mut reader, &mut out, &lengths, &distances)?;
+            }
+            _ => return Err(PngError::Corrupt("bad block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the per-scanline filters in place and returns the raw rows
+fn unfilter(data: &mut [u8], stride: usize, bpp: usize) -> Result<Vec<&[u8]>, PngError> {
+    let mut prev: Vec<u8> = vec![0; stride];
+    let mut rows = Vec::new();
+
+    for line in data.chunks_mut(stride + 1) {
+        let (filter, row) = line
+            .split_first_mut()
+            .ok_or(PngError::Corrupt("empty row"))?;
+        if row.len() != stride {
+            return Err(PngError::Corrupt("truncated row"));
+        }
+
+        for i in 0..stride {
+            let left = if i >= bpp { row[i - bpp] } else { 0 };
+            let up = prev[i];
+            let up_left = if i >= bpp { prev[i - bpp] } else { 0 };
+
+            row[i] = row[i].wrapping_add(match filter {
+                0 => 0,
+                1 => left,
+                2 => up,
+                3 => ((left as u16 + up as u16) / 2) as u8,
+                4 => paeth(left, up, up_left),
+                _ => return Err(PngError::Corrupt("bad filter type")),
+            });
+        }
+
+        prev.copy_from_slice(row);
+        rows.push(&*row);
+    }
+
+    Ok(rows)
+}
+
+/// Decodes a non-interlaced PNG into a frame, quantizing colors to 15 bits.
+/// Alpha is ignored.
+pub fn decode_png(bytes: &[u8]) -> Result<Framebuffer, PngError> {
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(PngError::Corrupt("missing signature"));
+    }
+
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut zlib = Vec::new();
+    let mut rest = &bytes[SIGNATURE.len()..];
+
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        let chunk = rest
+            .get(4..len + 8)
+            .ok_or(PngError::Corrupt("truncated chunk"))?;
+        let crc = rest
+            .get(len + 8..len + 12)
+            .ok_or(PngError::Corrupt("truncated chunk"))?;
+        if crc32(chunk).to_be_bytes() != crc {
+            return Err(PngError::Corrupt("chunk checksum mismatch"));
+        }
+
+        let (kind, data) = chunk.split_at(4);
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"IDAT" => zlib.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        rest = &rest[len + 12..];
+    }
+
+    let header = header.ok_or(PngError::Corrupt("missing header"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color, interlace) = (header[8], header[9], header[12]);
+
+    if interlace != 0 {
+        return Err(PngError::Unsupported("interlacing"));
+    }
+    let channels = match (color, depth) {
+        (COLOR_GRAY, 1 | 2 | 4 | 8 | 16) | (COLOR_PALETTE, 1 | 2 | 4 | 8) => 1,
+        (COLOR_GRAY_ALPHA, 8 | 16) => 2,
+        (COLOR_RGB, 8 | 16) => 3,
+        (COLOR_RGBA, 8 | 16) => 4,
+        _ => return Err(PngError::Unsupported("color type and bit depth")),
+    };
+
+    let bits_per_pixel = channels * depth as usize;
+    let stride = (width * bits_per_pixel).div_ceil(8);
+    let mut data = inflate(&zlib)?;
+    let rows = unfilter(&mut data, stride, bits_per_pixel.div_ceil(8))?;
+    if rows.len() < height {
+        return Err(PngError::Corrupt("missing rows"));
+    }
+
+    // Sample `n` of a row, reduced to 8 bits
+    let sample = |row: &[u8], n: usize| -> u8 {
+        match depth {
+            16 => row[n * 2],
+            8 => row[n],
+            _ => {
+                let bit = n * depth as usize;
+                let mask = (1u8 << depth) - 1;
+                (row[bit / 8] >> (8 - depth as usize - bit % 8)) & mask
+            }
+        }
+    };
+    let scale = |value: u8| -> u8 {
+        match depth {
+            1 => value * 0xFF,
+            2 => value * 0x55,
+            4 => value * 0x11,
+            _ => value,
+        }
+    };
+
+    let mut frame = Framebuffer::create(width, height);
+    for (y, row) in rows.iter().take(height).enumerate() {
+        for x in 0..width {
+            let rgb = match color {
+                COLOR_PALETTE => {
+                    let index = sample(row, x) as usize * 3;
+                    let entry = palette
+                        .get(index..index + 3)
+                        .ok_or(PngError::Corrupt("palette index out of range"))?;
+                    [entry[0], entry[1], entry[2]]
+                }
+                COLOR_GRAY | COLOR_GRAY_ALPHA => {
+                    let gray = scale(sample(row, x * channels));
+                    [gray; 3]
+                }
+                _ => [
+                    sample(row, x * channels),
+                    sample(row, x * channels + 1),
+                    sample(row, x * channels + 2),
+                ],
+            };
+            frame.set(x, y, Framebuffer::from_rgb888(rgb));
+        }
+    }
+
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    /// 4x2 RGB, zlib compressed with fixed Huffman codes, rows filtered Sub
+    /// and Up
+    const RGB_FILTERED: [u8; 78] = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00, 0x00, 0xF0,
+        0xCA, 0xEA, 0x34, 0x00, 0x00, 0x00, 0x15, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0xFC,
+        0xCF, 0xC0, 0xC0, 0x08, 0xC6, 0xFF, 0xFF, 0x33, 0x30, 0x31, 0x20, 0x01, 0x00, 0x5E, 0x18,
+        0x05, 0x01, 0xC4, 0xF6, 0xE0, 0xA3, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE,
+        0x42, 0x60, 0x82,
+    ];
+
+    /// 4x1 2-bit palette image: black, red, green, blue
+    const PALETTE_2BIT: [u8; 91] = [
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x00, 0x00, 0x00, 0x84,
+        0x52, 0xE7, 0x5E, 0x00, 0x00, 0x00, 0x0C, 0x50, 0x4C, 0x54, 0x45, 0x00, 0x00, 0x00, 0xFF,
+        0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF, 0x9B, 0xC0, 0x13, 0xDC, 0x00, 0x00, 0x00,
+        0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0x90, 0x06, 0x00, 0x00, 0x1D, 0x00, 0x1C,
+        0x23, 0x7C, 0x8F, 0xAC, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60,
+        0x82,
+    ];
+
+    const RED: u16 = Framebuffer::rgb555(31, 0, 0);
+    const GREEN: u16 = Framebuffer::rgb555(0, 31, 0);
+    const BLUE: u16 = Framebuffer::rgb555(0, 0, 31);
+    const WHITE: u16 = Framebuffer::rgb555(31, 31, 31);
+
+    #[test]
+    fn round_trip() {
+        let mut frame = Framebuffer::create(160, 144);
+        for y in 0..144 {
+            for x in 0..160 {
+                frame.set(x, y, Framebuffer::rgb555(x as u8, y as u8, (x ^ y) as u8));
+            }
+        }
+
+        let decoded = decode_png(&encode_png(&frame)).expect("decode");
+        assert_eq!(decoded, frame);
+    }
+
+    #[test]
+    fn decodes_compressed_rgb_and_palette() {
+        let frame = decode_png(&RGB_FILTERED).expect("decode RGB");
+        assert_eq!((frame.width(), frame.height()), (4, 2));
+        assert_eq!(
+            frame.pixels(),
+            [RED, GREEN, BLUE, WHITE, RED, GREEN, BLUE, WHITE]
+        );
+
+        let frame = decode_png(&PALETTE_2BIT).expect("decode palette");
+        assert_eq!(frame.pixels(), [0, RED, GREEN, BLUE]);
+    }
+
+    #[test]
+    fn rejects_damaged_files() {
+        assert!(decode_png(b"GIF89a").is_err());
+
+        let mut damaged = RGB_FILTERED;
+        damaged[45] ^= 0xFF;
+        assert!(matches!(decode_png(&damaged), Err(PngError::Corrupt(_))));
+    }
+}
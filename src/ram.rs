@@ -1,9 +1,33 @@
-use crate::addressable::Addressable;
+use crate::addressable::AddressError;
+use crate::inspect::Inspect;
 
-pub trait RAM: Addressable + std::fmt::Debug {
+pub trait RAM: Inspect + std::fmt::Debug {
     fn create(start: Self::Addr) -> Self
     where
         Self: Sized;
 
     fn deep_copy(&self) -> Vec<Self::Data>;
+
+    /// Number of banks, unbanked RAM has one
+    fn banks(&self) -> usize {
+        1
+    }
+
+    /// Bank mapped at `addr`, unbanked RAM only has bank 0
+    fn bank(&self, _addr: Self::Addr) -> usize {
+        0
+    }
+
+    /// Reads from a bank regardless of the selected one. Unbanked RAM only
+    /// has bank 0.
+    fn read_bank(
+        &self,
+        bank: usize,
+        addr: Self::Addr,
+    ) -> Result<Self::Data, AddressError<Self::Addr>> {
+        match bank {
+            0 => self.read_byte(addr),
+            _ => Err(AddressError::OutOfBounds(addr)),
+        }
+    }
 }
@@ -0,0 +1,123 @@
+use crate::timed::CycleTime;
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+// Keeps time for a system and the events its components have coming up, so
+// components only need to be brought up to date when something is due
+// rather than after every instruction. Time is counted from creation at a
+// fixed frequency all callers' frequencies should divide, e.g. the CGB
+// double speed clock.
+#[derive(Debug)]
+pub struct Scheduler<E> {
+    frequency: u32,
+    now: u64,
+    /// One entry for each pending event
+    queue: BinaryHeap<Reverse<(u64, E)>>,
+    /// When each pending event is due
+    deadlines: BTreeMap<E, u64>,
+}
+
+impl<E: Copy + Ord> Scheduler<E> {
+    pub fn create(frequency: u32) -> Self {
+        Scheduler {
+            frequency,
+            now: 0,
+            queue: BinaryHeap::new(),
+            deadlines: BTreeMap::new(),
+        }
+    }
+
+    /// Time passed since creation
+    pub fn now(&self) -> CycleTime {
+        CycleTime::new(self.frequency, self.now)
+    }
+
+    /// Moves time forward, events that become due are returned by `pop`
+    pub fn advance(&mut self, time: CycleTime) {
+        self.now = (self.now() + time).cycles();
+    }
+
+    /// Schedules an event `delay` from now, replacing any earlier schedule
+    /// of the same event. Delays are rounded up to the scheduler's frequency.
+    pub fn schedule(&mut self, event: E, delay: CycleTime) {
+        let at = self.now + delay.scale_up(self.frequency).cycles();
+        match self.deadlines.insert(event, at) {
+            Some(deadline) if deadline == at => return,
+            Some(_) => self.remove(event),
+            None => {}
+        }
+        self.queue.push(Reverse((at, event)));
+    }
+
+    pub fn cancel(&mut self, event: E) {
+        if self.deadlines.remove(&event).is_some() {
+            self.remove(event);
+        }
+    }
+
+    /// Drops an event's queue entry. There are only ever a few events
+    /// pending, so rebuilding the queue is cheap.
+    fn remove(&mut self, event: E) {
+        self.queue.retain(|&Reverse((_, queued))| queued != event);
+    }
+
+    /// Takes the earliest event that is due
+    pub fn pop(&mut self) -> Option<E> {
+        let &Reverse((at, event)) = self.queue.peek()?;
+        if at > self.now {
+            return None;
+        }
+
+        self.queue.pop();
+        self.deadlines.remove(&event);
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn events_in_order() {
+        let mut scheduler = Scheduler::create(8);
+        scheduler.schedule('b', CycleTime::new(8, 6));
+        scheduler.schedule('a', CycleTime::new(8, 4));
+        scheduler.schedule('c', CycleTime::new(8, 5));
+        // Moved, and rounded up to the scheduler's frequency
+        scheduler.schedule('c', CycleTime::new(4, 5));
+        scheduler.schedule('d', CycleTime::new(8, 1));
+        scheduler.cancel('d');
+
+        scheduler.advance(CycleTime::new(4, 3));
+        assert_eq!(scheduler.now(), CycleTime::new(8, 6));
+        assert_eq!(scheduler.pop(), Some('a'));
+        assert_eq!(scheduler.pop(), Some('b'));
+        assert_eq!(scheduler.pop(), None);
+
+        scheduler.advance(CycleTime::new(8, 4));
+        assert_eq!(scheduler.pop(), Some('c'));
+        assert_eq!(scheduler.pop(), None);
+    }
+
+    #[test]
+    fn cancel_and_reschedule_leave_nothing_behind() {
+        let mut scheduler = Scheduler::create(8);
+        for delay in 1..1000 {
+            scheduler.schedule('a', CycleTime::new(8, delay));
+            scheduler.schedule('b', CycleTime::new(8, delay));
+            scheduler.cancel('b');
+        }
+        assert_eq!(scheduler.queue.len(), 1);
+
+        // Cancelled and rescheduled at the same time, it still fires once
+        scheduler.schedule('b', CycleTime::new(8, 2));
+        scheduler.cancel('b');
+        scheduler.schedule('b', CycleTime::new(8, 2));
+        scheduler.advance(CycleTime::new(8, 1000));
+        assert_eq!(scheduler.pop(), Some('b'));
+        assert_eq!(scheduler.pop(), Some('a'));
+        assert_eq!(scheduler.pop(), None);
+    }
+}
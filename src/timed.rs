@@ -1,26 +1,122 @@
+use std::cmp::Ordering;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+// A number of cycles at some frequency, either a duration or, counted from
+// power on, a point in time. Times at different frequencies compare
+// exactly, so e.g. M-cycles, double speed cycles and RTC ticks can be mixed.
+#[derive(Clone, Copy, Debug)]
 pub struct CycleTime {
     /// Number of cycles passed
-    num: u32,
+    num: u64,
     /// Frequency in HZ
     frequency: u32,
 }
 
 impl CycleTime {
-    pub fn new(frequency: u32, cycles: u32) -> Self {
+    pub fn new(frequency: u32, cycles: u64) -> Self {
         CycleTime {
             num: cycles,
             frequency,
         }
     }
 
+    /// Number of cycles passed
+    pub fn cycles(&self) -> u64 {
+        self.num
+    }
+
+    /// Frequency in Hz the cycles are counted at
+    pub fn frequency(&self) -> u32 {
+        self.frequency
+    }
+
+    /// Length in microseconds, rounded to the nearest
     pub fn micros(&self) -> u64 {
-        let us = (1000000.0 * self.num as f64) / self.frequency as f64;
-        us.round() as u64
+        self.per_second(1_000_000)
+    }
+
+    /// Length in nanoseconds, rounded to the nearest
+    pub fn nanos(&self) -> u64 {
+        self.per_second(1_000_000_000)
+    }
+
+    fn per_second(&self, units: u128) -> u64 {
+        let frequency = self.frequency as u128;
+        ((self.num as u128 * units + frequency / 2) / frequency) as u64
+    }
+
+    /// Cycles times the other's frequency, comparable across frequencies
+    fn cross(&self, other: &CycleTime) -> u128 {
+        self.num as u128 * other.frequency as u128
+    }
+
+    /// The same time counted at another frequency, rounded down
+    pub fn scale(&self, frequency: u32) -> CycleTime {
+        let cycles = self.num as u128 * frequency as u128 / self.frequency as u128;
+        CycleTime::new(frequency, cycles as u64)
     }
 
-    // TODO: Add scaling function that can transform cycles to in this
-    // frequency to cycles of a lower or higher one
-    // pub fn scale(frequency: f64) -> usize {}
+    /// The same time counted at another frequency, rounded up so that at
+    /// least this much time passes
+    pub fn scale_up(&self, frequency: u32) -> CycleTime {
+        let cycles = (self.num as u128 * frequency as u128).div_ceil(self.frequency as u128);
+        CycleTime::new(frequency, cycles as u64)
+    }
+}
+
+impl PartialEq for CycleTime {
+    fn eq(&self, other: &Self) -> bool {
+        self.cross(other) == other.cross(self)
+    }
+}
+
+impl Eq for CycleTime {}
+
+impl PartialOrd for CycleTime {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for CycleTime {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.cross(other).cmp(&other.cross(self))
+    }
+}
+
+/// Sums are counted at the left-hand side's frequency, the right-hand side
+/// is rounded down to it
+impl Add for CycleTime {
+    type Output = CycleTime;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        CycleTime::new(self.frequency, self.num + rhs.scale(self.frequency).num)
+    }
+}
+
+impl AddAssign for CycleTime {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+/// Differences are counted at the left-hand side's frequency, the
+/// right-hand side is rounded down to it. Subtracting a longer time is a
+/// bug, caught in debug builds and saturating at zero otherwise.
+impl Sub for CycleTime {
+    type Output = CycleTime;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        let rhs = rhs.scale(self.frequency).num;
+        debug_assert!(rhs <= self.num, "{rhs} cycles subtracted from {self:?}");
+        CycleTime::new(self.frequency, self.num.saturating_sub(rhs))
+    }
+}
+
+impl SubAssign for CycleTime {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
 }
 
 pub trait Timed {
@@ -28,6 +124,12 @@ pub trait Timed {
     fn catchup(&mut self, time: CycleTime);
 }
 
+impl<T: ?Sized + Timed> Timed for Box<T> {
+    fn catchup(&mut self, time: CycleTime) {
+        (**self).catchup(time);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -37,5 +139,55 @@ mod tests {
             frequency: 32768,
         };
         assert_eq!(ct.micros(), 92);
+        assert_eq!(ct.nanos(), 91553);
+
+        // A frame is 16742706.3 ns, rounded to the nearest
+        let frame = super::CycleTime::new(4194304, 70224);
+        assert_eq!(frame.micros(), 16743);
+        assert_eq!(frame.nanos(), 16742706);
+    }
+
+    #[test]
+    fn arithmetic() {
+        use super::CycleTime;
+
+        let mcycles = CycleTime::new(1048576, 3);
+        let double_speed = CycleTime::new(8388608, 24);
+        let rtc = CycleTime::new(32768, 1);
+        assert_eq!(mcycles, double_speed);
+        assert!(rtc > double_speed);
+        assert_eq!(
+            rtc.cmp(&CycleTime::new(1048576, 32)),
+            std::cmp::Ordering::Equal
+        );
+
+        let mut time = double_speed + mcycles;
+        assert_eq!(time.cycles(), 48);
+        assert_eq!(time.frequency(), 8388608);
+        time -= CycleTime::new(4194304, 4);
+        assert_eq!(time.cycles(), 40);
+        // Less than an RTC tick is rounded away
+        assert_eq!((rtc - mcycles).cycles(), 1);
+        assert_eq!((mcycles + rtc).cycles(), 35);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "subtracted")]
+    fn sub_underflow() {
+        use super::CycleTime;
+
+        let _ = CycleTime::new(1048576, 3) - CycleTime::new(1048576, 4);
+    }
+
+    #[test]
+    fn scale() {
+        let ct = super::CycleTime::new(4194304, 7);
+        assert_eq!(ct.scale(8388608).cycles(), 14);
+        assert_eq!(ct.scale(1048576).cycles(), 1);
+        assert_eq!(ct.scale_up(1048576).cycles(), 2);
+        // A day of SGB cycles, without overflowing
+        let day = super::CycleTime::new(4295454, 4295454 * 86400);
+        assert_eq!(day.scale(32768).cycles(), 32768 * 86400);
     }
 }
@@ -0,0 +1,150 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Set alongside the middle index while it holds a value not read yet
+const FRESH: usize = 1 << 2;
+
+// Writer and reader each own one buffer and swap it with the middle one, so
+// neither ever waits on the other and no value is allocated after creation.
+// The locks are never contended, they only make the ownership checkable.
+#[derive(Debug)]
+struct Shared<T> {
+    buffers: [Mutex<T>; 3],
+    /// Index of the buffer between writer and reader, with FRESH
+    middle: AtomicUsize,
+    closed: AtomicBool,
+}
+
+/// Publishing end, e.g. the emulation thread handing off frames
+#[derive(Debug)]
+pub struct TripleWriter<T> {
+    shared: Arc<Shared<T>>,
+    index: usize,
+}
+
+/// Consuming end, always sees the newest value and skips any in between
+#[derive(Debug)]
+pub struct TripleReader<T> {
+    shared: Arc<Shared<T>>,
+    index: usize,
+}
+
+/// Creates a triple buffer with all three buffers set to `initial`
+pub fn triple_buffer<T: Clone>(initial: T) -> (TripleWriter<T>, TripleReader<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [
+            Mutex::new(initial.clone()),
+            Mutex::new(initial.clone()),
+            Mutex::new(initial),
+        ],
+        middle: AtomicUsize::new(1),
+        closed: AtomicBool::new(false),
+    });
+
+    (
+        TripleWriter {
+            shared: shared.clone(),
+            index: 0,
+        },
+        TripleReader { shared, index: 2 },
+    )
+}
+
+fn lock<T>(buffer: &Mutex<T>) -> MutexGuard<'_, T> {
+    buffer.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl<T> TripleWriter<T> {
+    /// Fills the back buffer and publishes it as the newest value. The back
+    /// buffer holds whatever was written two or three values ago.
+    pub fn write(&mut self, fill: impl FnOnce(&mut T)) {
+        fill(&mut lock(&self.shared.buffers[self.index]));
+        self.index = self
+            .shared
+            .middle
+            .swap(self.index | FRESH, Ordering::AcqRel)
+            & !FRESH;
+    }
+}
+
+impl<T> Drop for TripleWriter<T> {
+    fn drop(&mut self) {
+        self.shared.closed.store(true, Ordering::Release);
+    }
+}
+
+impl<T> TripleReader<T> {
+    /// Runs `read` on the newest value if one was published since the last
+    /// read
+    pub fn read<R>(&mut self, read: impl FnOnce(&T) -> R) -> Option<R> {
+        // Only the writer touches the middle in between, and it can only
+        // leave a fresh value there
+        if self.shared.middle.load(Ordering::Acquire) & FRESH == 0 {
+            return None;
+        }
+
+        self.index = self.shared.middle.swap(self.index, Ordering::AcqRel) & !FRESH;
+        Some(read(&lock(&self.shared.buffers[self.index])))
+    }
+
+    /// Whether the writer is gone. The last value may still be unread.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::Acquire)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::thread;
+
+    #[test]
+    fn newest_value_wins() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert_eq!(reader.read(|value| *value), None);
+
+        writer.write(|value| *value = 1);
+        assert_eq!(reader.read(|value| *value), Some(1));
+        assert_eq!(reader.read(|value| *value), None);
+
+        // A slow reader only misses values, the writer never waits
+        for n in 2..10 {
+            writer.write(|value| *value = n);
+        }
+        assert_eq!(reader.read(|value| *value), Some(9));
+
+        assert!(!reader.is_closed());
+        writer.write(|value| *value = 10);
+        drop(writer);
+        assert!(reader.is_closed());
+        assert_eq!(reader.read(|value| *value), Some(10));
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut writer, mut reader) = triple_buffer(vec![0u32; 16]);
+
+        let producer = thread::spawn(move || {
+            for n in 1..=1000 {
+                writer.write(|frame| frame.fill(n));
+            }
+        });
+
+        let mut last = 0;
+        loop {
+            let closed = reader.is_closed();
+            if let Some(frame) = reader.read(|frame| frame.clone()) {
+                // Every value is whole and they only move forward
+                assert!(frame.iter().all(|&n| n == frame[0]));
+                assert!(frame[0] > last);
+                last = frame[0];
+            }
+            if closed {
+                break;
+            }
+        }
+
+        producer.join().expect("producer");
+        assert_eq!(last, 1000);
+    }
+}
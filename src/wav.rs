@@ -0,0 +1,92 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+
+/// Size of the RIFF header up to the sample data
+const HEADER_SIZE: u32 = 44;
+
+// 16-bit PCM WAV writer. The chunk sizes are only known at the end, so
+// they are patched in by `finish`.
+#[derive(Debug)]
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    channels: u16,
+    /// Bytes of sample data written
+    data_size: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn create(mut out: W, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let block_align = channels * 2;
+
+        out.write_all(b"RIFF")?;
+        out.write_all(&(HEADER_SIZE - 8).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        // Integer PCM
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&channels.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * block_align as u32).to_le_bytes())?;
+        out.write_all(&block_align.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter {
+            out,
+            channels,
+            data_size: 0,
+        })
+    }
+
+    /// Appends interleaved samples, one per channel for each frame
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        if !samples.len().is_multiple_of(self.channels as usize) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "samples don't fill whole frames",
+            ));
+        }
+
+        for sample in samples {
+            self.out.write_all(&sample.to_le_bytes())?;
+        }
+        self.data_size += samples.len() as u32 * 2;
+
+        Ok(())
+    }
+
+    /// Fills in the chunk sizes and hands back the output
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + self.data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.out.write_all(&self.data_size.to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn patches_sizes() {
+        let mut wav = WavWriter::create(Cursor::new(Vec::new()), 48000, 2).expect("header");
+        wav.write_samples(&[1, -1, 2, -2]).expect("samples");
+        assert!(wav.write_samples(&[0]).is_err());
+        let bytes = wav.finish().expect("finish").into_inner();
+
+        assert_eq!(bytes.len(), 44 + 8);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(&bytes[4..8], &44u32.to_le_bytes());
+        assert_eq!(&bytes[24..28], &48000u32.to_le_bytes());
+        assert_eq!(&bytes[28..32], &192000u32.to_le_bytes());
+        assert_eq!(&bytes[40..44], &8u32.to_le_bytes());
+        assert_eq!(&bytes[44..], &[1, 0, 0xFF, 0xFF, 2, 0, 0xFE, 0xFF]);
+    }
+}
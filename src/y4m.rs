@@ -0,0 +1,94 @@
+use crate::framebuffer::Framebuffer;
+
+use std::io;
+use std::io::Write;
+
+// Uncompressed YUV4MPEG2 stream, 4:4:4 so no chroma is lost on pixel art.
+// Players and encoders such as ffmpeg read it directly.
+#[derive(Debug)]
+pub struct Y4mWriter<W: Write> {
+    out: W,
+    width: usize,
+    height: usize,
+    /// Y, U and V planes of the frame being written
+    planes: Vec<u8>,
+}
+
+/// BT.601 studio swing conversion of a 15-bit color
+fn to_yuv(color: u16) -> [u8; 3] {
+    let [r, g, b] = Framebuffer::to_rgb888(color).map(|c| c as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    [y as u8, u as u8, v as u8]
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the stream header, the frame rate is `rate_num / rate_den`
+    /// frames per second
+    pub fn create(
+        mut out: W,
+        width: usize,
+        height: usize,
+        rate_num: u32,
+        rate_den: u32,
+    ) -> io::Result<Self> {
+        writeln!(
+            out,
+            "YUV4MPEG2 W{width} H{height} F{rate_num}:{rate_den} Ip A1:1 C444"
+        )?;
+
+        Ok(Y4mWriter {
+            out,
+            width,
+            height,
+            planes: vec![0; width * height * 3],
+        })
+    }
+
+    pub fn write_frame(&mut self, frame: &Framebuffer) -> io::Result<()> {
+        if (frame.width(), frame.height()) != (self.width, self.height) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame size differs from the stream size",
+            ));
+        }
+
+        let size = self.width * self.height;
+        for (i, &color) in frame.pixels().iter().enumerate() {
+            let [y, u, v] = to_yuv(color);
+            self.planes[i] = y;
+            self.planes[size + i] = u;
+            self.planes[2 * size + i] = v;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn stream() {
+        let mut frame = Framebuffer::create(2, 1);
+        frame.set(1, 0, Framebuffer::rgb555(31, 31, 31));
+
+        let mut y4m = Y4mWriter::create(Vec::new(), 2, 1, 4194304, 70224).expect("header");
+        y4m.write_frame(&frame).expect("frame");
+        assert!(y4m.write_frame(&Framebuffer::create(1, 1)).is_err());
+        let bytes = y4m.finish().expect("finish");
+
+        let header = b"YUV4MPEG2 W2 H1 F4194304:70224 Ip A1:1 C444\n";
+        assert_eq!(&bytes[..header.len()], header);
+        // Black and white in studio swing, neutral chroma
+        assert_eq!(&bytes[header.len()..], b"FRAME\n\x10\xEB\x80\x80\x80\x80");
+    }
+}
@@ -0,0 +1,57 @@
+//! Runs the Blargg and Mooneye test ROMs found below `$GAMERBOY_TEST_ROMS`.
+//!
+//! ROMs listed in `passing.txt` inside that directory must keep passing,
+//! everything else is reported but does not fail the test. Every ROM runs
+//! on the plain interpreter and with the block cache. The test is skipped
+//! when the variable is unset.
+
+use gamerboy::{gameboy, Model};
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+/// Emulated seconds a single ROM may run before timing out
+const TIMEOUT_SECS: u64 = 120;
+
+#[test]
+fn test_roms() {
+    let Ok(dir) = env::var("GAMERBOY_TEST_ROMS") else {
+        eprintln!("GAMERBOY_TEST_ROMS is not set, skipping test ROMs");
+        return;
+    };
+    let dir = PathBuf::from(dir);
+
+    let expected = gameboy::expected_passing(&dir).expect("readable passing.txt");
+    let roms = gameboy::find_test_roms(&dir).expect("readable test ROM directory");
+
+    let mut regressions = Vec::new();
+    let mut newly_passing = Vec::new();
+
+    for (rom, cached) in roms.iter().flat_map(|rom| [(rom, false), (rom, true)]) {
+        let name = rom.strip_prefix(&dir).expect("ROM below test directory");
+        let report = gameboy::run_test_rom(
+            fs::read(rom).expect("readable ROM"),
+            TIMEOUT_SECS * Model::CYCLE_CLOCK as u64,
+            cached,
+        );
+        let mode = if cached { "cached" } else { "plain" };
+        println!("{:<8} {mode:<6} {}", report.verdict, name.display());
+
+        let passed = report.verdict == gameboy::Verdict::Passed;
+        match (passed, expected.contains(name)) {
+            (false, true) => regressions.push(format!("{} ({mode})", name.display())),
+            (true, false) => newly_passing.push(format!("{} ({mode})", name.display())),
+            _ => {}
+        }
+    }
+
+    for name in &newly_passing {
+        println!("newly passing, consider adding to passing.txt: {name}");
+    }
+
+    assert!(
+        regressions.is_empty(),
+        "test ROMs regressed: {regressions:?}"
+    );
+}